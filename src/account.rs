@@ -0,0 +1,244 @@
+use std::sync::OnceLock;
+
+use actix_web::{error, get, post, web, Error, HttpResponse};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use chrono::prelude::*;
+use uuid::Uuid;
+
+use crate::{
+    auth::{Authenticator, Principal},
+    model::{AppState, LoginUserSchema, RegisterUserSchema, User},
+    response::{error_response, GenericResponse, SingleUserResponse, TokenResponse, UserData},
+};
+
+fn validate_registration(payload: &RegisterUserSchema) -> Result<(), &'static str> {
+    let username = &payload.username;
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || "_.-".contains(c);
+    if !(3..=32).contains(&username.len()) || !username.chars().all(valid_char) {
+        return Err("Username must be 3-32 letters, digits, '_', '.' or '-'.");
+    }
+    if !(8..=128).contains(&payload.password.chars().count()) {
+        return Err("Password must be 8-128 characters.");
+    }
+    Ok(())
+}
+
+fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .and_then(|hash| Argon2::default().verify_password(password.as_bytes(), &hash))
+        .is_ok()
+}
+
+// Checked against when the username is unknown, so a failed login takes as
+// long whether or not the account exists.
+fn dummy_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| hash_password("not the password").expect("hashing a constant"))
+}
+
+// Register a new account
+#[post("/auth/register")]
+async fn register(
+    app_state: web::Data<AppState>,
+    payload: web::Json<RegisterUserSchema>,
+) -> Result<HttpResponse, Error> {
+    let payload = payload.into_inner();
+    if let Err(message) = validate_registration(&payload) {
+        return Ok(error_response(HttpResponse::BadRequest(), message));
+    }
+
+    let password = payload.password;
+    let password_hash = web::block(move || hash_password(&password))
+        .await?
+        .map_err(error::ErrorInternalServerError)?;
+    let user = app_state.user_db.create_user(User {
+        id: Uuid::new_v4().to_string(),
+        username: payload.username,
+        passwordHash: password_hash,
+        createdAt: Utc::now(),
+    })?;
+
+    let response_json = &SingleUserResponse {
+        status: "success".to_string(),
+        data: UserData { user: user.into() },
+    };
+    Ok(HttpResponse::Created().json(response_json))
+}
+
+// Exchange a username and password for a bearer token
+#[post("/auth/login")]
+async fn login(
+    app_state: web::Data<AppState>,
+    authenticator: web::Data<Authenticator>,
+    payload: web::Json<LoginUserSchema>,
+) -> Result<HttpResponse, Error> {
+    let payload = payload.into_inner();
+    let user = app_state.user_db.find_user_by_username(&payload.username)?;
+    let hash = match &user {
+        Some(user) => user.passwordHash.clone(),
+        None => dummy_hash().to_string(),
+    };
+    let password = payload.password;
+    let verified = web::block(move || verify_password(&password, &hash)).await?;
+
+    match user {
+        Some(user) if verified => {
+            let issued = authenticator.issue(&user.id)?;
+            let response_json = &TokenResponse {
+                status: "success".to_string(),
+                token: issued.token,
+                token_type: "Bearer".to_string(),
+                expires_in: issued.expires_in,
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        _ => Ok(error_response(
+            HttpResponse::Unauthorized(),
+            "Invalid username or password.",
+        )),
+    }
+}
+
+// Revoke the token the request was made with
+#[post("/auth/logout")]
+async fn logout(
+    app_state: web::Data<AppState>,
+    principal: Principal,
+) -> Result<HttpResponse, Error> {
+    let Some(token_id) = &principal.token_id else {
+        return Ok(error_response(
+            HttpResponse::BadRequest(),
+            "Token has no id and cannot be revoked.",
+        ));
+    };
+    app_state
+        .user_db
+        .revoke_token(token_id, principal.expires_at)?;
+
+    let response_json = &GenericResponse {
+        status: "success".to_string(),
+        message: "Logged out successfully.".to_string(),
+    };
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+// Get the account the token belongs to
+#[get("/me")]
+async fn me(app_state: web::Data<AppState>, principal: Principal) -> Result<HttpResponse, Error> {
+    match app_state.user_db.get_user(&principal.subject)? {
+        Some(user) => {
+            let response_json = &SingleUserResponse {
+                status: "success".to_string(),
+                data: UserData { user: user.into() },
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        None => Ok(error_response(HttpResponse::NotFound(), "User not found.")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::test_support::authenticator;
+    use crate::handler::config;
+    use crate::model::AppState;
+    use crate::response::{GenericResponse, SingleUserResponse, TokenResponse};
+    use actix_web::{http, test, web, App};
+    use serde_json::json;
+
+    #[actix_web::test]
+    async fn register_login_logout_test() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::init()))
+                .app_data(authenticator())
+                .configure(config),
+        )
+        .await;
+        let credentials = json!({ "username": "alice", "password": "correct horse" });
+
+        let req = test::TestRequest::post()
+            .uri("/api/v1/auth/register")
+            .set_json(&credentials)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::CREATED);
+        let registered: SingleUserResponse = test::read_body_json(resp).await;
+        assert_eq!(registered.data.user.username, "alice");
+
+        let req = test::TestRequest::post()
+            .uri("/api/v1/auth/register")
+            .set_json(&credentials)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::CONFLICT);
+
+        let req = test::TestRequest::post()
+            .uri("/api/v1/auth/login")
+            .set_json(json!({ "username": "alice", "password": "wrong password" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+
+        let req = test::TestRequest::post()
+            .uri("/api/v1/auth/login")
+            .set_json(&credentials)
+            .to_request();
+        let login: TokenResponse = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(login.token_type, "Bearer");
+        let bearer = ("Authorization", format!("Bearer {}", login.token));
+
+        let req = test::TestRequest::get()
+            .uri("/api/v1/me")
+            .insert_header(bearer.clone())
+            .to_request();
+        let me: SingleUserResponse = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(me.data.user.id, registered.data.user.id);
+
+        let req = test::TestRequest::post()
+            .uri("/api/v1/auth/logout")
+            .insert_header(bearer.clone())
+            .to_request();
+        let logout: GenericResponse = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(logout.status, "success");
+
+        let req = test::TestRequest::get()
+            .uri("/api/v1/me")
+            .insert_header(bearer)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn register_validation_test() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::init()))
+                .app_data(authenticator())
+                .configure(config),
+        )
+        .await;
+        for body in [
+            json!({ "username": "al", "password": "long enough" }),
+            json!({ "username": "al ice", "password": "long enough" }),
+            json!({ "username": "alice", "password": "short" }),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/api/v1/auth/register")
+                .set_json(&body)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST, "{}", body);
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use actix_web::{delete, get, post, web, Error, HttpResponse};
+use chrono::prelude::*;
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use uuid::Uuid;
+
+use crate::{
+    auth::Principal,
+    model::{ApiKey, AppState, CreateApiKeySchema},
+    response::{
+        error_response, ApiKeyData, ApiKeyListResponse, CreatedApiKeyResponse, GenericResponse,
+    },
+};
+
+const KEY_PREFIX: &str = "tdk_";
+
+// Characters of the key kept in the clear so users can tell their keys apart
+const VISIBLE_LENGTH: usize = KEY_PREFIX.len() + 8;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Keys are random, so a fast unsalted hash is enough to keep them unusable if
+// the store leaks, and it lets keys be looked up by their hash.
+pub fn hash_key(key: &str) -> String {
+    hex(digest(&SHA256, key.as_bytes()).as_ref())
+}
+
+fn generate_key() -> Result<String, Error> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| actix_web::error::ErrorInternalServerError("no randomness available"))?;
+    Ok(format!("{}{}", KEY_PREFIX, hex(&bytes)))
+}
+
+// API keys can do no more than they were created for, so managing keys takes
+// a user's bearer token.
+fn forbid_api_keys(principal: &Principal) -> Option<HttpResponse> {
+    principal.is_api_key().then(|| {
+        error_response(
+            HttpResponse::Forbidden(),
+            "API keys cannot be managed with an API key.",
+        )
+    })
+}
+
+fn validate(payload: &CreateApiKeySchema) -> Result<(), &'static str> {
+    if payload.name.trim().is_empty() || payload.name.chars().count() > 100 {
+        return Err("Name must be 1-100 characters.");
+    }
+    if payload.scopes.is_empty() {
+        return Err("At least one scope is required.");
+    }
+    if payload
+        .expiresAt
+        .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        return Err("expiresAt must be in the future.");
+    }
+    Ok(())
+}
+
+// Create an API key. The key itself is only ever returned here.
+#[post("/api-keys")]
+async fn create_api_key(
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    payload: web::Json<CreateApiKeySchema>,
+) -> Result<HttpResponse, Error> {
+    if let Some(response) = forbid_api_keys(&principal) {
+        return Ok(response);
+    }
+    let payload = payload.into_inner();
+    if let Err(message) = validate(&payload) {
+        return Ok(error_response(HttpResponse::BadRequest(), message));
+    }
+
+    let key = generate_key()?;
+    let mut scopes = payload.scopes;
+    scopes.sort_unstable();
+    scopes.dedup();
+    let api_key = app_state.api_key_db.create_api_key(ApiKey {
+        id: Uuid::new_v4().to_string(),
+        ownerId: principal.subject,
+        name: payload.name,
+        prefix: key[..VISIBLE_LENGTH].to_string(),
+        keyHash: hash_key(&key),
+        scopes,
+        createdAt: Utc::now(),
+        expiresAt: payload.expiresAt,
+    })?;
+
+    let response_json = &CreatedApiKeyResponse {
+        status: "success".to_string(),
+        key,
+        data: ApiKeyData {
+            api_key: api_key.into(),
+        },
+    };
+    Ok(HttpResponse::Created().json(response_json))
+}
+
+// List the caller's API keys
+#[get("/api-keys")]
+async fn get_api_keys(
+    app_state: web::Data<AppState>,
+    principal: Principal,
+) -> Result<HttpResponse, Error> {
+    if let Some(response) = forbid_api_keys(&principal) {
+        return Ok(response);
+    }
+    let keys = app_state.api_key_db.list_api_keys(&principal.subject)?;
+
+    let response_json = &ApiKeyListResponse {
+        status: "success".to_string(),
+        results: keys.len(),
+        api_keys: keys.into_iter().map(Into::into).collect(),
+    };
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+// Revoke an API key
+#[delete("/api-keys/{id}")]
+async fn delete_api_key(
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    if let Some(response) = forbid_api_keys(&principal) {
+        return Ok(response);
+    }
+    let id = path.into_inner();
+
+    if app_state
+        .api_key_db
+        .delete_api_key(&principal.subject, &id)?
+    {
+        let response_json = &GenericResponse {
+            status: "success".to_string(),
+            message: "API key revoked successfully.".to_string(),
+        };
+        Ok(HttpResponse::Ok().json(response_json))
+    } else {
+        Ok(error_response(
+            HttpResponse::NotFound(),
+            "API key not found.",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::test_support::{authenticator, bearer};
+    use crate::handler::config;
+    use crate::model::Scope;
+    use crate::response::TodoListResponse;
+    use crate::test_support::{api, app_states};
+    use actix_web::{http, test, App};
+    use serde_json::json;
+
+    #[actix_web::test]
+    async fn api_key_lifecycle_test() {
+        for app_data in app_states() {
+            let app = api(&app_data).await;
+            let req = test::TestRequest::post()
+                .uri("/api/v1/api-keys")
+                .insert_header(bearer("alice"))
+                .set_json(json!({ "name": "ci", "scopes": ["todos:read"] }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::CREATED);
+            let created: CreatedApiKeyResponse = test::read_body_json(resp).await;
+            assert!(created.key.starts_with(&created.data.api_key.prefix));
+            let req = test::TestRequest::post()
+                .uri("/api/v1/api-keys")
+                .insert_header(bearer("bob"))
+                .set_json(json!({
+                    "name": "sync",
+                    "scopes": ["todos:write", "todos:read", "todos:write"]
+                }))
+                .to_request();
+            let sync: CreatedApiKeyResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(
+                sync.data.api_key.scopes,
+                [Scope::TodosRead, Scope::TodosWrite]
+            );
+
+            // Both header forms are accepted, within the key's scopes
+            let req = test::TestRequest::get()
+                .uri("/api/v1/todos")
+                .insert_header(("X-Api-Key", created.key.as_str()))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            let authorization = format!("ApiKey {}", created.key);
+            let req = test::TestRequest::get()
+                .uri("/api/v1/todos")
+                .insert_header(("Authorization", authorization.as_str()))
+                .to_request();
+            let todos: TodoListResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(todos.results, 0);
+            let req = test::TestRequest::post()
+                .uri("/api/v1/todos")
+                .insert_header(("X-Api-Key", created.key.as_str()))
+                .set_json(json!({ "title": "Test Todo", "content": "Test Todo Content" }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::FORBIDDEN);
+
+            // Keys cannot manage keys
+            let req = test::TestRequest::get()
+                .uri("/api/v1/api-keys")
+                .insert_header(("X-Api-Key", created.key.as_str()))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::FORBIDDEN);
+
+            let req = test::TestRequest::get()
+                .uri("/api/v1/api-keys")
+                .insert_header(bearer("alice"))
+                .to_request();
+            let body = test::call_and_read_body(&app, req).await;
+            let keys: ApiKeyListResponse = serde_json::from_slice(&body).unwrap();
+            assert_eq!(keys.results, 1);
+            assert!(!String::from_utf8_lossy(&body).contains(&created.key));
+
+            let uri = format!("/api/v1/api-keys/{}", created.data.api_key.id);
+            let req = test::TestRequest::delete()
+                .uri(&uri)
+                .insert_header(bearer("bob"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+            let req = test::TestRequest::delete()
+                .uri(&uri)
+                .insert_header(bearer("alice"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+
+            let req = test::TestRequest::get()
+                .uri("/api/v1/todos")
+                .insert_header(("X-Api-Key", created.key.as_str()))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[actix_web::test]
+    async fn expired_api_key_test() {
+        let app_data = web::Data::new(AppState::init());
+        let key = "tdk_expired";
+        app_data
+            .api_key_db
+            .create_api_key(ApiKey {
+                id: Uuid::new_v4().to_string(),
+                ownerId: "alice".to_string(),
+                name: "old".to_string(),
+                prefix: key.to_string(),
+                keyHash: hash_key(key),
+                scopes: vec![Scope::TodosRead],
+                createdAt: Utc::now(),
+                expiresAt: Some(Utc::now() - chrono::Duration::seconds(1)),
+            })
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(app_data)
+                .app_data(authenticator())
+                .configure(config),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/api/v1/todos")
+            .insert_header(("X-Api-Key", key))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,620 @@
+use std::fmt;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api_key::hash_key;
+use crate::config::{AuthConfig, ConfigError, JwtAlgorithm};
+use crate::model::{AppState, Scope};
+use crate::repository::RepositoryError;
+use crate::response::error_response;
+
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    exp: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    iat: Option<i64>,
+    // Token id, needed to revoke the token before it expires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    jti: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    iss: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    aud: Option<String>,
+}
+
+// The caller a request was authenticated as. Handlers behind
+// `Authentication` take this as an argument to find out who is calling.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub subject: String,
+    pub token_id: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    // The scopes of the API key the request was made with; None for bearer
+    // tokens, which may do anything their user can.
+    pub scopes: Option<Vec<Scope>>,
+}
+
+impl Principal {
+    pub fn is_api_key(&self) -> bool {
+        self.scopes.is_some()
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes
+            .as_ref()
+            .is_none_or(|scopes| scopes.contains(&scope))
+    }
+}
+
+// A freshly signed token handed out by login
+pub struct IssuedToken {
+    pub token: String,
+    pub expires_in: u64,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    MissingToken,
+    ExpiredToken,
+    InvalidToken(String),
+    RevokedToken,
+    InsufficientScope(Scope),
+    NotConfigured,
+    Repository(RepositoryError),
+}
+
+impl From<RepositoryError> for AuthError {
+    fn from(err: RepositoryError) -> Self {
+        AuthError::Repository(err)
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::MissingToken => write!(f, "Missing bearer token."),
+            AuthError::ExpiredToken => write!(f, "Token has expired."),
+            AuthError::InvalidToken(reason) => write!(f, "Invalid token: {}.", reason),
+            AuthError::RevokedToken => write!(f, "Token has been revoked."),
+            AuthError::InsufficientScope(scope) => {
+                write!(f, "API key is missing the {} scope.", scope.as_str())
+            }
+            AuthError::NotConfigured => write!(f, "Authentication is not configured."),
+            AuthError::Repository(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::NotConfigured => StatusCode::INTERNAL_SERVER_ERROR,
+            AuthError::Repository(err) => err.status_code(),
+            AuthError::InsufficientScope(_) => StatusCode::FORBIDDEN,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        match self {
+            AuthError::NotConfigured | AuthError::Repository(_) => {}
+            AuthError::InsufficientScope(scope) => {
+                let challenge = format!(
+                    "Bearer error=\"insufficient_scope\", scope=\"{}\"",
+                    scope.as_str()
+                );
+                response.insert_header((header::WWW_AUTHENTICATE, challenge));
+            }
+            AuthError::MissingToken => {
+                response.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+            }
+            _ => {
+                response
+                    .insert_header((header::WWW_AUTHENTICATE, "Bearer error=\"invalid_token\""));
+            }
+        }
+        error_response(response, &self.to_string())
+    }
+}
+
+enum VerificationKeys {
+    Single(DecodingKey),
+    // Keyed by `kid`; a set with one key also accepts tokens without a kid.
+    Jwks(Vec<(Option<String>, DecodingKey)>),
+}
+
+// Verifies bearer tokens against the keys and claims from `AuthConfig`, and
+// signs the tokens issued by login.
+pub struct Authenticator {
+    keys: VerificationKeys,
+    validation: Validation,
+    // None when RS256 is configured without a private key
+    signing_key: Option<EncodingKey>,
+    algorithm: Algorithm,
+    token_ttl: u64,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+impl Authenticator {
+    pub fn from_config(config: &AuthConfig) -> Result<Authenticator, ConfigError> {
+        let file_error = |path: &std::path::Path, err: &dyn fmt::Display| {
+            ConfigError::File(path.to_path_buf(), err.to_string())
+        };
+        let (algorithm, keys, signing_key) = match config.algorithm {
+            JwtAlgorithm::HS256 => {
+                let secret = config.secret.as_deref().unwrap_or_default();
+                if secret.is_empty() {
+                    return Err(ConfigError::Invalid(
+                        "HS256 needs a non-empty auth.secret".to_string(),
+                    ));
+                }
+                let key = DecodingKey::from_secret(secret.as_bytes());
+                let signing_key = EncodingKey::from_secret(secret.as_bytes());
+                (
+                    Algorithm::HS256,
+                    VerificationKeys::Single(key),
+                    Some(signing_key),
+                )
+            }
+            JwtAlgorithm::RS256 => {
+                let keys = if let Some(path) = &config.public_key_path {
+                    let pem = std::fs::read(path).map_err(|err| file_error(path, &err))?;
+                    let key =
+                        DecodingKey::from_rsa_pem(&pem).map_err(|err| file_error(path, &err))?;
+                    VerificationKeys::Single(key)
+                } else if let Some(path) = &config.jwks_path {
+                    let text =
+                        std::fs::read_to_string(path).map_err(|err| file_error(path, &err))?;
+                    let set: JwkSet =
+                        serde_json::from_str(&text).map_err(|err| file_error(path, &err))?;
+                    let keys = set
+                        .keys
+                        .iter()
+                        .map(|jwk| {
+                            let key =
+                                DecodingKey::from_jwk(jwk).map_err(|err| file_error(path, &err))?;
+                            Ok((jwk.common.key_id.clone(), key))
+                        })
+                        .collect::<Result<Vec<_>, ConfigError>>()?;
+                    VerificationKeys::Jwks(keys)
+                } else {
+                    return Err(ConfigError::Invalid(
+                        "RS256 needs auth.public_key_path or auth.jwks_path".to_string(),
+                    ));
+                };
+                let signing_key = match &config.private_key_path {
+                    Some(path) => {
+                        let pem = std::fs::read(path).map_err(|err| file_error(path, &err))?;
+                        let key = EncodingKey::from_rsa_pem(&pem)
+                            .map_err(|err| file_error(path, &err))?;
+                        Some(key)
+                    }
+                    None => None,
+                };
+                (Algorithm::RS256, keys, signing_key)
+            }
+        };
+
+        let mut validation = Validation::new(algorithm);
+        validation.leeway = config.leeway;
+        if let Some(issuer) = &config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        if let Some(audience) = &config.audience {
+            validation.set_audience(&[audience]);
+        }
+        Ok(Authenticator {
+            keys,
+            validation,
+            signing_key,
+            algorithm,
+            token_ttl: config.token_ttl,
+            issuer: config.issuer.clone(),
+            audience: config.audience.clone(),
+        })
+    }
+
+    // Signs a token for `subject` with a fresh token id, so it can be revoked.
+    pub fn issue(&self, subject: &str) -> Result<IssuedToken, AuthError> {
+        let key = self.signing_key.as_ref().ok_or(AuthError::NotConfigured)?;
+        let now = Utc::now();
+        let claims = Claims {
+            sub: subject.to_string(),
+            exp: (now + Duration::seconds(self.token_ttl as i64)).timestamp(),
+            iat: Some(now.timestamp()),
+            jti: Some(Uuid::new_v4().to_string()),
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+        };
+        let token = jsonwebtoken::encode(&Header::new(self.algorithm), &claims, key)
+            .map_err(|err| AuthError::InvalidToken(err.to_string()))?;
+        Ok(IssuedToken {
+            token,
+            expires_in: self.token_ttl,
+        })
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Principal, AuthError> {
+        let key = match &self.keys {
+            VerificationKeys::Single(key) => key,
+            VerificationKeys::Jwks(keys) => {
+                let kid = jsonwebtoken::decode_header(token)
+                    .map_err(|err| AuthError::InvalidToken(err.to_string()))?
+                    .kid;
+                let key = match (&kid, keys.as_slice()) {
+                    (None, [(_, key)]) => Some(key),
+                    (kid, keys) => keys
+                        .iter()
+                        .find(|(key_id, _)| kid.is_some() && key_id == kid)
+                        .map(|(_, key)| key),
+                };
+                key.ok_or_else(|| AuthError::InvalidToken("unknown signing key".to_string()))?
+            }
+        };
+
+        let data =
+            jsonwebtoken::decode::<Claims>(token, key, &self.validation).map_err(
+                |err| match err.kind() {
+                    ErrorKind::ExpiredSignature => AuthError::ExpiredToken,
+                    _ => AuthError::InvalidToken(err.to_string()),
+                },
+            )?;
+        Ok(Principal {
+            subject: data.claims.sub,
+            token_id: data.claims.jti,
+            expires_at: DateTime::from_timestamp(data.claims.exp, 0).unwrap_or_default(),
+            scopes: None,
+        })
+    }
+}
+
+// The credentials of an `Authorization: <scheme> <credentials>` header
+fn authorization<'a>(req: &'a ServiceRequest, scheme: &str) -> Option<&'a str> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (name, credentials) = value.split_once(' ')?;
+    name.eq_ignore_ascii_case(scheme)
+        .then(|| credentials.trim())
+        .filter(|credentials| !credentials.is_empty())
+}
+
+// An API key from `X-Api-Key` or `Authorization: ApiKey ...`
+fn api_key(req: &ServiceRequest) -> Option<&str> {
+    match req.headers().get("x-api-key") {
+        Some(value) => value.to_str().ok().map(str::trim),
+        None => authorization(req, "apikey"),
+    }
+}
+
+fn authenticate_api_key(app_state: &AppState, key: &str) -> Result<Principal, AuthError> {
+    let key = app_state
+        .api_key_db
+        .find_api_key(&hash_key(key))?
+        .ok_or_else(|| AuthError::InvalidToken("unknown API key".to_string()))?;
+    if key.is_expired() {
+        return Err(AuthError::ExpiredToken);
+    }
+    Ok(Principal {
+        subject: key.ownerId,
+        token_id: None,
+        expires_at: key.expiresAt.unwrap_or(DateTime::<Utc>::MAX_UTC),
+        scopes: Some(key.scopes),
+    })
+}
+
+fn authenticate(req: &ServiceRequest) -> Result<Principal, AuthError> {
+    let authenticator = req
+        .app_data::<web::Data<Authenticator>>()
+        .ok_or(AuthError::NotConfigured)?;
+    let app_state = req
+        .app_data::<web::Data<AppState>>()
+        .ok_or(AuthError::NotConfigured)?;
+    if let Some(key) = api_key(req) {
+        return authenticate_api_key(app_state, key);
+    }
+    let token = authorization(req, "bearer").ok_or(AuthError::MissingToken)?;
+    let principal = authenticator.verify(token)?;
+    if let Some(token_id) = &principal.token_id {
+        if app_state.user_db.is_token_revoked(token_id)? {
+            return Err(AuthError::RevokedToken);
+        }
+    }
+    Ok(principal)
+}
+
+// Middleware that rejects requests without a valid, unrevoked bearer token or
+// API key and makes the `Principal` available to the wrapped handlers. Needs a
+// `web::Data<Authenticator>` and a `web::Data<AppState>` in the app data.
+pub struct Authentication;
+
+impl<S, B> Transform<S, ServiceRequest> for Authentication
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = AuthenticationMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthenticationMiddleware { service }))
+    }
+}
+
+pub struct AuthenticationMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthenticationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        match authenticate(&req) {
+            Ok(principal) => {
+                log::debug!("Authenticated {} for {}", principal.subject, req.path());
+                req.extensions_mut().insert(principal);
+                let response = self.service.call(req);
+                Box::pin(async move { Ok(response.await?.map_into_left_body()) })
+            }
+            Err(err) => {
+                let response = req.into_response(err.error_response());
+                Box::pin(async move { Ok(response.map_into_right_body()) })
+            }
+        }
+    }
+}
+
+// Route middleware that only lets API keys through if they carry `scope`.
+// Bearer tokens always pass. Goes on routes inside `Authentication`.
+pub struct RequireScope(pub Scope);
+
+impl<S, B> Transform<S, ServiceRequest> for RequireScope
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RequireScopeMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireScopeMiddleware {
+            service,
+            scope: self.0,
+        }))
+    }
+}
+
+pub struct RequireScopeMiddleware<S> {
+    service: S,
+    scope: Scope,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireScopeMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let allowed = match req.extensions().get::<Principal>() {
+            Some(principal) if principal.has_scope(self.scope) => Ok(()),
+            Some(_) => Err(AuthError::InsufficientScope(self.scope)),
+            None => Err(AuthError::MissingToken),
+        };
+        match allowed {
+            Ok(()) => {
+                let response = self.service.call(req);
+                Box::pin(async move { Ok(response.await?.map_into_left_body()) })
+            }
+            Err(err) => {
+                let response = req.into_response(err.error_response());
+                Box::pin(async move { Ok(response.map_into_right_body()) })
+            }
+        }
+    }
+}
+
+impl FromRequest for Principal {
+    type Error = AuthError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<Principal>()
+                .cloned()
+                .ok_or(AuthError::MissingToken),
+        )
+    }
+}
+
+#[cfg(test)]
+pub mod test_support {
+    use super::*;
+
+    pub const SECRET: &str = "test-secret";
+
+    pub fn authenticator() -> web::Data<Authenticator> {
+        let config = AuthConfig {
+            secret: Some(SECRET.to_string()),
+            ..AuthConfig::default()
+        };
+        web::Data::new(Authenticator::from_config(&config).unwrap())
+    }
+
+    pub fn token(claims: serde_json::Value) -> String {
+        let key = EncodingKey::from_secret(SECRET.as_bytes());
+        jsonwebtoken::encode(&Header::default(), &claims, &key).unwrap()
+    }
+
+    // An Authorization header carrying a valid token for `subject`
+    pub fn bearer(subject: &str) -> (header::HeaderName, String) {
+        let exp = chrono::Utc::now().timestamp() + 3600;
+        let token = token(serde_json::json!({ "sub": subject, "exp": exp }));
+        (header::AUTHORIZATION, format!("Bearer {}", token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_support::{authenticator, bearer, token};
+    use super::*;
+    use crate::response::GenericResponse;
+    use actix_web::{get, http, test, App, Responder};
+    use std::path::PathBuf;
+
+    #[get("/whoami")]
+    async fn whoami(principal: Principal) -> impl Responder {
+        principal.subject
+    }
+
+    fn testdata(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join(name)
+    }
+
+    #[actix_web::test]
+    async fn middleware_test() {
+        let app = test::init_service(
+            App::new()
+                .app_data(authenticator())
+                .app_data(web::Data::new(AppState::init()))
+                .service(web::scope("").wrap(Authentication).service(whoami)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/whoami")
+            .insert_header(bearer("alice"))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, "alice");
+
+        let req = test::TestRequest::get().uri("/whoami").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+        assert!(resp.headers().contains_key(header::WWW_AUTHENTICATE));
+        let body: GenericResponse = test::read_body_json(resp).await;
+        assert_eq!(body.status, "error");
+
+        let expired = token(serde_json::json!({ "sub": "alice", "exp": 1 }));
+        let req = test::TestRequest::get()
+            .uri("/whoami")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", expired)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+        let body: GenericResponse = test::read_body_json(resp).await;
+        assert_eq!(body.message, "Token has expired.");
+    }
+
+    #[actix_web::test]
+    async fn revoked_token_test() {
+        let authenticator = authenticator();
+        let app_state = web::Data::new(AppState::init());
+        let app = test::init_service(
+            App::new()
+                .app_data(authenticator.clone())
+                .app_data(app_state.clone())
+                .service(web::scope("").wrap(Authentication).service(whoami)),
+        )
+        .await;
+        let issued = authenticator.issue("alice").unwrap();
+        let principal = authenticator.verify(&issued.token).unwrap();
+        let authorization = (header::AUTHORIZATION, format!("Bearer {}", issued.token));
+
+        let req = test::TestRequest::get()
+            .uri("/whoami")
+            .insert_header(authorization.clone())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+
+        let token_id = principal.token_id.unwrap();
+        app_state
+            .user_db
+            .revoke_token(&token_id, principal.expires_at)
+            .unwrap();
+        let req = test::TestRequest::get()
+            .uri("/whoami")
+            .insert_header(authorization)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn rs256_keys_test() {
+        let pem = std::fs::read(testdata("jwt_rs256_private.pem")).unwrap();
+        let key = EncodingKey::from_rsa_pem(&pem).unwrap();
+        let claims =
+            serde_json::json!({ "sub": "bot", "exp": chrono::Utc::now().timestamp() + 60 });
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some("test-key".to_string());
+        let signed = jsonwebtoken::encode(&header, &claims, &key).unwrap();
+
+        let pem_config = AuthConfig {
+            algorithm: JwtAlgorithm::RS256,
+            public_key_path: Some(testdata("jwt_rs256_public.pem")),
+            ..AuthConfig::default()
+        };
+        let jwks_config = AuthConfig {
+            algorithm: JwtAlgorithm::RS256,
+            jwks_path: Some(testdata("jwt_jwks.json")),
+            ..AuthConfig::default()
+        };
+        for config in [pem_config, jwks_config] {
+            let authenticator = Authenticator::from_config(&config).unwrap();
+            assert_eq!(authenticator.verify(&signed).unwrap().subject, "bot");
+            // A token signed with the HS256 secret must not pass as RS256.
+            let forged = token(claims.clone());
+            assert!(authenticator.verify(&forged).is_err());
+        }
+    }
+
+    #[actix_web::test]
+    async fn hs256_needs_a_secret_test() {
+        for secret in [None, Some(String::new())] {
+            let config = AuthConfig {
+                secret,
+                ..AuthConfig::default()
+            };
+            let err = Authenticator::from_config(&config).err().unwrap();
+            assert!(matches!(err, ConfigError::Invalid(_)));
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use std::time::SystemTime;
+
+use actix_web::http::header::{
+    self, ContentType, EntityTag, Header, IfMatch, IfModifiedSince, IfNoneMatch, LastModified,
+};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, HttpResponseBuilder};
+use chrono::prelude::*;
+use ring::digest::{digest, SHA256};
+
+use crate::config::Config;
+use crate::model::{Subtasks, Todo};
+use crate::response::error_response;
+
+// A todo's version as a strong entity tag. The subtask rollup is part of a
+// todo's responses but changing a subtask leaves the todo's version alone, so
+// the tag covers the rollup too.
+pub fn etag(todo: &Todo, subtasks: Option<&Subtasks>) -> EntityTag {
+    match subtasks {
+        Some(subtasks) => EntityTag::new_strong(format!(
+            "{}-{}-{}",
+            todo.version, subtasks.completed, subtasks.total
+        )),
+        None => EntityTag::new_strong(todo.version.to_string()),
+    }
+}
+
+// A strong entity tag for a response body made of more than one todo
+pub fn body_etag(body: &[u8]) -> EntityTag {
+    let hash = digest(&SHA256, body);
+    let hex: String = hash.as_ref()[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    EntityTag::new_strong(hex)
+}
+
+// How a GET response can be revalidated. If-Modified-Since is only honoured
+// with `dated` set, where `last_modified` moves with every change to the
+// body; removing a todo from a list moves no todo's updatedAt, for one.
+pub struct Validators {
+    pub etag: EntityTag,
+    pub last_modified: Option<DateTime<Utc>>,
+    pub dated: bool,
+}
+
+// Answers a GET with the JSON `body` and its caching headers, or with an empty
+// 304 when the client's copy is still current
+pub fn cacheable(
+    req: &HttpRequest,
+    mut response: HttpResponseBuilder,
+    validators: Validators,
+    body: Vec<u8>,
+) -> HttpResponse {
+    let cache_control = req
+        .app_data::<web::Data<Config>>()
+        .map(|config| config.caching.clone())
+        .unwrap_or_default()
+        .cache_control;
+    response
+        .insert_header(header::ETag(validators.etag.clone()))
+        .insert_header((header::CACHE_CONTROL, cache_control));
+    if let Some(last_modified) = validators.last_modified {
+        response.insert_header(LastModified(SystemTime::from(last_modified).into()));
+    }
+    if not_modified(req, &validators) {
+        return response.status(StatusCode::NOT_MODIFIED).finish();
+    }
+    response.content_type(ContentType::json()).body(body)
+}
+
+// If-None-Match wins over If-Modified-Since when a request has both
+fn not_modified(req: &HttpRequest, validators: &Validators) -> bool {
+    if req.headers().contains_key(header::IF_NONE_MATCH) {
+        return match IfNoneMatch::parse(req) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&validators.etag)),
+            Err(_) => false,
+        };
+    }
+    let (true, Some(last_modified)) = (validators.dated, validators.last_modified) else {
+        return false;
+    };
+    // HTTP dates have whole seconds
+    IfModifiedSince::parse(req).is_ok_and(|IfModifiedSince(since)| {
+        last_modified.timestamp() <= DateTime::<Utc>::from(SystemTime::from(since)).timestamp()
+    })
+}
+
+// The response refusing a write whose If-Match header does not match
+// `current`, the ETag of the todo it changes or `None` when there is none
+// yet. Without the header the write goes ahead unless
+// `concurrency.require_if_match` is set, and even then only creating a todo
+// does, as the client cannot know its ETag.
+pub fn if_match_failed(req: &HttpRequest, current: Option<&EntityTag>) -> Option<HttpResponse> {
+    if !req.headers().contains_key(header::IF_MATCH) {
+        let required = req
+            .app_data::<web::Data<Config>>()
+            .is_some_and(|config| config.concurrency.require_if_match);
+        if required && current.is_some() {
+            return Some(error_response(
+                HttpResponse::PreconditionRequired(),
+                "This request needs an If-Match header with the todo's ETag.",
+            ));
+        }
+        return None;
+    }
+
+    let matched = match IfMatch::parse(req) {
+        Ok(IfMatch::Any) => current.is_some(),
+        Ok(IfMatch::Items(tags)) => {
+            current.is_some_and(|current| tags.iter().any(|tag| tag.strong_eq(current)))
+        }
+        Err(_) => {
+            return Some(error_response(
+                HttpResponse::BadRequest(),
+                "Malformed If-Match header.",
+            ))
+        }
+    };
+    if matched {
+        None
+    } else {
+        Some(error_response(
+            HttpResponse::PreconditionFailed(),
+            "The todo has changed since it was read.",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CachingConfig, ConcurrencyConfig};
+    use actix_web::body::MessageBody;
+    use actix_web::test::TestRequest;
+    use chrono::Duration;
+
+    fn todo(version: u64) -> Todo {
+        Todo {
+            version,
+            ..Todo::default()
+        }
+    }
+
+    fn header_value(response: &HttpResponse, name: header::HeaderName) -> Option<&str> {
+        response
+            .headers()
+            .get(name)
+            .map(|value| value.to_str().unwrap())
+    }
+
+    #[test]
+    fn etag_test() {
+        assert_eq!(etag(&todo(3), None), EntityTag::new_strong("3".to_string()));
+        let subtasks = Subtasks {
+            total: 2,
+            completed: 1,
+        };
+        assert_eq!(
+            etag(&todo(3), Some(&subtasks)),
+            EntityTag::new_strong("3-1-2".to_string())
+        );
+
+        assert_eq!(body_etag(b"[1]"), body_etag(b"[1]"));
+        assert_ne!(body_etag(b"[1]"), body_etag(b"[2]"));
+        assert_eq!(body_etag(b"[1]").tag().len(), 32);
+    }
+
+    #[test]
+    fn cacheable_test() {
+        let modified = Utc.with_ymd_and_hms(2030, 1, 1, 9, 0, 0).unwrap();
+        let respond = |req: TestRequest, dated: bool| {
+            let validators = Validators {
+                etag: EntityTag::new_strong("v1".to_string()),
+                last_modified: Some(modified + Duration::milliseconds(250)),
+                dated,
+            };
+            let req = req.to_http_request();
+            cacheable(&req, HttpResponse::Ok(), validators, b"[]".to_vec())
+        };
+        let http_date = |at: DateTime<Utc>| at.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+
+        // A first request gets the body and everything needed to revalidate it
+        let response = respond(TestRequest::default(), true);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header_value(&response, header::ETAG), Some("\"v1\""));
+        assert_eq!(
+            header_value(&response, header::CACHE_CONTROL),
+            Some("private, no-cache")
+        );
+        assert_eq!(
+            header_value(&response, header::LAST_MODIFIED),
+            Some("Tue, 01 Jan 2030 09:00:00 GMT")
+        );
+        assert_eq!(
+            response.into_body().try_into_bytes().unwrap().as_ref(),
+            b"[]"
+        );
+
+        let config = Config {
+            caching: CachingConfig {
+                cache_control: "no-store".to_string(),
+            },
+            ..Config::default()
+        };
+        let response = respond(
+            TestRequest::default().app_data(web::Data::new(config)),
+            true,
+        );
+        assert_eq!(
+            header_value(&response, header::CACHE_CONTROL),
+            Some("no-store")
+        );
+
+        for (if_none_match, if_modified_since, dated, expected) in [
+            (Some("\"v1\""), None, true, StatusCode::NOT_MODIFIED),
+            (Some("W/\"v1\""), None, true, StatusCode::NOT_MODIFIED),
+            (Some("\"v0\", \"v1\""), None, true, StatusCode::NOT_MODIFIED),
+            (Some("*"), None, true, StatusCode::NOT_MODIFIED),
+            (Some("\"v0\""), None, true, StatusCode::OK),
+            // Dates only count when the validators say they are reliable
+            (None, Some(modified), true, StatusCode::NOT_MODIFIED),
+            (None, Some(modified), false, StatusCode::OK),
+            (
+                None,
+                Some(modified - Duration::seconds(1)),
+                true,
+                StatusCode::OK,
+            ),
+            // If-None-Match wins over If-Modified-Since
+            (Some("\"v0\""), Some(modified), true, StatusCode::OK),
+        ] {
+            let mut req = TestRequest::default();
+            if let Some(tags) = if_none_match {
+                req = req.insert_header((header::IF_NONE_MATCH, tags));
+            }
+            if let Some(since) = if_modified_since {
+                req = req.insert_header((header::IF_MODIFIED_SINCE, http_date(since)));
+            }
+            let response = respond(req, dated);
+            assert_eq!(
+                response.status(),
+                expected,
+                "{:?} {:?} {}",
+                if_none_match,
+                if_modified_since,
+                dated
+            );
+            assert_eq!(header_value(&response, header::ETAG), Some("\"v1\""));
+        }
+    }
+
+    #[test]
+    fn if_match_failed_test() {
+        let current = EntityTag::new_strong("1".to_string());
+        let status = |req: TestRequest, current: Option<&EntityTag>| {
+            if_match_failed(&req.to_http_request(), current).map(|response| response.status())
+        };
+        let if_match = |tags: &str| TestRequest::default().insert_header((header::IF_MATCH, tags));
+
+        // Without the header writes go ahead unless the config asks for it
+        assert_eq!(status(TestRequest::default(), Some(&current)), None);
+        let config = Config {
+            concurrency: ConcurrencyConfig {
+                require_if_match: true,
+            },
+            ..Config::default()
+        };
+        let required = || TestRequest::default().app_data(web::Data::new(config.clone()));
+        assert_eq!(
+            status(required(), Some(&current)),
+            Some(StatusCode::PRECONDITION_REQUIRED)
+        );
+        assert_eq!(status(required(), None), None);
+
+        for (tags, current, expected) in [
+            ("\"1\"", Some(&current), None),
+            ("\"0\", \"1\"", Some(&current), None),
+            ("*", Some(&current), None),
+            // If-Match compares strongly
+            (
+                "W/\"1\"",
+                Some(&current),
+                Some(StatusCode::PRECONDITION_FAILED),
+            ),
+            (
+                "\"2\"",
+                Some(&current),
+                Some(StatusCode::PRECONDITION_FAILED),
+            ),
+            ("\"1\"", None, Some(StatusCode::PRECONDITION_FAILED)),
+            ("*", None, Some(StatusCode::PRECONDITION_FAILED)),
+            // Tags that cannot be read at all are skipped
+            ("1", Some(&current), Some(StatusCode::PRECONDITION_FAILED)),
+        ] {
+            assert_eq!(status(if_match(tags), current), expected, "{}", tags);
+        }
+        // A header that is not even text is refused outright
+        let garbled = header::HeaderValue::from_bytes(b"\"\xff\"").unwrap();
+        let req = TestRequest::default().insert_header((header::IF_MATCH, garbled));
+        assert_eq!(status(req, Some(&current)), Some(StatusCode::BAD_REQUEST));
+    }
+}
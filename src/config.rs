@@ -0,0 +1,771 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use actix_web::http::header::{HeaderName, HeaderValue};
+use clap::Parser;
+use serde::Deserialize;
+
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+// A year, for reminder offsets in minutes and the delay in seconds
+const MAX_REMINDER_OFFSET: u64 = 366 * 24 * 60;
+const MAX_REMINDER_DELAY: u64 = 366 * 24 * 60 * 60;
+
+// Settings are layered: built-in defaults, then the TOML file, then APP_*
+// environment variables (and RUST_LOG), then command line flags.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub log: LogConfig,
+    pub cors: CorsConfig,
+    pub storage: StorageConfig,
+    pub auth: AuthConfig,
+    pub pagination: PaginationConfig,
+    pub reminders: ReminderConfig,
+    pub concurrency: ConcurrencyConfig,
+    pub caching: CachingConfig,
+    pub trash: TrashConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    // Defaults to one worker per physical core when unset
+    pub workers: Option<usize>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: "127.0.0.1".to_string(),
+            port: 8000,
+            workers: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    // An env_logger filter such as "info" or "actix_web=info,server_boilerplate=debug"
+    pub filter: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            filter: "actix_web=info".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    // Exact origins, patterns with `*` wildcards such as
+    // "https://*.example.com", or "*" for any origin
+    pub allowed_origins: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    // Response headers browsers may expose to scripts
+    pub exposed_headers: Vec<String>,
+    // Seconds browsers may cache a preflight response
+    pub max_age: Option<usize>,
+    pub supports_credentials: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: vec!["http://localhost:8000".to_string()],
+            allowed_headers: vec![
+                "content-type".to_string(),
+                "authorization".to_string(),
+                "accept".to_string(),
+                "x-api-key".to_string(),
+                "if-match".to_string(),
+                "if-none-match".to_string(),
+                "if-modified-since".to_string(),
+            ],
+            exposed_headers: vec!["link".to_string(), "etag".to_string()],
+            max_age: Some(3600),
+            supports_credentials: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    Memory,
+    Wal,
+    Sqlite,
+    // Only available when built with the kv feature
+    Kv,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "memory" => Ok(StorageBackend::Memory),
+            "wal" => Ok(StorageBackend::Wal),
+            "sqlite" => Ok(StorageBackend::Sqlite),
+            "kv" => Ok(StorageBackend::Kv),
+            other => Err(format!(
+                "unknown store {:?}, expected \"memory\", \"wal\", \"sqlite\" or \"kv\"",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    pub sqlite_path: PathBuf,
+    pub kv_path: PathBuf,
+    pub wal_dir: PathBuf,
+    // The wal store writes a snapshot after this many log entries
+    pub wal_compact_every: usize,
+    // Subject given the todos the sqlite, wal and kv stores kept from before
+    // todos had owners. Until one is set those todos stay stored but no one
+    // sees them.
+    pub legacy_owner: Option<String>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backend: StorageBackend::Memory,
+            sqlite_path: PathBuf::from("todos.db"),
+            kv_path: PathBuf::from("todos.redb"),
+            wal_dir: PathBuf::from("data"),
+            wal_compact_every: 1000,
+            legacy_owner: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum JwtAlgorithm {
+    HS256,
+    RS256,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub algorithm: JwtAlgorithm,
+    // Shared HS256 secret. Prefer APP_JWT_SECRET over writing it to a file.
+    pub secret: Option<String>,
+    // RS256 verification key as a PEM file, or a local JWKS file whose keys
+    // are picked by the token's `kid`. Set exactly one of the two.
+    pub public_key_path: Option<PathBuf>,
+    pub jwks_path: Option<PathBuf>,
+    // RS256 signing key (PEM) for the tokens issued by login. Without it
+    // RS256 deployments can verify tokens but not issue them.
+    pub private_key_path: Option<PathBuf>,
+    // Lifetime in seconds of the tokens issued by login
+    pub token_ttl: u64,
+    // Required `iss` and `aud` claims when set, also stamped on issued tokens
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    // Seconds of clock skew tolerated when checking `exp` and `nbf`
+    pub leeway: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            algorithm: JwtAlgorithm::HS256,
+            secret: None,
+            public_key_path: None,
+            jwks_path: None,
+            private_key_path: None,
+            token_ttl: 3600,
+            issuer: None,
+            audience: None,
+            leeway: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PaginationConfig {
+    // Page size when a request gives no limit
+    pub default_page_size: usize,
+    // Larger limits, the default included, are clamped to this
+    pub max_page_size: usize,
+}
+
+impl Default for PaginationConfig {
+    fn default() -> Self {
+        PaginationConfig {
+            default_page_size: 10,
+            max_page_size: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifierKind {
+    Log,
+    Webhook,
+    Smtp,
+}
+
+impl FromStr for NotifierKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "log" => Ok(NotifierKind::Log),
+            "webhook" => Ok(NotifierKind::Webhook),
+            "smtp" => Ok(NotifierKind::Smtp),
+            other => Err(format!(
+                "unknown notifier {:?}, expected \"log\", \"webhook\" or \"smtp\"",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReminderConfig {
+    pub enabled: bool,
+    // Minutes before a todo is due to remind about it; 0 reminds when it is due
+    pub offsets: Vec<u64>,
+    // Seconds between scans for reminders to send
+    pub interval: u64,
+    // Seconds a reminder may be late, e.g. after downtime, before it is
+    // dropped instead of sent
+    pub max_delay: u64,
+    pub notifier: NotifierKind,
+    // Plain http:// URL the webhook notifier POSTs JSON to
+    pub webhook_url: Option<String>,
+    pub smtp: SmtpConfig,
+}
+
+impl Default for ReminderConfig {
+    fn default() -> Self {
+        ReminderConfig {
+            enabled: true,
+            offsets: vec![60, 0],
+            interval: 60,
+            max_delay: 3600,
+            notifier: NotifierKind::Log,
+            webhook_url: None,
+            smtp: SmtpConfig::default(),
+        }
+    }
+}
+
+// An SMTP relay taking unauthenticated plain-text mail, such as a local MTA
+// or a test server
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        SmtpConfig {
+            host: "localhost".to_string(),
+            port: 25,
+            from: "todos@localhost".to_string(),
+            to: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConcurrencyConfig {
+    // Refuse PATCH, PUT and DELETE on an existing todo without an If-Match
+    // header, rather than only checking the header when it is sent
+    pub require_if_match: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CachingConfig {
+    // Cache-Control sent with todo and todo list responses. The default lets
+    // clients keep them but has them revalidate with the ETag before reuse.
+    pub cache_control: String,
+}
+
+impl Default for CachingConfig {
+    fn default() -> Self {
+        CachingConfig {
+            cache_control: "private, no-cache".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrashConfig {
+    // Days a deleted todo stays in the trash before it is purged for good
+    pub retention_days: u64,
+    // Seconds between scans for todos to purge
+    pub purge_interval: u64,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        TrashConfig {
+            retention_days: 30,
+            purge_interval: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Default, Parser)]
+#[command(about = "Actix Server Boilerplate")]
+pub struct Cli {
+    /// TOML config file [default: config.toml, if present]
+    #[arg(long, short)]
+    pub config: Option<PathBuf>,
+    /// Address to bind to
+    #[arg(long)]
+    pub host: Option<String>,
+    /// Port to listen on
+    #[arg(long, short)]
+    pub port: Option<u16>,
+    /// Number of worker threads
+    #[arg(long)]
+    pub workers: Option<usize>,
+    /// env_logger filter
+    #[arg(long)]
+    pub log: Option<String>,
+    /// Allowed CORS origin, may be repeated
+    #[arg(long = "cors-origin")]
+    pub cors_origins: Vec<String>,
+    /// Todo store: memory, wal, sqlite or kv
+    #[arg(long)]
+    pub store: Option<StorageBackend>,
+    /// SQLite database file for the sqlite store
+    #[arg(long)]
+    pub sqlite_path: Option<PathBuf>,
+    /// Database file for the kv store
+    #[arg(long)]
+    pub kv_path: Option<PathBuf>,
+    /// Log and snapshot directory for the wal store
+    #[arg(long)]
+    pub wal_dir: Option<PathBuf>,
+    /// Log entries between wal store snapshots
+    #[arg(long)]
+    pub wal_compact_every: Option<usize>,
+    /// Largest page size list endpoints return
+    #[arg(long)]
+    pub max_page_size: Option<usize>,
+    /// Reminder delivery: log, webhook or smtp
+    #[arg(long)]
+    pub notifier: Option<NotifierKind>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    File(PathBuf, String),
+    Env(String, String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::File(path, message) => {
+                write!(f, "Could not load {}: {}", path.display(), message)
+            }
+            ConfigError::Env(name, message) => write!(f, "Invalid {}: {}", name, message),
+            ConfigError::Invalid(message) => write!(f, "Invalid configuration: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    // Loads the configuration for this process from its arguments and environment.
+    pub fn load() -> Result<Config, ConfigError> {
+        Config::load_from(Cli::parse(), |name| std::env::var(name).ok())
+    }
+
+    pub fn load_from(
+        cli: Cli,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Config, ConfigError> {
+        let mut config = match &cli.config {
+            Some(path) => Config::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Config::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Config::default(),
+        };
+        config.apply_env(env)?;
+        config.apply_cli(cli);
+        // Lowering the max page size brings the default down with it
+        let pagination = &mut config.pagination;
+        pagination.default_page_size = pagination.default_page_size.min(pagination.max_page_size);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| ConfigError::File(path.to_path_buf(), err.to_string()))?;
+        toml::from_str(&text).map_err(|err| ConfigError::File(path.to_path_buf(), err.to_string()))
+    }
+
+    fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        if let Some(host) = env("APP_HOST") {
+            self.server.host = host;
+        }
+        if let Some(port) = parse_env(&env, "APP_PORT")? {
+            self.server.port = port;
+        }
+        if let Some(workers) = parse_env(&env, "APP_WORKERS")? {
+            self.server.workers = Some(workers);
+        }
+        if let Some(filter) = env("RUST_LOG") {
+            self.log.filter = filter;
+        }
+        if let Some(origins) = env("APP_CORS_ORIGINS") {
+            self.cors.allowed_origins = split_list(&origins);
+        }
+        if let Some(max_age) = parse_env(&env, "APP_CORS_MAX_AGE")? {
+            self.cors.max_age = Some(max_age);
+        }
+        if let Some(backend) = parse_env(&env, "APP_STORE")? {
+            self.storage.backend = backend;
+        }
+        if let Some(path) = env("APP_SQLITE_PATH") {
+            self.storage.sqlite_path = path.into();
+        }
+        if let Some(owner) = env("APP_LEGACY_OWNER") {
+            self.storage.legacy_owner = Some(owner);
+        }
+        if let Some(path) = env("APP_KV_PATH") {
+            self.storage.kv_path = path.into();
+        }
+        if let Some(dir) = env("APP_WAL_DIR") {
+            self.storage.wal_dir = dir.into();
+        }
+        if let Some(every) = parse_env(&env, "APP_WAL_COMPACT_EVERY")? {
+            self.storage.wal_compact_every = every;
+        }
+        if let Some(secret) = env("APP_JWT_SECRET") {
+            self.auth.secret = Some(secret);
+        }
+        if let Some(size) = parse_env(&env, "APP_MAX_PAGE_SIZE")? {
+            self.pagination.max_page_size = size;
+        }
+        if let Some(enabled) = parse_env(&env, "APP_REMINDERS_ENABLED")? {
+            self.reminders.enabled = enabled;
+        }
+        if let Some(notifier) = parse_env(&env, "APP_NOTIFIER")? {
+            self.reminders.notifier = notifier;
+        }
+        if let Some(url) = env("APP_WEBHOOK_URL") {
+            self.reminders.webhook_url = Some(url);
+        }
+        if let Some(required) = parse_env(&env, "APP_REQUIRE_IF_MATCH")? {
+            self.concurrency.require_if_match = required;
+        }
+        if let Some(cache_control) = env("APP_CACHE_CONTROL") {
+            self.caching.cache_control = cache_control;
+        }
+        if let Some(days) = parse_env(&env, "APP_TRASH_RETENTION_DAYS")? {
+            self.trash.retention_days = days;
+        }
+        Ok(())
+    }
+
+    fn apply_cli(&mut self, cli: Cli) {
+        if let Some(host) = cli.host {
+            self.server.host = host;
+        }
+        if let Some(port) = cli.port {
+            self.server.port = port;
+        }
+        if cli.workers.is_some() {
+            self.server.workers = cli.workers;
+        }
+        if let Some(filter) = cli.log {
+            self.log.filter = filter;
+        }
+        if !cli.cors_origins.is_empty() {
+            self.cors.allowed_origins = cli.cors_origins;
+        }
+        if let Some(backend) = cli.store {
+            self.storage.backend = backend;
+        }
+        if let Some(path) = cli.sqlite_path {
+            self.storage.sqlite_path = path;
+        }
+        if let Some(path) = cli.kv_path {
+            self.storage.kv_path = path;
+        }
+        if let Some(dir) = cli.wal_dir {
+            self.storage.wal_dir = dir;
+        }
+        if let Some(every) = cli.wal_compact_every {
+            self.storage.wal_compact_every = every;
+        }
+        if let Some(size) = cli.max_page_size {
+            self.pagination.max_page_size = size;
+        }
+        if let Some(notifier) = cli.notifier {
+            self.reminders.notifier = notifier;
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: String| Err(ConfigError::Invalid(message));
+
+        if self.server.host.trim().is_empty() {
+            return invalid("server.host must not be empty".to_string());
+        }
+        if self.server.workers == Some(0) {
+            return invalid("server.workers must be at least 1".to_string());
+        }
+        for origin in self
+            .cors
+            .allowed_origins
+            .iter()
+            .filter(|origin| *origin != "*")
+        {
+            let rest = origin
+                .strip_prefix("http://")
+                .or_else(|| origin.strip_prefix("https://"));
+            if !rest.is_some_and(|host| !host.is_empty() && !host.contains('/')) {
+                return invalid(format!(
+                    "cors.allowed_origins entry {:?} must be \"*\" or look like scheme://host[:port]",
+                    origin
+                ));
+            }
+        }
+        if self.cors.supports_credentials
+            && self.cors.allowed_origins.iter().any(|origin| origin == "*")
+        {
+            return invalid(
+                "cors.allowed_origins cannot be \"*\" with cors.supports_credentials; \
+                 list the origins instead"
+                    .to_string(),
+            );
+        }
+        let headers = self.cors.allowed_headers.iter();
+        for header in headers.chain(&self.cors.exposed_headers) {
+            if HeaderName::from_str(header).is_err() {
+                return invalid(format!("{:?} is not a valid CORS header name", header));
+            }
+        }
+        if self.storage.backend == StorageBackend::Kv && !cfg!(feature = "kv") {
+            return invalid("storage.backend \"kv\" needs a build with the kv feature".to_string());
+        }
+        if self.storage.wal_compact_every == 0 {
+            return invalid("storage.wal_compact_every must be at least 1".to_string());
+        }
+        let auth = &self.auth;
+        match auth.algorithm {
+            JwtAlgorithm::HS256 if auth.secret.as_deref().unwrap_or_default().is_empty() => {
+                return invalid(
+                    "auth.secret (or APP_JWT_SECRET) is required for HS256".to_string(),
+                );
+            }
+            JwtAlgorithm::RS256 if auth.public_key_path.is_some() == auth.jwks_path.is_some() => {
+                return invalid(
+                    "RS256 needs exactly one of auth.public_key_path and auth.jwks_path"
+                        .to_string(),
+                );
+            }
+            _ => {}
+        }
+        if auth.token_ttl == 0 {
+            return invalid("auth.token_ttl must be at least 1 second".to_string());
+        }
+        let pagination = &self.pagination;
+        if pagination.default_page_size == 0
+            || pagination.default_page_size > pagination.max_page_size
+        {
+            return invalid(
+                "pagination.default_page_size must be between 1 and pagination.max_page_size"
+                    .to_string(),
+            );
+        }
+        if HeaderValue::from_str(&self.caching.cache_control).is_err() {
+            return invalid(format!(
+                "{:?} is not a valid caching.cache_control value",
+                self.caching.cache_control
+            ));
+        }
+        if self.trash.purge_interval == 0 {
+            return invalid("trash.purge_interval must be at least 1 second".to_string());
+        }
+        let reminders = &self.reminders;
+        if reminders.enabled {
+            if reminders.offsets.is_empty() || reminders.interval == 0 {
+                return invalid(
+                    "reminders need at least one offset and an interval of at least 1 second"
+                        .to_string(),
+                );
+            }
+            if reminders
+                .offsets
+                .iter()
+                .any(|offset| *offset > MAX_REMINDER_OFFSET)
+            {
+                return invalid(format!(
+                    "reminders.offsets must be at most {} minutes",
+                    MAX_REMINDER_OFFSET
+                ));
+            }
+            if reminders.max_delay > MAX_REMINDER_DELAY {
+                return invalid(format!(
+                    "reminders.max_delay must be at most {} seconds",
+                    MAX_REMINDER_DELAY
+                ));
+            }
+            let url = reminders.webhook_url.as_deref().unwrap_or_default();
+            if reminders.notifier == NotifierKind::Webhook && !url.starts_with("http://") {
+                return invalid(
+                    "the webhook notifier needs reminders.webhook_url (or APP_WEBHOOK_URL) \
+                     starting with http://"
+                        .to_string(),
+                );
+            }
+            if reminders.notifier == NotifierKind::Smtp && reminders.smtp.to.is_empty() {
+                return invalid("the smtp notifier needs reminders.smtp.to".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_env<T: FromStr>(
+    env: &impl Fn(&str) -> Option<String>,
+    name: &str,
+) -> Result<Option<T>, ConfigError>
+where
+    T::Err: fmt::Display,
+{
+    env(name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|err: T::Err| ConfigError::Env(name.to_string(), err.to_string()))
+        })
+        .transpose()
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn layers_override_in_order_test() {
+        let path = std::env::temp_dir().join(format!("config-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            "[server]\nhost = \"0.0.0.0\"\nport = 9000\nworkers = 2\n\n[storage]\nbackend = \"sqlite\"\n",
+        )
+        .unwrap();
+        let cli = Cli::try_parse_from([
+            "server",
+            "--config",
+            path.to_str().unwrap(),
+            "--port",
+            "9002",
+            "--max-page-size",
+            "5",
+        ])
+        .unwrap();
+
+        let env = env(&[
+            ("APP_PORT", "9001"),
+            ("APP_WORKERS", "4"),
+            ("APP_JWT_SECRET", "secret"),
+        ]);
+        let config = Config::load_from(cli, env);
+        std::fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        assert_eq!(config.server.host, "0.0.0.0");
+        assert_eq!(config.server.workers, Some(4));
+        assert_eq!(config.server.port, 9002);
+        assert_eq!(config.pagination.max_page_size, 5);
+        assert_eq!(config.pagination.default_page_size, 5);
+        assert_eq!(config.storage.backend, StorageBackend::Sqlite);
+    }
+
+    #[test]
+    fn rejects_invalid_values_test() {
+        let err = Config::load_from(Cli::default(), env(&[("APP_PORT", "eighty")])).unwrap_err();
+        assert!(matches!(err, ConfigError::Env(name, _) if name == "APP_PORT"));
+
+        let cors = env(&[("APP_CORS_ORIGINS", "localhost:8000")]);
+        let err = Config::load_from(Cli::default(), cors).unwrap_err();
+        assert!(matches!(err, ConfigError::Invalid(_)));
+
+        let err = Config::load_from(Cli::default(), env(&[])).unwrap_err();
+        assert!(matches!(err, ConfigError::Invalid(message) if message.contains("auth.secret")));
+
+        let pages = env(&[("APP_JWT_SECRET", "secret"), ("APP_MAX_PAGE_SIZE", "0")]);
+        let err = Config::load_from(Cli::default(), pages).unwrap_err();
+        assert!(matches!(err, ConfigError::Invalid(message) if message.contains("pagination")));
+
+        let webhook = env(&[("APP_JWT_SECRET", "secret"), ("APP_NOTIFIER", "webhook")]);
+        let err = Config::load_from(Cli::default(), webhook).unwrap_err();
+        assert!(matches!(err, ConfigError::Invalid(message) if message.contains("webhook_url")));
+
+        let mut config = Config::default();
+        config.auth.secret = Some("secret".to_string());
+        config.cors.allowed_origins = vec!["*".to_string()];
+        config.cors.supports_credentials = true;
+        let err = config.validate().unwrap_err();
+        assert!(
+            matches!(err, ConfigError::Invalid(message) if message.contains("supports_credentials"))
+        );
+        config.cors.supports_credentials = false;
+        assert!(config.validate().is_ok());
+
+        config.reminders.max_delay = u64::MAX;
+        let err = config.validate().unwrap_err();
+        assert!(matches!(err, ConfigError::Invalid(message) if message.contains("max_delay")));
+        config.reminders.max_delay = 3600;
+        config.reminders.offsets = vec![0, u64::MAX];
+        let err = config.validate().unwrap_err();
+        assert!(matches!(err, ConfigError::Invalid(message) if message.contains("offsets")));
+    }
+}
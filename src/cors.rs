@@ -0,0 +1,190 @@
+use actix_cors::Cors;
+
+use crate::config::CorsConfig;
+use crate::handler::ROUTES;
+
+// Builds the CORS middleware. Allowed methods are taken from the registered
+// routes, so adding a route with a new method needs no CORS change.
+pub fn cors(config: &CorsConfig) -> Cors {
+    let mut methods: Vec<&str> = ROUTES.iter().map(|(method, _)| *method).collect();
+    methods.sort_unstable();
+    methods.dedup();
+
+    let mut cors = Cors::default()
+        .allowed_methods(methods)
+        .allowed_headers(config.allowed_headers.iter().map(String::as_str))
+        .max_age(config.max_age);
+    if !config.exposed_headers.is_empty() {
+        cors = cors.expose_headers(config.exposed_headers.iter().map(String::as_str));
+    }
+    if config.supports_credentials {
+        cors = cors.supports_credentials();
+    }
+
+    if config.allowed_origins.iter().any(|origin| origin == "*") {
+        return cors.allow_any_origin();
+    }
+    let (patterns, exact): (Vec<String>, Vec<String>) = config
+        .allowed_origins
+        .iter()
+        .cloned()
+        .partition(|origin| origin.contains('*'));
+    for origin in &exact {
+        cors = cors.allowed_origin(origin);
+    }
+    if !patterns.is_empty() {
+        cors = cors.allowed_origin_fn(move |origin, _| {
+            let origin = origin.to_str().unwrap_or_default();
+            patterns
+                .iter()
+                .any(|pattern| wildcard_match(pattern, origin))
+        });
+    }
+    cors
+}
+
+// Matches `value` against `pattern`, where each `*` stands for one or more
+// characters other than `/`, so "https://*.example.com" matches
+// "https://app.example.com" but not "https://evil.com/.example.com".
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts = parts.peekable();
+    while let Some(part) = parts.next() {
+        let found = if parts.peek().is_none() {
+            // The last literal part has to end the value.
+            rest.strip_suffix(part)
+                .filter(|wild| !wild.is_empty())
+                .map(|wild| (wild, ""))
+        } else {
+            // The wildcard takes at least the first character, however many
+            // bytes it is
+            rest.chars().next().map(char::len_utf8).and_then(|first| {
+                let index = first + rest[first..].find(part)?;
+                Some((&rest[..index], &rest[index + part.len()..]))
+            })
+        };
+        match found {
+            Some((wild, tail)) if !wild.contains('/') => rest = tail,
+            _ => return false,
+        }
+    }
+    rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler;
+    use crate::model::AppState;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{http, web, App};
+
+    fn config(origins: &[&str]) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: origins.iter().map(|origin| origin.to_string()).collect(),
+            ..CorsConfig::default()
+        }
+    }
+
+    #[test]
+    fn wildcard_match_test() {
+        assert!(wildcard_match(
+            "https://*.example.com",
+            "https://app.example.com"
+        ));
+        assert!(wildcard_match(
+            "http://localhost:*",
+            "http://localhost:3000"
+        ));
+        assert!(!wildcard_match(
+            "https://*.example.com",
+            "https://example.com"
+        ));
+        assert!(!wildcard_match(
+            "https://*.example.com",
+            "https://evil.com/.example.com"
+        ));
+        assert!(!wildcard_match(
+            "https://*.example.com",
+            "https://app.example.com.evil"
+        ));
+        // Hosts need not be ASCII
+        assert!(wildcard_match(
+            "https://*.example.com",
+            "https://bücher.example.com"
+        ));
+        assert!(wildcard_match(
+            "http://*.localhost:*",
+            "http://ünïcode.localhost:3000"
+        ));
+        assert!(wildcard_match(
+            "http://*.localhost:*",
+            "http://é.localhost:8"
+        ));
+        assert!(!wildcard_match(
+            "http://*.localhost:*",
+            "http://é.localhost:"
+        ));
+    }
+
+    // Every registered route must answer a preflight for its own method.
+    #[actix_web::test]
+    async fn preflight_every_route_test() {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::init()))
+                .configure(handler::config)
+                .wrap(cors(&config(&[
+                    "http://localhost:8000",
+                    "https://*.example.com",
+                ]))),
+        )
+        .await;
+
+        for (method, path) in ROUTES {
+            let uri = path.replace("{id}", &uuid::Uuid::new_v4().to_string());
+            for origin in ["http://localhost:8000", "https://app.example.com"] {
+                let req = TestRequest::default()
+                    .method(http::Method::OPTIONS)
+                    .uri(&uri)
+                    .insert_header(("Origin", origin))
+                    .insert_header(("Access-Control-Request-Method", *method))
+                    .to_request();
+                let resp = call_service(&app, req).await;
+                assert_eq!(resp.status(), http::StatusCode::OK, "{} {}", method, uri);
+                let headers = resp.headers();
+                assert_eq!(headers.get("access-control-allow-origin").unwrap(), origin);
+                assert_eq!(headers.get("access-control-max-age").unwrap(), "3600");
+                let allowed = headers
+                    .get("access-control-allow-methods")
+                    .unwrap()
+                    .to_str()
+                    .unwrap();
+                assert!(allowed.contains(method), "{} not in {}", method, allowed);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn preflight_rejects_unknown_origin_test() {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::init()))
+                .configure(handler::config)
+                .wrap(cors(&config(&["https://*.example.com"]))),
+        )
+        .await;
+        let req = TestRequest::default()
+            .method(http::Method::OPTIONS)
+            .uri("/api/v1/todos")
+            .insert_header(("Origin", "https://example.org"))
+            .insert_header(("Access-Control-Request-Method", "GET"))
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,8 +1,29 @@
 use crate::{
-    model::{AppState, CreateTodoSchema, QueryOptions, Todo, UpdateTodoSchema},
-    response::{GenericResponse, SingleTodoResponse, TodoData, TodoListResponse},
+    account, api_key,
+    auth::{Authentication, Principal, RequireScope},
+    conditional::{body_etag, cacheable, etag, if_match_failed, Validators},
+    config::Config,
+    list,
+    model::{
+        AppState, CreateTodoSchema, DeleteMode, DeleteOptions, QueryOptions, ReplaceTodoSchema,
+        Scope, SearchOptions, Todo,
+    },
+    pagination::{encode_cursor, link_header},
+    patch::{patch_todo, Patch, PatchError, JSON_PATCH, MERGE_PATCH},
+    recurrence::{self, normalize_recurrence, Rule},
+    repository::{RepositoryError, TodoFilter},
+    response::{
+        error_response, GenericResponse, SearchResponse, SingleTodoResponse, TodoData,
+        TodoListResponse,
+    },
+    search::parse_query,
+    subtask::{self, parent_error},
+    tag::{self, normalize_tags},
+    trash::{self, live_todo, Trashed},
 };
-use actix_web::{delete, get, patch, post, web, HttpResponse, Responder};
+use actix_web::error::InternalError;
+use actix_web::http::header;
+use actix_web::{get, web, HttpRequest, HttpResponse, HttpResponseBuilder, Responder};
 use chrono::prelude::*;
 use uuid::Uuid;
 
@@ -19,145 +40,703 @@ pub async fn health_checker_handler() -> impl Responder {
 }
 
 // Get all todos
-#[get("/todos")]
 async fn get_todos(
+    req: HttpRequest,
     app_state: web::Data<AppState>,
+    principal: Principal,
     query: web::Query<QueryOptions>,
-) -> impl Responder {
-    let todo_db = app_state.todo_db.lock().unwrap();
-    let limit = query.limit.unwrap_or(10);
-    let page = query.page.unwrap_or(1);
-    let offset = (page - 1) * limit;
-    let results = todo_db.len();
-    let todos = todo_db
-        .iter()
-        .skip(offset)
-        .take(limit)
-        .cloned()
-        .collect::<Vec<Todo>>();
-
-    let response_json = &TodoListResponse {
+) -> Result<HttpResponse, RepositoryError> {
+    todo_list_response(&req, &app_state, &principal.subject, &query, |_| {})
+}
+
+// Lists the owner's todos as `query` asks, narrowed further by `narrow`, with
+// paging cursors and Link headers
+pub fn todo_list_response(
+    req: &HttpRequest,
+    app_state: &AppState,
+    owner: &str,
+    query: &QueryOptions,
+    narrow: impl FnOnce(&mut TodoFilter),
+) -> Result<HttpResponse, RepositoryError> {
+    let pagination = req
+        .app_data::<web::Data<Config>>()
+        .map(|config| config.pagination.clone())
+        .unwrap_or_default();
+    let mut todo_query = match query.todo_query(&pagination) {
+        Ok(todo_query) => todo_query,
+        Err(message) => return Ok(error_response(HttpResponse::BadRequest(), &message)),
+    };
+    narrow(&mut todo_query.filter);
+    // Every change to the owner's todos moves their latest updatedAt, including
+    // those that take a todo off the page. Purges only empty the trash, and
+    // some views change with the clock alone, so those go undated. It is read
+    // before the page so a change in between leaves the page looking older
+    // than it is, never newer.
+    let dated = !todo_query.filter.deleted && !query.is_time_relative();
+    let latest_change = match dated {
+        true => app_state.todo_db.max_updated_at(owner)?,
+        false => None,
+    };
+    let page = app_state.todo_db.list(owner, &todo_query)?;
+
+    let sort = todo_query.sort_keys();
+    let next_cursor = page
+        .items
+        .last()
+        .filter(|_| page.has_more)
+        .map(|todo| encode_cursor(todo, sort));
+    let prev_cursor = page
+        .items
+        .first()
+        .filter(|_| page.has_previous)
+        .map(|todo| encode_cursor(todo, sort));
+    let mut links = vec![("first", None)];
+    if let Some(cursor) = &prev_cursor {
+        links.push(("prev", Some(("before", cursor.as_str()))));
+    }
+    if let Some(cursor) = &next_cursor {
+        links.push(("next", Some(("after", cursor.as_str()))));
+    }
+
+    let last_modified = match dated {
+        true => latest_change,
+        false => page.items.iter().filter_map(|todo| todo.updatedAt).max(),
+    };
+    let body = serde_json::to_vec(&TodoListResponse {
         status: "success".to_string(),
-        results,
-        todos,
+        results: page.items.len(),
+        total: page.total,
+        has_more: page.has_more,
+        next_cursor: next_cursor.clone(),
+        prev_cursor: prev_cursor.clone(),
+        todos: page.items,
+    })?;
+    let validators = Validators {
+        etag: body_etag(&body),
+        last_modified,
+        dated,
     };
 
-    HttpResponse::Ok().json(response_json)
+    let mut response = HttpResponse::Ok();
+    response.insert_header((header::LINK, link_header(req, &links)));
+    Ok(cacheable(req, response, validators, body))
+}
+
+// Full-text search over titles and contents, best match first
+async fn search_todos(
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    query: web::Query<SearchOptions>,
+) -> Result<HttpResponse, RepositoryError> {
+    let pagination = req
+        .app_data::<web::Data<Config>>()
+        .map(|config| config.pagination.clone())
+        .unwrap_or_default();
+    let limit = query.limit.unwrap_or(pagination.default_page_size);
+    if limit == 0 {
+        return Ok(error_response(
+            HttpResponse::BadRequest(),
+            "limit must be at least 1.",
+        ));
+    }
+    let clauses = match parse_query(query.q.as_deref().unwrap_or_default()) {
+        Ok(clauses) => clauses,
+        Err(message) => return Ok(error_response(HttpResponse::BadRequest(), &message)),
+    };
+    let hits = app_state.search.search(
+        &principal.subject,
+        &clauses,
+        limit.min(pagination.max_page_size),
+    )?;
+
+    let response_json = &SearchResponse {
+        status: "success".to_string(),
+        results: hits.len(),
+        hits,
+    };
+    Ok(HttpResponse::Ok().json(response_json))
 }
 
 // Create new todo
-#[post("/todos")]
 async fn create_todo(
     app_state: web::Data<AppState>,
+    principal: Principal,
     payload: web::Json<CreateTodoSchema>,
-) -> impl Responder {
-    let mut todo_db = app_state.todo_db.lock().unwrap();
-    let mut todo = Todo::from(payload.into_inner());
+) -> Result<HttpResponse, RepositoryError> {
+    create_todo_response(&app_state, &principal.subject, payload.into_inner())
+}
+
+// Validates and stores a new todo for `owner`
+pub fn create_todo_response(
+    app_state: &AppState,
+    owner: &str,
+    payload: CreateTodoSchema,
+) -> Result<HttpResponse, RepositoryError> {
+    let mut todo = Todo::from(payload);
+    if let Some(message) = todo_error(app_state, owner, None, &mut todo)? {
+        return Ok(error_response(HttpResponse::BadRequest(), &message));
+    }
     let id = Uuid::new_v4().to_string();
     let now = Utc::now();
     todo.id = Some(id);
     todo.createdAt = Some(now);
     todo.updatedAt = Some(now);
-    todo_db.push(todo.clone());
+    todo.ownerId = Some(owner.to_string());
+    let todo = app_state.todo_db.create(todo)?;
 
-    let response_json = &SingleTodoResponse {
-        status: "success".to_string(),
-        data: TodoData { todo },
+    Ok(single_todo(
+        HttpResponse::Ok(),
+        TodoData {
+            todo,
+            next_occurrence: None,
+            subtasks: None,
+        },
+    ))
+}
+
+// Checks and normalizes the fields a client sets on a new or replaced todo,
+// returning why they are invalid if they are. `id` is the todo being replaced.
+fn todo_error(
+    app_state: &AppState,
+    owner: &str,
+    id: Option<&str>,
+    todo: &mut Todo,
+) -> Result<Option<String>, RepositoryError> {
+    todo.tags = match normalize_tags(std::mem::take(&mut todo.tags)) {
+        Ok(tags) => tags,
+        Err(message) => return Ok(Some(message)),
     };
+    if let Some(rule) = &todo.recurrence {
+        if todo.dueAt.is_none() {
+            return Ok(Some(NEEDS_DUE_AT.to_string()));
+        }
+        todo.recurrence = match normalize_recurrence(rule) {
+            Ok(rule) => Some(rule),
+            Err(message) => return Ok(Some(message)),
+        };
+    }
+    if let Some(parent) = &todo.parentId {
+        if let Some(message) = parent_error(app_state, owner, id, parent)? {
+            return Ok(Some(message));
+        }
+    }
+    if let Some(list_id) = &todo.listId {
+        if app_state.list_db.get_list(owner, list_id)?.is_none() {
+            return Ok(Some(list::NOT_FOUND.to_string()));
+        }
+    }
+    Ok(None)
+}
 
-    HttpResponse::Ok().json(response_json)
+// When `updated` completes `todo` and it recurs, stores the todo that carries
+// the series on. It stays in the same list and under the same parent, and
+// starts out open and unchanged. The rule moves over to it, so the write
+// completing `todo` has to clear it there, or completing that one again would
+// fork the series. Creating the next occurrence first means a failure before
+// that write leaves the rule where it was rather than losing it.
+fn continue_series(
+    app_state: &AppState,
+    todo: &Todo,
+    updated: &Todo,
+    now: DateTime<Utc>,
+) -> Result<Option<Todo>, RepositoryError> {
+    let completing = updated.completed == Some(true) && todo.completed != Some(true);
+    let (true, Some(rule), Some(due_at)) = (completing, &updated.recurrence, updated.dueAt) else {
+        return Ok(None);
+    };
+    let next = rule
+        .parse::<Rule>()
+        .ok()
+        .and_then(|rule| rule.next_after(due_at));
+    let next = next.map(|(rule, due_at)| Todo {
+        id: Some(Uuid::new_v4().to_string()),
+        title: updated.title.clone(),
+        content: updated.content.clone(),
+        completed: None,
+        createdAt: Some(now),
+        updatedAt: Some(now),
+        ownerId: updated.ownerId.clone(),
+        tags: updated.tags.clone(),
+        dueAt: Some(due_at),
+        priority: updated.priority,
+        recurrence: Some(rule.to_string()),
+        parentId: updated.parentId.clone(),
+        listId: updated.listId.clone(),
+        version: 1,
+        deletedAt: None,
+    });
+    next.map(|next| app_state.todo_db.create(next)).transpose()
 }
 
-// Get single todo by id
-#[get("/todos/{id}")]
-async fn get_todo_by_id(app_state: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
-    let todo_db = app_state.todo_db.lock().unwrap();
+// Takes back the next occurrence `continue_series` stored when the write
+// completing its todo did not go in
+fn withdraw_occurrence(
+    app_state: &AppState,
+    owner: &str,
+    next_occurrence: Option<&Todo>,
+) -> Result<(), RepositoryError> {
+    if let Some(id) = next_occurrence.and_then(|next| next.id.as_deref()) {
+        app_state.todo_db.delete(owner, id)?;
+    }
+    Ok(())
+}
+
+// Get single todo by id. Todos with subtasks carry no Last-Modified, as their
+// rollup changes without the todo's updatedAt moving.
+async fn get_todo_by_id(
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    path: web::Path<String>,
+) -> Result<HttpResponse, RepositoryError> {
     let id = path.into_inner();
-    let todo = todo_db.iter().find(|todo| todo.id == Some(id.clone()));
+    let todo = live_todo(&app_state, &principal.subject, &id)?;
 
     if let Some(todo) = todo {
-        let response_json = &SingleTodoResponse {
-            status: "success".to_string(),
-            data: TodoData { todo: todo.clone() },
+        let data = TodoData {
+            todo,
+            next_occurrence: None,
+            subtasks: subtask::rollup(&app_state, &principal.subject, &id)?,
         };
-        HttpResponse::Ok().json(response_json)
-    } else {
-        let response_json = &GenericResponse {
-            status: "error".to_string(),
-            message: "Todo not found.".to_string(),
+        let validators = Validators {
+            etag: etag(&data.todo, data.subtasks.as_ref()),
+            last_modified: data.todo.updatedAt.filter(|_| data.subtasks.is_none()),
+            dated: true,
         };
-        HttpResponse::NotFound().json(response_json)
+        let body = serde_json::to_vec(&SingleTodoResponse {
+            status: "success".to_string(),
+            data,
+        })?;
+        Ok(cacheable(&req, HttpResponse::Ok(), validators, body))
+    } else {
+        Ok(error_response(HttpResponse::NotFound(), NOT_FOUND))
     }
 }
 
-// Patch route for todos
-#[patch("/todos/{id}")]
+// Patch route for todos. Plain JSON bodies set the fields they name, merge
+// patches (RFC 7396) can also clear fields with null, and JSON patches
+// (RFC 6902) apply their operations in order. Completing a recurring todo
+// creates its next occurrence, which carries the rule on from there.
 async fn update_todo_by_id(
+    req: HttpRequest,
     app_state: web::Data<AppState>,
+    principal: Principal,
     path: web::Path<String>,
-    payload: web::Json<UpdateTodoSchema>,
-) -> impl Responder {
-    let mut todo_db = app_state.todo_db.lock().unwrap();
+    body: web::Bytes,
+) -> Result<HttpResponse, RepositoryError> {
     let id = path.into_inner();
-    let payload = payload.into_inner();
-    let todo = todo_db.iter_mut().find(|todo| todo.id == Some(id.clone()));
+    let owner = &principal.subject;
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    let patch = match Patch::parse(content_type, &body) {
+        Ok(patch) => patch,
+        Err(err) => return Ok(patch_failed(err)),
+    };
+    let Some(current) = live_todo(&app_state, owner, &id)? else {
+        return Ok(error_response(HttpResponse::NotFound(), NOT_FOUND));
+    };
+    let current_etag = todo_etag(&app_state, owner, &current)?;
+    if let Some(response) = if_match_failed(&req, Some(&current_etag)) {
+        return Ok(response);
+    }
+    let mut patched = match patch_todo(&current, &patch) {
+        Ok(patched) => patched,
+        Err(err) => return Ok(patch_failed(err)),
+    };
+    if let Some(message) = todo_error(&app_state, owner, Some(&id), &mut patched)? {
+        return Ok(error_response(HttpResponse::BadRequest(), &message));
+    }
 
-    if let Some(todo) = todo {
-        let now = Utc::now();
-        todo.title = payload.title.unwrap_or(todo.title.clone());
-        todo.content = payload.content.unwrap_or(todo.content.clone());
-        todo.completed = payload.completed;
-        todo.updatedAt = Some(now);
+    // The patch was checked against `current`, so it only goes in if nothing
+    // changed the todo since
+    let now = Utc::now();
+    patched.touch(now);
+    let next_occurrence = continue_series(&app_state, &current, &patched, now)?;
+    if next_occurrence.is_some() {
+        patched.recurrence = None;
+    }
+    let mut changed = None;
+    let todo = app_state.todo_db.update(owner, &id, &mut |todo| {
+        if todo.version != current.version {
+            changed = Some(todo.clone());
+            return;
+        }
+        *todo = patched.clone();
+    })?;
+    if changed.is_some() || todo.is_none() {
+        withdraw_occurrence(&app_state, owner, next_occurrence.as_ref())?;
+    }
+    if let Some(latest) = changed {
+        let latest_etag = todo_etag(&app_state, owner, &latest)?;
+        if let Some(response) = if_match_failed(&req, Some(&latest_etag)) {
+            return Ok(response);
+        }
+        return Ok(patch_failed(PatchError::TestFailed(CHANGED.to_string())));
+    }
 
-        let response_json = &SingleTodoResponse {
-            status: "success".to_string(),
-            data: TodoData { todo: todo.clone() },
+    if let Some(todo) = todo {
+        let data = TodoData {
+            todo,
+            next_occurrence,
+            subtasks: subtask::rollup(&app_state, owner, &id)?,
         };
-        HttpResponse::Ok().json(response_json)
+        Ok(single_todo(HttpResponse::Ok(), data))
     } else {
-        let response_json = &GenericResponse {
-            status: "error".to_string(),
-            message: "Todo not found.".to_string(),
-        };
-        HttpResponse::NotFound().json(response_json)
+        Ok(error_response(HttpResponse::NotFound(), NOT_FOUND))
     }
 }
 
-// Delete route for todos
-#[delete("/todos/{id}")]
-async fn delete_todo_by_id(
+// Put route for todos. Replaces every field a client can set, or creates the
+// todo under the id in the path when there is none, so sync clients can
+// upsert without looking first. A todo in the trash has to be restored first.
+async fn replace_todo_by_id(
+    req: HttpRequest,
     app_state: web::Data<AppState>,
+    principal: Principal,
     path: web::Path<String>,
-) -> impl Responder {
-    let mut todo_db = app_state.todo_db.lock().unwrap();
+    payload: web::Json<ReplaceTodoSchema>,
+) -> Result<HttpResponse, RepositoryError> {
     let id = path.into_inner();
-    let todo = todo_db.iter().position(|todo| todo.id == Some(id.clone()));
+    let owner = &principal.subject;
+    let mut replacement = Todo::from(payload.into_inner());
+    if replacement
+        .id
+        .as_ref()
+        .is_some_and(|body_id| *body_id != id)
+    {
+        return Ok(error_response(
+            HttpResponse::BadRequest(),
+            "The id in the body does not match the path.",
+        ));
+    }
+    if let Some(message) = todo_error(&app_state, owner, Some(&id), &mut replacement)? {
+        return Ok(error_response(HttpResponse::BadRequest(), &message));
+    }
+
+    let current = live_todo(&app_state, owner, &id)?;
+    if current.is_none() && app_state.todo_db.get(owner, &id)?.is_some() {
+        return Ok(error_response(
+            HttpResponse::Conflict(),
+            "Todo is in the trash; restore it first.",
+        ));
+    }
+    let current_etag = current
+        .as_ref()
+        .map(|current| todo_etag(&app_state, owner, current))
+        .transpose()?;
+    if let Some(response) = if_match_failed(&req, current_etag.as_ref()) {
+        return Ok(response);
+    }
+    let Some(current) = current else {
+        if !Uuid::try_parse(&id).is_ok_and(|uuid| uuid.to_string() == id) {
+            return Ok(error_response(
+                HttpResponse::BadRequest(),
+                "New todo ids must be lowercase hyphenated UUIDs.",
+            ));
+        }
+        replacement.id = Some(id);
+        replacement.ownerId = Some(owner.clone());
+        // Ids are unique across owners, but another owner's todo is no more
+        // visible here than anywhere else
+        let todo = match app_state.todo_db.create(replacement) {
+            Err(RepositoryError::Conflict(_)) => {
+                return Ok(error_response(HttpResponse::NotFound(), NOT_FOUND))
+            }
+            todo => todo?,
+        };
+        let mut response = HttpResponse::Created();
+        response.insert_header((header::LOCATION, req.path()));
+        let data = TodoData {
+            todo,
+            next_occurrence: None,
+            subtasks: None,
+        };
+        return Ok(single_todo(response, data));
+    };
+
+    // Like PATCH, the replacement only goes in if nothing changed the todo
+    // since it was checked
+    let now = Utc::now();
+    let mut updated = Todo {
+        id: current.id.clone(),
+        ownerId: current.ownerId.clone(),
+        createdAt: current.createdAt,
+        version: current.version,
+        ..replacement
+    };
+    updated.touch(now);
+    let next_occurrence = continue_series(&app_state, &current, &updated, now)?;
+    if next_occurrence.is_some() {
+        updated.recurrence = None;
+    }
+    let mut changed = None;
+    let todo = app_state.todo_db.update(owner, &id, &mut |todo| {
+        if todo.version != current.version {
+            changed = Some(todo.clone());
+            return;
+        }
+        *todo = updated.clone();
+    })?;
+    if changed.is_some() || todo.is_none() {
+        withdraw_occurrence(&app_state, owner, next_occurrence.as_ref())?;
+    }
+    if let Some(latest) = changed {
+        let latest_etag = todo_etag(&app_state, owner, &latest)?;
+        if let Some(response) = if_match_failed(&req, Some(&latest_etag)) {
+            return Ok(response);
+        }
+        return Ok(error_response(HttpResponse::Conflict(), CHANGED));
+    }
 
     if let Some(todo) = todo {
-        todo_db.remove(todo);
-        let response_json = &GenericResponse {
-            status: "success".to_string(),
-            message: "Todo deleted successfully.".to_string(),
+        let data = TodoData {
+            todo,
+            next_occurrence,
+            subtasks: subtask::rollup(&app_state, owner, &id)?,
         };
-        HttpResponse::Ok().json(response_json)
+        Ok(single_todo(HttpResponse::Ok(), data))
     } else {
-        let response_json = &GenericResponse {
-            status: "error".to_string(),
-            message: "Todo not found.".to_string(),
-        };
-        HttpResponse::NotFound().json(response_json)
+        Ok(error_response(HttpResponse::NotFound(), NOT_FOUND))
+    }
+}
+
+// Delete route for todos, which moves them to the trash. A todo with subtasks
+// is only deleted when `children` says what happens to them.
+async fn delete_todo_by_id(
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    path: web::Path<String>,
+    query: web::Query<DeleteOptions>,
+) -> Result<HttpResponse, RepositoryError> {
+    let id = path.into_inner();
+    let owner = &principal.subject;
+    let Some(current) = live_todo(&app_state, owner, &id)? else {
+        return Ok(error_response(HttpResponse::NotFound(), NOT_FOUND));
+    };
+    let current_etag = todo_etag(&app_state, owner, &current)?;
+    if let Some(response) = if_match_failed(&req, Some(&current_etag)) {
+        return Ok(response);
     }
+
+    let now = Utc::now();
+    let mode = query.children.unwrap_or_default();
+    if mode == DeleteMode::Reject && !subtask::children(&app_state, owner, &id)?.is_empty() {
+        return Ok(error_response(
+            HttpResponse::Conflict(),
+            "Todo has subtasks; pass children=orphan or children=cascade.",
+        ));
+    }
+    let others: Vec<Todo> = match mode {
+        DeleteMode::Reject => Vec::new(),
+        DeleteMode::Orphan => subtask::children(&app_state, owner, &id)?,
+        DeleteMode::Cascade => subtask::descendants(&app_state, owner, &id)?,
+    };
+    let others: Vec<String> = others.into_iter().filter_map(|todo| todo.id).collect();
+    let mut along = |todo: &mut Todo| match mode {
+        DeleteMode::Orphan if todo.parentId.as_ref() == Some(&id) => {
+            todo.parentId = None;
+            todo.touch(now);
+        }
+        DeleteMode::Cascade if todo.deletedAt.is_none() => {
+            todo.deletedAt = Some(now);
+            todo.touch(now);
+        }
+        _ => {}
+    };
+    // Like PATCH, the todo is only trashed if nothing changed it since it was
+    // checked, and its subtasks go with it in the same write or not at all
+    let version = Some(current.version);
+    match trash::trash(&app_state, owner, &id, version, &others, &mut along, now)? {
+        Trashed::Moved => {}
+        Trashed::Changed(latest) => {
+            let latest_etag = todo_etag(&app_state, owner, &latest)?;
+            if let Some(response) = if_match_failed(&req, Some(&latest_etag)) {
+                return Ok(response);
+            }
+            return Ok(error_response(HttpResponse::Conflict(), CHANGED));
+        }
+        Trashed::NotFound => return Ok(error_response(HttpResponse::NotFound(), NOT_FOUND)),
+    }
+
+    let response_json = &GenericResponse {
+        status: "success".to_string(),
+        message: "Todo moved to the trash.".to_string(),
+    };
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+pub const NOT_FOUND: &str = "Todo not found.";
+
+const NEEDS_DUE_AT: &str = "A recurring todo needs a dueAt.";
+
+const CHANGED: &str = "Todo was changed by another request; try again.";
+
+// The ETag of `todo` as it is now
+fn todo_etag(
+    app_state: &AppState,
+    owner: &str,
+    todo: &Todo,
+) -> Result<header::EntityTag, RepositoryError> {
+    let id = todo.id.as_deref().unwrap_or_default();
+    Ok(etag(todo, subtask::rollup(app_state, owner, id)?.as_ref()))
 }
 
+// A single todo, tagged with its version
+pub fn single_todo(mut response: HttpResponseBuilder, data: TodoData) -> HttpResponse {
+    response
+        .insert_header(header::ETag(etag(&data.todo, data.subtasks.as_ref())))
+        .json(SingleTodoResponse {
+            status: "success".to_string(),
+            data,
+        })
+}
+
+// Bodies and query strings that fail to parse, such as malformed dates, get
+// the same JSON error as other bad requests
+fn invalid_input<E>(err: E) -> actix_web::Error
+where
+    E: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    let response = error_response(HttpResponse::BadRequest(), &err.to_string());
+    InternalError::from_response(err, response).into()
+}
+
+fn patch_failed(err: PatchError) -> HttpResponse {
+    let (response, message) = match err {
+        PatchError::Unsupported => {
+            let mut response = HttpResponse::UnsupportedMediaType();
+            response.insert_header((
+                "Accept-Patch",
+                format!("application/json, {}, {}", MERGE_PATCH, JSON_PATCH),
+            ));
+            (
+                response,
+                "PATCH takes application/json, merge patch or JSON patch bodies.".to_string(),
+            )
+        }
+        PatchError::Invalid(message) => (HttpResponse::BadRequest(), message),
+        PatchError::TestFailed(message) => (HttpResponse::Conflict(), message),
+    };
+    error_response(response, &message)
+}
+
+// Every method and path registered by `config`, used to derive the CORS policy.
+// Keep in sync with `config`; the route tests check both directions.
+pub const ROUTES: &[(&str, &str)] = &[
+    ("GET", "/api/v1/health-check"),
+    ("GET", "/api/v1/todos"),
+    ("POST", "/api/v1/todos"),
+    ("GET", "/api/v1/todos/search"),
+    ("GET", "/api/v1/todos/tree"),
+    ("GET", "/api/v1/todos/{id}"),
+    ("PUT", "/api/v1/todos/{id}"),
+    ("PATCH", "/api/v1/todos/{id}"),
+    ("DELETE", "/api/v1/todos/{id}"),
+    ("GET", "/api/v1/todos/{id}/occurrences"),
+    ("GET", "/api/v1/todos/{id}/children"),
+    ("GET", "/api/v1/todos/{id}/tree"),
+    ("POST", "/api/v1/todos/{id}/restore"),
+    ("GET", "/api/v1/trash"),
+    ("GET", "/api/v1/lists"),
+    ("POST", "/api/v1/lists"),
+    ("GET", "/api/v1/lists/{id}"),
+    ("PATCH", "/api/v1/lists/{id}"),
+    ("DELETE", "/api/v1/lists/{id}"),
+    ("GET", "/api/v1/lists/{id}/todos"),
+    ("POST", "/api/v1/lists/{id}/todos"),
+    ("GET", "/api/v1/tags"),
+    ("PATCH", "/api/v1/tags/{name}"),
+    ("POST", "/api/v1/tags/{name}/merge"),
+    ("POST", "/api/v1/auth/register"),
+    ("POST", "/api/v1/auth/login"),
+    ("POST", "/api/v1/auth/logout"),
+    ("GET", "/api/v1/me"),
+    ("GET", "/api/v1/api-keys"),
+    ("POST", "/api/v1/api-keys"),
+    ("DELETE", "/api/v1/api-keys/{id}"),
+];
+
 // Merge the Routes
 pub fn config(conf: &mut web::ServiceConfig) {
+    // API keys only reach the todo routes their scopes allow
+    let read = || RequireScope(Scope::TodosRead);
+    let write = || RequireScope(Scope::TodosWrite);
+
+    // Everything but the health check, registration and login needs a bearer
+    // token or an API key
+    let authenticated = web::scope("")
+        .wrap(Authentication)
+        .service(
+            web::resource("/todos")
+                .route(web::get().to(get_todos).wrap(read()))
+                .route(web::post().to(create_todo).wrap(write())),
+        )
+        // Before /todos/{id}, which would otherwise take these as ids
+        .service(web::resource("/todos/search").route(web::get().to(search_todos).wrap(read())))
+        .service(web::resource("/todos/tree").route(web::get().to(subtask::get_tree).wrap(read())))
+        .service(
+            web::resource("/todos/{id}")
+                .route(web::get().to(get_todo_by_id).wrap(read()))
+                .route(web::put().to(replace_todo_by_id).wrap(write()))
+                .route(web::patch().to(update_todo_by_id).wrap(write()))
+                .route(web::delete().to(delete_todo_by_id).wrap(write())),
+        )
+        .service(
+            web::resource("/todos/{id}/occurrences")
+                .route(web::get().to(recurrence::get_occurrences).wrap(read())),
+        )
+        .service(
+            web::resource("/todos/{id}/children")
+                .route(web::get().to(subtask::get_children).wrap(read())),
+        )
+        .service(
+            web::resource("/todos/{id}/tree")
+                .route(web::get().to(subtask::get_subtree).wrap(read())),
+        )
+        .service(
+            web::resource("/todos/{id}/restore")
+                .route(web::post().to(trash::restore_todo).wrap(write())),
+        )
+        .service(web::resource("/trash").route(web::get().to(trash::get_trash).wrap(read())))
+        .service(
+            web::resource("/lists")
+                .route(web::get().to(list::get_lists).wrap(read()))
+                .route(web::post().to(list::create_list).wrap(write())),
+        )
+        .service(
+            web::resource("/lists/{id}")
+                .route(web::get().to(list::get_list).wrap(read()))
+                .route(web::patch().to(list::update_list).wrap(write()))
+                .route(web::delete().to(list::delete_list).wrap(write())),
+        )
+        .service(
+            web::resource("/lists/{id}/todos")
+                .route(web::get().to(list::get_list_todos).wrap(read()))
+                .route(web::post().to(list::create_list_todo).wrap(write())),
+        )
+        .service(web::resource("/tags").route(web::get().to(tag::get_tags).wrap(read())))
+        .service(
+            web::resource("/tags/{name}").route(web::patch().to(tag::rename_tag).wrap(write())),
+        )
+        .service(
+            web::resource("/tags/{name}/merge").route(web::post().to(tag::merge_tag).wrap(write())),
+        )
+        .service(account::logout)
+        .service(account::me)
+        .service(api_key::create_api_key)
+        .service(api_key::get_api_keys)
+        .service(api_key::delete_api_key);
     let scope = web::scope("/api/v1")
+        .app_data(web::JsonConfig::default().error_handler(|err, _| invalid_input(err)))
+        .app_data(web::QueryConfig::default().error_handler(|err, _| invalid_input(err)))
         .service(health_checker_handler)
-        .service(get_todos)
-        .service(create_todo)
-        .service(get_todo_by_id)
-        .service(update_todo_by_id)
-        .service(delete_todo_by_id);
+        .service(account::register)
+        .service(account::login)
+        .service(authenticated);
 
     conf.service(scope);
 }
@@ -165,129 +744,1411 @@ pub fn config(conf: &mut web::ServiceConfig) {
 #[cfg(test)]
 mod v1test {
     use super::*;
-    use crate::model::CreateTodoSchema;
+    use crate::auth::{self, test_support::bearer};
+    use crate::model::{CreateTodoSchema, Priority, UpdateTodoSchema};
+    use crate::repository::TodoQuery;
+    use crate::test_support::{api, app_states, racing, request};
     use actix_web::{http, test, App};
 
     #[actix_web::test]
     async fn health_checker_test() {
-        let mut app = test::init_service(App::new().service(health_checker_handler)).await;
+        let app = test::init_service(App::new().service(health_checker_handler)).await;
         let req = test::TestRequest::get().uri("/health-check").to_request();
-        let resp = test::call_service(&mut app, req).await;
+        let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), http::StatusCode::OK);
     }
 
     #[actix_web::test]
     async fn get_todos_test() {
-        let todo_db = AppState::init();
-        let app_data = web::Data::new(todo_db);
-        let mut app =
-            test::init_service(App::new().app_data(app_data.clone()).service(get_todos)).await;
-        let req = test::TestRequest::get().uri("/todos").to_request();
-        let resp = test::call_service(&mut app, req).await;
-        assert_eq!(resp.status(), http::StatusCode::OK);
+        for app_data in app_states() {
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_data.clone())
+                    .app_data(auth::test_support::authenticator())
+                    .wrap(Authentication)
+                    .route("/todos", web::get().to(get_todos)),
+            )
+            .await;
+            let req = test::TestRequest::get()
+                .uri("/todos")
+                .insert_header(bearer("alice"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+        }
     }
 
     #[actix_web::test]
     async fn create_todo_test() {
-        let todo_db = AppState::init();
-        let app_data = web::Data::new(todo_db);
-        let mut app =
-            test::init_service(App::new().app_data(app_data.clone()).service(create_todo)).await;
-        let body = CreateTodoSchema {
-            title: "Test Todo".to_string(),
-            content: "Test Todo Content".to_string(),
-        };
-        let req = test::TestRequest::post()
-            .uri("/todos")
-            .set_json(&body)
-            .to_request();
-        let resp = test::call_service(&mut app, req).await;
-        assert_eq!(resp.status(), http::StatusCode::OK);
+        for app_data in app_states() {
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_data.clone())
+                    .app_data(auth::test_support::authenticator())
+                    .wrap(Authentication)
+                    .route("/todos", web::post().to(create_todo)),
+            )
+            .await;
+            let body = CreateTodoSchema {
+                title: "Test Todo".to_string(),
+                content: "Test Todo Content".to_string(),
+                ..Default::default()
+            };
+            let req = test::TestRequest::post()
+                .uri("/todos")
+                .set_json(&body)
+                .insert_header(bearer("alice"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+        }
     }
 
     // Test creating and getting a todo by id
     #[actix_web::test]
     async fn get_todo_by_id_test() {
-        let todo_db = AppState::init();
-        let app_data = web::Data::new(todo_db);
-        let mut app = test::init_service(
-            App::new()
-                .app_data(app_data.clone())
-                .service(create_todo)
-                .service(get_todo_by_id),
-        )
-        .await;
-        let body = CreateTodoSchema {
-            title: "Test Todo".to_string(),
-            content: "Test Todo Content".to_string(),
-        };
-        let req = test::TestRequest::post()
-            .uri("/todos")
-            .set_json(&body)
-            .to_request();
-        let resp = test::call_service(&mut app, req).await;
-        assert_eq!(resp.status(), http::StatusCode::OK);
-        let todo: SingleTodoResponse = test::read_body_json(resp).await;
-        let id = todo.data.todo.id.unwrap();
-        let req = test::TestRequest::get()
-            .uri(&format!("/todos/{}", id))
-            .to_request();
-        let resp = test::call_service(&mut app, req).await;
-        assert_eq!(resp.status(), http::StatusCode::OK);
+        for app_data in app_states() {
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_data.clone())
+                    .app_data(auth::test_support::authenticator())
+                    .wrap(Authentication)
+                    .route("/todos", web::post().to(create_todo))
+                    .route("/todos/{id}", web::get().to(get_todo_by_id)),
+            )
+            .await;
+            let body = CreateTodoSchema {
+                title: "Test Todo".to_string(),
+                content: "Test Todo Content".to_string(),
+                ..Default::default()
+            };
+            let req = test::TestRequest::post()
+                .uri("/todos")
+                .set_json(&body)
+                .insert_header(bearer("alice"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            let todo: SingleTodoResponse = test::read_body_json(resp).await;
+            let id = todo.data.todo.id.unwrap();
+            let req = test::TestRequest::get()
+                .uri(&format!("/todos/{}", id))
+                .insert_header(bearer("alice"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+        }
     }
 
     #[actix_web::test]
     async fn get_todo_by_id_not_found_test() {
-        let todo_db = AppState::init();
-        let app_data = web::Data::new(todo_db);
-        let mut app = test::init_service(
-            App::new()
-                .app_data(app_data.clone())
-                .service(get_todo_by_id),
-        )
-        .await;
-        let id = uuid::Uuid::new_v4().to_string();
-        let req = test::TestRequest::get()
-            .uri(&format!("/todos/{}", id))
-            .to_request();
-        let resp = test::call_service(&mut app, req).await;
-        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+        for app_data in app_states() {
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_data.clone())
+                    .app_data(auth::test_support::authenticator())
+                    .wrap(Authentication)
+                    .route("/todos/{id}", web::get().to(get_todo_by_id)),
+            )
+            .await;
+            let id = uuid::Uuid::new_v4().to_string();
+            let req = test::TestRequest::get()
+                .uri(&format!("/todos/{}", id))
+                .insert_header(bearer("alice"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+        }
     }
 
     // Test creating and updating a todo by id
     #[actix_web::test]
     async fn update_todo_by_id_test() {
-        let todo_db = AppState::init();
-        let app_data = web::Data::new(todo_db);
-        let mut app = test::init_service(
+        for app_data in app_states() {
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_data.clone())
+                    .app_data(auth::test_support::authenticator())
+                    .wrap(Authentication)
+                    .route("/todos", web::post().to(create_todo))
+                    .route("/todos/{id}", web::patch().to(update_todo_by_id)),
+            )
+            .await;
+            let body = CreateTodoSchema {
+                title: "Test Todo".to_string(),
+                content: "Test Todo Content".to_string(),
+                ..Default::default()
+            };
+            let req = test::TestRequest::post()
+                .uri("/todos")
+                .set_json(&body)
+                .insert_header(bearer("alice"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            let todo: SingleTodoResponse = test::read_body_json(resp).await;
+            let id = todo.data.todo.id.unwrap();
+            let body = UpdateTodoSchema {
+                completed: Some(true),
+                ..Default::default()
+            };
+            let req = test::TestRequest::patch()
+                .uri(&format!("/todos/{}", id))
+                .set_json(&body)
+                .insert_header(bearer("alice"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+        }
+    }
+
+    // Each field of UpdateTodoSchema in each kind of PATCH body
+    #[actix_web::test]
+    async fn patch_todo_test() {
+        use serde_json::{json, Value};
+
+        for app_data in app_states() {
+            let app = api(&app_data).await;
+            let post = |uri: &str, body: Value| {
+                test::TestRequest::post()
+                    .uri(uri)
+                    .insert_header(bearer("alice"))
+                    .set_json(body)
+                    .to_request()
+            };
+            let list: crate::response::SingleListResponse = test::call_and_read_body_json(
+                &app,
+                post("/api/v1/lists", json!({ "name": "Work" })),
+            )
+            .await;
+            let list_id = list.data.list.id;
+            let parent: SingleTodoResponse = test::call_and_read_body_json(
+                &app,
+                post("/api/v1/todos", json!({ "title": "Parent", "content": "" })),
+            )
+            .await;
+            let parent_id = parent.data.todo.id.unwrap();
+            let created: SingleTodoResponse = test::call_and_read_body_json(
+                &app,
+                post("/api/v1/todos", json!({ "title": "Report", "content": "" })),
+            )
+            .await;
+            let id = created.data.todo.id.clone().unwrap();
+            let uri = format!("/api/v1/todos/{}", id);
+
+            let patch = |content_type: &str, body: &Value| {
+                test::TestRequest::patch()
+                    .uri(&uri)
+                    .insert_header(bearer("alice"))
+                    .insert_header((header::CONTENT_TYPE, content_type.to_string()))
+                    .set_payload(body.to_string())
+                    .to_request()
+            };
+            // The todo as JSON, without what every update bumps
+            let fields = |todo: Todo| {
+                let mut todo = serde_json::to_value(todo).unwrap();
+                let fields = todo.as_object_mut().unwrap();
+                fields.remove("updatedAt");
+                fields.remove("version");
+                todo
+            };
+            let mut expected = fields(created.data.todo);
+
+            // Plain JSON sets only the fields it names
+            for (field, value) in [
+                ("title", json!("Quarterly report")),
+                ("content", json!("Numbers")),
+                ("completed", json!(true)),
+                ("tags", json!(["work"])),
+                ("dueAt", json!("2030-01-05T09:00:00Z")),
+                ("priority", json!("high")),
+                ("recurrence", json!("FREQ=DAILY")),
+                ("parentId", json!(parent_id)),
+                ("listId", json!(list_id)),
+            ] {
+                let body = json!({ field: value });
+                let updated: SingleTodoResponse =
+                    test::call_and_read_body_json(&app, patch("application/json", &body)).await;
+                expected[field] = value;
+                assert_eq!(fields(updated.data.todo), expected, "{}", field);
+            }
+            // Null leaves a field as it was
+            let body = json!({ "title": "Renamed", "dueAt": null });
+            let updated: SingleTodoResponse =
+                test::call_and_read_body_json(&app, patch("application/json", &body)).await;
+            expected["title"] = json!("Renamed");
+            assert_eq!(fields(updated.data.todo), expected);
+
+            // A merge patch clears fields set to null
+            for (field, cleared) in [
+                ("recurrence", Value::Null),
+                ("dueAt", Value::Null),
+                ("priority", Value::Null),
+                ("parentId", Value::Null),
+                ("listId", Value::Null),
+                ("completed", Value::Null),
+                ("tags", json!([])),
+            ] {
+                let body = json!({ field: null });
+                let updated: SingleTodoResponse =
+                    test::call_and_read_body_json(&app, patch(MERGE_PATCH, &body)).await;
+                expected[field] = cleared;
+                assert_eq!(fields(updated.data.todo), expected, "{}", field);
+            }
+            let body = json!({ "title": "Merged", "content": "Patched", "tags": ["Home"] });
+            let updated: SingleTodoResponse =
+                test::call_and_read_body_json(&app, patch(MERGE_PATCH, &body)).await;
+            expected["title"] = json!("Merged");
+            expected["content"] = json!("Patched");
+            expected["tags"] = json!(["home"]);
+            assert_eq!(fields(updated.data.todo), expected);
+
+            // A JSON patch applies its operations in order
+            let body = json!([
+                { "op": "test", "path": "/title", "value": "Merged" },
+                { "op": "replace", "path": "/title", "value": "Patched" },
+                { "op": "add", "path": "/tags/-", "value": "later" },
+                { "op": "copy", "from": "/title", "path": "/content" },
+                { "op": "replace", "path": "/completed", "value": true },
+                { "op": "replace", "path": "/dueAt", "value": "2030-02-01T00:00:00Z" },
+                { "op": "replace", "path": "/priority", "value": "low" },
+                { "op": "replace", "path": "/recurrence", "value": "freq=weekly" },
+                { "op": "replace", "path": "/parentId", "value": parent_id },
+                { "op": "replace", "path": "/listId", "value": list_id },
+            ]);
+            let updated: SingleTodoResponse =
+                test::call_and_read_body_json(&app, patch(JSON_PATCH, &body)).await;
+            for (field, value) in [
+                ("title", json!("Patched")),
+                ("content", json!("Patched")),
+                ("tags", json!(["home", "later"])),
+                ("completed", json!(true)),
+                ("dueAt", json!("2030-02-01T00:00:00Z")),
+                ("priority", json!("low")),
+                ("parentId", json!(parent_id)),
+                ("listId", json!(list_id)),
+            ] {
+                expected[field] = value;
+            }
+            // Completing it hands the rule on to the next occurrence
+            let next = updated.data.next_occurrence.unwrap();
+            assert_eq!(next.recurrence.as_deref(), Some("FREQ=WEEKLY"));
+            assert_eq!(fields(updated.data.todo), expected);
+            let body = json!([
+                { "op": "test", "path": "/recurrence", "value": null },
+                { "op": "remove", "path": "/dueAt" },
+                { "op": "remove", "path": "/priority" },
+                { "op": "remove", "path": "/parentId" },
+                { "op": "remove", "path": "/listId" },
+                { "op": "remove", "path": "/completed" },
+                { "op": "remove", "path": "/tags/0" },
+            ]);
+            let updated: SingleTodoResponse =
+                test::call_and_read_body_json(&app, patch(JSON_PATCH, &body)).await;
+            for field in [
+                "recurrence",
+                "dueAt",
+                "priority",
+                "parentId",
+                "listId",
+                "completed",
+            ] {
+                expected[field] = Value::Null;
+            }
+            expected["tags"] = json!(["later"]);
+            assert_eq!(fields(updated.data.todo), expected);
+
+            for (content_type, body, status) in [
+                // A failed test applies nothing
+                (
+                    JSON_PATCH,
+                    json!([
+                        { "op": "replace", "path": "/title", "value": "Lost" },
+                        { "op": "test", "path": "/title", "value": "Merged" },
+                    ]),
+                    http::StatusCode::CONFLICT,
+                ),
+                (
+                    JSON_PATCH,
+                    json!([{ "op": "replace", "path": "/id", "value": "other" }]),
+                    http::StatusCode::BAD_REQUEST,
+                ),
+                (
+                    JSON_PATCH,
+                    json!([{ "op": "add", "path": "/colour", "value": "red" }]),
+                    http::StatusCode::BAD_REQUEST,
+                ),
+                (
+                    JSON_PATCH,
+                    json!([{ "op": "remove", "path": "/title" }]),
+                    http::StatusCode::BAD_REQUEST,
+                ),
+                (
+                    JSON_PATCH,
+                    json!({ "op": "remove", "path": "/tags" }),
+                    http::StatusCode::BAD_REQUEST,
+                ),
+                (
+                    MERGE_PATCH,
+                    json!({ "content": null }),
+                    http::StatusCode::BAD_REQUEST,
+                ),
+                (
+                    MERGE_PATCH,
+                    json!({ "createdAt": null }),
+                    http::StatusCode::BAD_REQUEST,
+                ),
+                (
+                    MERGE_PATCH,
+                    json!({ "recurrence": "FREQ=DAILY" }),
+                    http::StatusCode::BAD_REQUEST,
+                ),
+                (
+                    MERGE_PATCH,
+                    json!({ "listId": "missing" }),
+                    http::StatusCode::BAD_REQUEST,
+                ),
+                (
+                    MERGE_PATCH,
+                    json!({ "parentId": id }),
+                    http::StatusCode::BAD_REQUEST,
+                ),
+                (
+                    "text/plain",
+                    json!({}),
+                    http::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                ),
+            ] {
+                let resp = test::call_service(&app, patch(content_type, &body)).await;
+                assert_eq!(resp.status(), status, "{}", body);
+            }
+            let req = test::TestRequest::get()
+                .uri(&uri)
+                .insert_header(bearer("alice"))
+                .to_request();
+            let unchanged: SingleTodoResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(fields(unchanged.data.todo), expected);
+        }
+    }
+
+    #[actix_web::test]
+    async fn replace_todo_by_id_test() {
+        for app_data in app_states() {
+            let app = api(&app_data).await;
+            let put = |user: &str, id: &str, body: serde_json::Value| {
+                test::TestRequest::put()
+                    .uri(&format!("/api/v1/todos/{}", id))
+                    .insert_header(bearer(user))
+                    .set_json(body)
+                    .to_request()
+            };
+            let id = Uuid::new_v4().to_string();
+            let body = serde_json::json!({
+                "title": "Report",
+                "content": "Quarterly",
+                "completed": false,
+                "tags": ["Work"],
+                "dueAt": "2030-01-05T09:00:00Z",
+            });
+
+            // The first PUT creates the todo under the client's id
+            let resp = test::call_service(&app, put("alice", &id, body.clone())).await;
+            assert_eq!(resp.status(), http::StatusCode::CREATED);
+            assert_eq!(
+                resp.headers().get(header::LOCATION).unwrap(),
+                &format!("/api/v1/todos/{}", id)
+            );
+            let created: SingleTodoResponse = test::read_body_json(resp).await;
+            assert_eq!(created.data.todo.id.as_ref(), Some(&id));
+            assert_eq!(created.data.todo.tags, ["work"]);
+
+            // Later ones replace it, clearing whatever the body leaves out
+            let body = serde_json::json!({ "title": "Report", "content": "", "completed": true });
+            let resp = test::call_service(&app, put("alice", &id, body.clone())).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            let replaced: SingleTodoResponse = test::read_body_json(resp).await;
+            let todo = replaced.data.todo;
+            assert_eq!(todo.completed, Some(true));
+            assert!(todo.tags.is_empty());
+            assert_eq!(todo.dueAt, None);
+            assert_eq!(todo.createdAt, created.data.todo.createdAt);
+
+            for (id, body) in [
+                // Incomplete body
+                (
+                    id.clone(),
+                    serde_json::json!({ "title": "Report", "content": "" }),
+                ),
+                // Ids that disagree
+                (
+                    id.clone(),
+                    serde_json::json!({
+                        "id": Uuid::new_v4().to_string(),
+                        "title": "Report", "content": "", "completed": false
+                    }),
+                ),
+                // New ids must be UUIDs
+                (
+                    "report".to_string(),
+                    serde_json::json!({ "title": "Report", "content": "", "completed": false }),
+                ),
+                (
+                    id.to_uppercase(),
+                    serde_json::json!({ "title": "Report", "content": "", "completed": false }),
+                ),
+            ] {
+                let resp = test::call_service(&app, put("alice", &id, body)).await;
+                assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+            }
+
+            // Another user cannot take the id over, nor learn that it is taken
+            let resp = test::call_service(&app, put("bob", &id, body.clone())).await;
+            assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+            let resp: GenericResponse = test::read_body_json(resp).await;
+            assert_eq!(resp.message, NOT_FOUND);
+            let resp = test::call_service(&app, put("alice", &id, body.clone())).await;
+            let replaced: SingleTodoResponse = test::read_body_json(resp).await;
+            assert_eq!(replaced.data.todo.ownerId.as_deref(), Some("alice"));
+
+            // Nor does a PUT bring a trashed todo back
+            trash::trash(&app_data, "alice", &id, None, &[], &mut |_| {}, Utc::now()).unwrap();
+            let resp = test::call_service(&app, put("alice", &id, body)).await;
+            assert_eq!(resp.status(), http::StatusCode::CONFLICT);
+            let resp: GenericResponse = test::read_body_json(resp).await;
+            assert_eq!(resp.message, "Todo is in the trash; restore it first.");
+        }
+    }
+
+    #[actix_web::test]
+    async fn if_match_test() {
+        use crate::config::ConcurrencyConfig;
+        use serde_json::json;
+
+        for (app_data, require_if_match) in app_states().into_iter().zip([false, true]) {
+            let settings = Config {
+                concurrency: ConcurrencyConfig { require_if_match },
+                ..Config::default()
+            };
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_data.clone())
+                    .app_data(web::Data::new(settings))
+                    .app_data(auth::test_support::authenticator())
+                    .configure(config),
+            )
+            .await;
+            let request = |method: http::Method, uri: &str, if_match: Option<&str>| {
+                let mut req = test::TestRequest::default()
+                    .method(method)
+                    .uri(uri)
+                    .insert_header(bearer("alice"))
+                    .set_json(json!({ "title": "Report", "content": "", "completed": false }));
+                if let Some(if_match) = if_match {
+                    req = req.insert_header((header::IF_MATCH, if_match));
+                }
+                req.to_request()
+            };
+            let etag = |resp: &actix_web::dev::ServiceResponse| {
+                resp.headers()
+                    .get(header::ETAG)
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            };
+
+            let resp =
+                test::call_service(&app, request(http::Method::POST, "/api/v1/todos", None)).await;
+            assert_eq!(etag(&resp), "\"1\"");
+            let created: SingleTodoResponse = test::read_body_json(resp).await;
+            let uri = format!("/api/v1/todos/{}", created.data.todo.id.unwrap());
+            let resp = test::call_service(&app, request(http::Method::GET, &uri, None)).await;
+            assert_eq!(etag(&resp), "\"1\"");
+
+            let resp =
+                test::call_service(&app, request(http::Method::PATCH, &uri, Some("\"1\""))).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            assert_eq!(etag(&resp), "\"2\"");
+            let updated: SingleTodoResponse = test::read_body_json(resp).await;
+            assert_eq!(updated.data.todo.version, 2);
+
+            // A client still holding version 1 is turned away
+            for method in [http::Method::PATCH, http::Method::PUT, http::Method::DELETE] {
+                let resp = test::call_service(&app, request(method, &uri, Some("\"1\""))).await;
+                assert_eq!(resp.status(), http::StatusCode::PRECONDITION_FAILED);
+            }
+            // Weak tags never match
+            let resp =
+                test::call_service(&app, request(http::Method::PUT, &uri, Some("W/\"2\""))).await;
+            assert_eq!(resp.status(), http::StatusCode::PRECONDITION_FAILED);
+            let resp =
+                test::call_service(&app, request(http::Method::PUT, &uri, Some("\"1\", \"2\"")))
+                    .await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            assert_eq!(etag(&resp), "\"3\"");
+
+            // `*` only matches a todo that exists, so it stops create-on-PUT
+            let new_uri = format!("/api/v1/todos/{}", Uuid::new_v4());
+            let resp =
+                test::call_service(&app, request(http::Method::PUT, &new_uri, Some("*"))).await;
+            assert_eq!(resp.status(), http::StatusCode::PRECONDITION_FAILED);
+            // Creating never needs If-Match
+            let resp = test::call_service(&app, request(http::Method::PUT, &new_uri, None)).await;
+            assert_eq!(resp.status(), http::StatusCode::CREATED);
+
+            let unconditional = match require_if_match {
+                true => http::StatusCode::PRECONDITION_REQUIRED,
+                false => http::StatusCode::OK,
+            };
+            let resp = test::call_service(&app, request(http::Method::PATCH, &uri, None)).await;
+            assert_eq!(resp.status(), unconditional);
+            let resp =
+                test::call_service(&app, request(http::Method::DELETE, &new_uri, None)).await;
+            assert_eq!(resp.status(), unconditional);
+            let resp =
+                test::call_service(&app, request(http::Method::DELETE, &uri, Some("*"))).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+        }
+    }
+
+    #[actix_web::test]
+    async fn replace_race_test() {
+        use serde_json::json;
+
+        for app_data in app_states() {
+            let app = api(&app_data).await;
+            let req = request(
+                http::Method::POST,
+                "/api/v1/todos",
+                json!({ "title": "Report", "content": "" }),
+            );
+            let created: SingleTodoResponse = test::call_and_read_body_json(&app, req).await;
+            let id = created.data.todo.id.unwrap();
+            let uri = format!("/api/v1/todos/{}", id);
+
+            // Another request changes or trashes the todo between the PUT's
+            // checks and its write
+            for (if_match, trashes, status) in [
+                (Some("\"1\""), false, http::StatusCode::PRECONDITION_FAILED),
+                (None, false, http::StatusCode::CONFLICT),
+                (Some("*"), true, http::StatusCode::CONFLICT),
+            ] {
+                let racing_id = id.clone();
+                let raced = racing(&app_data, move |todo_db| {
+                    let now = Utc::now();
+                    todo_db
+                        .update("alice", &racing_id, &mut |todo| {
+                            todo.content = "Edited".to_string();
+                            if trashes {
+                                todo.deletedAt = Some(now);
+                            }
+                            todo.touch(now);
+                        })
+                        .unwrap();
+                });
+                let app = api(&raced).await;
+                let mut req = test::TestRequest::put()
+                    .uri(&uri)
+                    .insert_header(bearer("alice"))
+                    .set_json(json!({ "title": "Summary", "content": "", "completed": false }));
+                if let Some(if_match) = if_match {
+                    req = req.insert_header((header::IF_MATCH, if_match));
+                }
+                let resp = test::call_service(&app, req.to_request()).await;
+                assert_eq!(resp.status(), status, "{:?}", if_match);
+
+                let stored = app_data.todo_db.get("alice", &id).unwrap().unwrap();
+                assert_eq!(stored.title, "Report");
+                assert_eq!(stored.content, "Edited");
+                assert_eq!(stored.deletedAt.is_some(), trashes);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn delete_race_test() {
+        use serde_json::json;
+
+        for app_data in app_states() {
+            let app = api(&app_data).await;
+            let req = request(
+                http::Method::POST,
+                "/api/v1/todos",
+                json!({ "title": "Report", "content": "" }),
+            );
+            let created: SingleTodoResponse = test::call_and_read_body_json(&app, req).await;
+            let id = created.data.todo.id.unwrap();
+
+            // A PATCH lands between the DELETE's If-Match check and the trashing
+            let racing_id = id.clone();
+            let raced = racing(&app_data, move |todo_db| {
+                todo_db
+                    .update("alice", &racing_id, &mut |todo| todo.touch(Utc::now()))
+                    .unwrap();
+            });
+            let app = api(&raced).await;
+            let req = test::TestRequest::delete()
+                .uri(&format!("/api/v1/todos/{}", id))
+                .insert_header(bearer("alice"))
+                .insert_header((header::IF_MATCH, "\"1\""))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::PRECONDITION_FAILED);
+            let stored = app_data.todo_db.get("alice", &id).unwrap().unwrap();
+            assert_eq!(stored.deletedAt, None);
+
+            // Subtasks are only orphaned or trashed along with their parent
+            let req = request(
+                http::Method::POST,
+                "/api/v1/todos",
+                json!({ "title": "Draft", "content": "", "parentId": id }),
+            );
+            let child: SingleTodoResponse =
+                test::call_and_read_body_json(&api(&app_data).await, req).await;
+            let child_id = child.data.todo.id.unwrap();
+            for children in ["orphan", "cascade"] {
+                let racing_id = id.clone();
+                let raced = racing(&app_data, move |todo_db| {
+                    todo_db
+                        .update("alice", &racing_id, &mut |todo| todo.touch(Utc::now()))
+                        .unwrap();
+                });
+                let req = test::TestRequest::delete()
+                    .uri(&format!("/api/v1/todos/{}?children={}", id, children))
+                    .insert_header(bearer("alice"))
+                    .to_request();
+                let resp = test::call_service(&api(&raced).await, req).await;
+                assert_eq!(resp.status(), http::StatusCode::CONFLICT, "{}", children);
+                let child = app_data.todo_db.get("alice", &child_id).unwrap().unwrap();
+                assert_eq!(child.parentId.as_ref(), Some(&id));
+                assert_eq!(child.deletedAt, None);
+                assert_eq!(child.version, 1);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn conditional_get_test() {
+        use crate::config::CachingConfig;
+        use serde_json::json;
+
+        for app_data in app_states() {
+            let settings = Config {
+                caching: CachingConfig {
+                    cache_control: "private, max-age=5".to_string(),
+                },
+                ..Config::default()
+            };
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_data.clone())
+                    .app_data(web::Data::new(settings))
+                    .app_data(auth::test_support::authenticator())
+                    .configure(config),
+            )
+            .await;
+            let get = |uri: &str, conditions: &[(header::HeaderName, &str)]| {
+                let mut req = test::TestRequest::get()
+                    .uri(uri)
+                    .insert_header(bearer("alice"));
+                for condition in conditions {
+                    req = req.insert_header(condition.clone());
+                }
+                req.to_request()
+            };
+            let header_value = |resp: &actix_web::dev::ServiceResponse, name| {
+                resp.headers()
+                    .get(name)
+                    .map(|value| value.to_str().unwrap().to_string())
+            };
+
+            let req = test::TestRequest::post()
+                .uri("/api/v1/todos")
+                .insert_header(bearer("alice"))
+                .set_json(json!({ "title": "Dashboard", "content": "" }))
+                .to_request();
+            let created: SingleTodoResponse = test::call_and_read_body_json(&app, req).await;
+            let id = created.data.todo.id.unwrap();
+            let uri = format!("/api/v1/todos/{}", id);
+
+            let resp = test::call_service(&app, get(&uri, &[])).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            assert_eq!(
+                header_value(&resp, header::CACHE_CONTROL).as_deref(),
+                Some("private, max-age=5")
+            );
+            let last_modified = header_value(&resp, header::LAST_MODIFIED).unwrap();
+            let resp =
+                test::call_service(&app, get(&uri, &[(header::IF_NONE_MATCH, "W/\"1\"")])).await;
+            assert_eq!(resp.status(), http::StatusCode::NOT_MODIFIED);
+            assert_eq!(header_value(&resp, header::ETAG).as_deref(), Some("\"1\""));
+            assert!(test::read_body(resp).await.is_empty());
+            let resp = test::call_service(
+                &app,
+                get(&uri, &[(header::IF_MODIFIED_SINCE, &last_modified)]),
+            )
+            .await;
+            assert_eq!(resp.status(), http::StatusCode::NOT_MODIFIED);
+            // If-None-Match wins over If-Modified-Since
+            let conditions = [
+                (header::IF_NONE_MATCH, "\"2\""),
+                (header::IF_MODIFIED_SINCE, last_modified.as_str()),
+            ];
+            let resp = test::call_service(&app, get(&uri, &conditions)).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+
+            let resp = test::call_service(&app, get("/api/v1/todos", &[])).await;
+            let list_etag = header_value(&resp, header::ETAG).unwrap();
+            assert!(header_value(&resp, header::LAST_MODIFIED).is_some());
+            let resp = test::call_service(
+                &app,
+                get("/api/v1/todos", &[(header::IF_NONE_MATCH, &list_etag)]),
+            )
+            .await;
+            assert_eq!(resp.status(), http::StatusCode::NOT_MODIFIED);
+
+            // A new subtask changes the parent's rollup and the list, but no
+            // updatedAt the client has seen
+            let req = test::TestRequest::post()
+                .uri("/api/v1/todos")
+                .insert_header(bearer("alice"))
+                .set_json(json!({ "title": "Chart", "content": "", "parentId": id }))
+                .to_request();
+            test::call_service(&app, req).await;
+            let resp = test::call_service(
+                &app,
+                get(&uri, &[(header::IF_MODIFIED_SINCE, &last_modified)]),
+            )
+            .await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            assert_eq!(
+                header_value(&resp, header::ETAG).as_deref(),
+                Some("\"1-0-1\"")
+            );
+            assert_eq!(header_value(&resp, header::LAST_MODIFIED), None);
+            let conditions = [
+                (header::IF_NONE_MATCH, list_etag.as_str()),
+                (header::IF_MODIFIED_SINCE, "Fri, 01 Jan 2100 00:00:00 GMT"),
+            ];
+            let resp = test::call_service(&app, get("/api/v1/todos", &conditions)).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            assert_ne!(header_value(&resp, header::ETAG).unwrap(), list_etag);
+
+            // Lists revalidate by date too, and a todo leaving one moves it
+            let resp = test::call_service(&app, get("/api/v1/todos", &[])).await;
+            let list_modified = header_value(&resp, header::LAST_MODIFIED).unwrap();
+            let resp = test::call_service(
+                &app,
+                get(
+                    "/api/v1/todos",
+                    &[(header::IF_MODIFIED_SINCE, &list_modified)],
+                ),
+            )
+            .await;
+            assert_eq!(resp.status(), http::StatusCode::NOT_MODIFIED);
+            let chart = app_data
+                .todo_db
+                .list("alice", &TodoQuery::default())
+                .unwrap();
+            let chart_id = chart.items[1].id.clone().unwrap();
+            // HTTP dates have whole seconds, so the change has to land in a
+            // later one
+            let later = Utc::now() + chrono::TimeDelta::seconds(2);
+            trash::trash(&app_data, "alice", &chart_id, None, &[], &mut |_| {}, later).unwrap();
+            let resp = test::call_service(
+                &app,
+                get(
+                    "/api/v1/todos",
+                    &[(header::IF_MODIFIED_SINCE, &list_modified)],
+                ),
+            )
+            .await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            let list: TodoListResponse = test::read_body_json(resp).await;
+            assert_eq!(list.todos.len(), 1);
+        }
+    }
+
+    // Test creating and deleting a todo by id
+    #[actix_web::test]
+    async fn delete_todo_by_id_test() {
+        for app_data in app_states() {
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_data.clone())
+                    .app_data(auth::test_support::authenticator())
+                    .wrap(Authentication)
+                    .route("/todos", web::post().to(create_todo))
+                    .route("/todos/{id}", web::get().to(get_todo_by_id))
+                    .route("/todos/{id}", web::delete().to(delete_todo_by_id)),
+            )
+            .await;
+            let body = CreateTodoSchema {
+                title: "Test Todo".to_string(),
+                content: "Test Todo Content".to_string(),
+                ..Default::default()
+            };
+            let req = test::TestRequest::post()
+                .uri("/todos")
+                .set_json(&body)
+                .insert_header(bearer("alice"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            let todo: SingleTodoResponse = test::read_body_json(resp).await;
+            let id = todo.data.todo.id.unwrap();
+            let req = test::TestRequest::delete()
+                .uri(&format!("/todos/{}", id))
+                .insert_header(bearer("alice"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            let req = test::TestRequest::get()
+                .uri(&format!("/todos/{}", id))
+                .insert_header(bearer("alice"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[actix_web::test]
+    async fn filter_todos_test() {
+        for app_data in app_states() {
+            let app = api(&app_data).await;
+            let mut ids = Vec::new();
+            for (title, content) in [
+                ("Buy milk", "From the corner shop"),
+                ("Write report", "Quarterly numbers"),
+                ("Call mum", "About the milk delivery"),
+            ] {
+                let body = CreateTodoSchema {
+                    title: title.to_string(),
+                    content: content.to_string(),
+                    ..Default::default()
+                };
+                let req = test::TestRequest::post()
+                    .uri("/api/v1/todos")
+                    .insert_header(bearer("alice"))
+                    .set_json(&body)
+                    .to_request();
+                let todo: SingleTodoResponse = test::call_and_read_body_json(&app, req).await;
+                ids.push(todo.data.todo.id.unwrap());
+            }
+            let body = UpdateTodoSchema {
+                completed: Some(true),
+                ..Default::default()
+            };
+            let req = test::TestRequest::patch()
+                .uri(&format!("/api/v1/todos/{}", ids[1]))
+                .insert_header(bearer("alice"))
+                .set_json(&body)
+                .to_request();
+            let updated: SingleTodoResponse = test::call_and_read_body_json(&app, req).await;
+            let updated_at = updated.data.todo.updatedAt.unwrap();
+            let created_at = updated.data.todo.createdAt.unwrap();
+
+            let at = |time: DateTime<Utc>| time.to_rfc3339_opts(SecondsFormat::Nanos, true);
+            // query, expected titles, expected filtered total
+            let cases = [
+                ("completed=true".to_string(), vec!["Write report"], 1),
+                (
+                    "completed=false".to_string(),
+                    vec!["Buy milk", "Call mum"],
+                    2,
+                ),
+                ("q=MILK".to_string(), vec!["Buy milk", "Call mum"], 2),
+                ("q=milk&completed=true".to_string(), vec![], 0),
+                (
+                    format!("updated_since={}", at(updated_at)),
+                    vec!["Write report"],
+                    1,
+                ),
+                (
+                    format!("created_after={}", at(created_at)),
+                    vec!["Call mum"],
+                    1,
+                ),
+                (
+                    format!("created_before={}", at(created_at)),
+                    vec!["Buy milk"],
+                    1,
+                ),
+                ("q=milk&limit=1&page=2".to_string(), vec!["Call mum"], 2),
+            ];
+            for (query, titles, total) in cases {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/api/v1/todos?{}", query))
+                    .insert_header(bearer("alice"))
+                    .to_request();
+                let todos: TodoListResponse = test::call_and_read_body_json(&app, req).await;
+                let found: Vec<&str> = todos.todos.iter().map(|todo| todo.title.as_str()).collect();
+                assert_eq!(found, titles, "{}", query);
+                assert_eq!(todos.results, titles.len(), "{}", query);
+                assert_eq!(todos.total, total, "{}", query);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn sort_todos_test() {
+        for app_data in app_states() {
+            let app = api(&app_data).await;
+            for title in ["banana", "Apple", "cherry", "apple"] {
+                let body = CreateTodoSchema {
+                    title: title.to_string(),
+                    content: "Test Todo Content".to_string(),
+                    ..Default::default()
+                };
+                let req = test::TestRequest::post()
+                    .uri("/api/v1/todos")
+                    .insert_header(bearer("alice"))
+                    .set_json(&body)
+                    .to_request();
+                let todo: SingleTodoResponse = test::call_and_read_body_json(&app, req).await;
+                if title == "cherry" || title == "Apple" {
+                    let body = UpdateTodoSchema {
+                        completed: Some(true),
+                        ..Default::default()
+                    };
+                    let req = test::TestRequest::patch()
+                        .uri(&format!("/api/v1/todos/{}", todo.data.todo.id.unwrap()))
+                        .insert_header(bearer("alice"))
+                        .set_json(&body)
+                        .to_request();
+                    test::call_service(&app, req).await;
+                }
+            }
+
+            let cases = [
+                ("", vec!["banana", "Apple", "cherry", "apple"]),
+                (
+                    "sort=createdAt&order=desc",
+                    vec!["apple", "cherry", "Apple", "banana"],
+                ),
+                ("sort=title", vec!["Apple", "apple", "banana", "cherry"]),
+                (
+                    "sort=-completed,title",
+                    vec!["Apple", "cherry", "apple", "banana"],
+                ),
+                (
+                    "sort=completed,-createdAt",
+                    vec!["apple", "banana", "cherry", "Apple"],
+                ),
+                ("sort=title&order=desc&limit=2", vec!["cherry", "banana"]),
+                ("sort=-updatedAt&limit=2", vec!["apple", "cherry"]),
+            ];
+            for (query, titles) in cases {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/api/v1/todos?{}", query))
+                    .insert_header(bearer("alice"))
+                    .to_request();
+                let todos: TodoListResponse = test::call_and_read_body_json(&app, req).await;
+                let found: Vec<&str> = todos.todos.iter().map(|todo| todo.title.as_str()).collect();
+                assert_eq!(found, titles, "{}", query);
+            }
+
+            for query in ["sort=colour", "order=up"] {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/api/v1/todos?{}", query))
+                    .insert_header(bearer("alice"))
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST, "{}", query);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn due_dates_test() {
+        for app_data in app_states() {
+            let app = api(&app_data).await;
+            let now = Utc::now();
+            let days = |days: i64| Some(now + chrono::TimeDelta::days(days));
+            for (title, due_at, priority) in [
+                ("Past", days(-2), Some(Priority::Low)),
+                ("Done", days(-2), Some(Priority::Normal)),
+                ("Now", Some(now), None),
+                ("Soon", days(3), Some(Priority::Urgent)),
+                ("Later", days(10), Some(Priority::High)),
+            ] {
+                let body = CreateTodoSchema {
+                    title: title.to_string(),
+                    content: String::new(),
+                    dueAt: due_at,
+                    priority,
+                    ..Default::default()
+                };
+                let req = test::TestRequest::post()
+                    .uri("/api/v1/todos")
+                    .insert_header(bearer("alice"))
+                    .set_json(&body)
+                    .to_request();
+                let todo: SingleTodoResponse = test::call_and_read_body_json(&app, req).await;
+                if title == "Done" {
+                    let req = test::TestRequest::patch()
+                        .uri(&format!("/api/v1/todos/{}", todo.data.todo.id.unwrap()))
+                        .insert_header(bearer("alice"))
+                        .set_json(serde_json::json!({ "completed": true }))
+                        .to_request();
+                    test::call_service(&app, req).await;
+                }
+            }
+
+            let cases = [
+                ("overdue=true", vec!["Past", "Now"]),
+                ("overdue=false", vec!["Done", "Soon", "Later"]),
+                ("due_today=true", vec!["Now"]),
+                ("due_within=7d", vec!["Soon"]),
+                ("due_within=2w", vec!["Soon", "Later"]),
+                ("due_within=2w&due_today=true", vec![]),
+                (
+                    "sort=-priority",
+                    vec!["Soon", "Later", "Done", "Past", "Now"],
+                ),
+                (
+                    "sort=dueAt&completed=false&order=desc",
+                    vec!["Later", "Soon", "Now", "Past"],
+                ),
+            ];
+            for (query, titles) in cases {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/api/v1/todos?{}", query))
+                    .insert_header(bearer("alice"))
+                    .to_request();
+                let todos: TodoListResponse = test::call_and_read_body_json(&app, req).await;
+                let found: Vec<&str> = todos.todos.iter().map(|todo| todo.title.as_str()).collect();
+                assert_eq!(found, titles, "{}", query);
+            }
+
+            for query in [
+                "due_within=7",
+                "due_within=0d",
+                "due_today=true&tz=Mars",
+                "overdue=maybe",
+            ] {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/api/v1/todos?{}", query))
+                    .insert_header(bearer("alice"))
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST, "{}", query);
+            }
+
+            // Malformed dates are rejected with a JSON error
+            let req = test::TestRequest::post()
+                .uri("/api/v1/todos")
+                .insert_header(bearer("alice"))
+                .set_json(
+                    serde_json::json!({ "title": "t", "content": "", "dueAt": "next tuesday" }),
+                )
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+            let body: GenericResponse = test::read_body_json(resp).await;
+            assert_eq!(body.status, "error");
+        }
+    }
+
+    #[actix_web::test]
+    async fn search_todos_test() {
+        for app_data in app_states() {
+            let app = api(&app_data).await;
+            let mut ids = Vec::new();
+            for (title, content) in [
+                ("Buy milk", "From the corner shop"),
+                ("Write report", "Quarterly milk numbers"),
+            ] {
+                let body = CreateTodoSchema {
+                    title: title.to_string(),
+                    content: content.to_string(),
+                    ..Default::default()
+                };
+                let req = test::TestRequest::post()
+                    .uri("/api/v1/todos")
+                    .insert_header(bearer("alice"))
+                    .set_json(&body)
+                    .to_request();
+                let todo: SingleTodoResponse = test::call_and_read_body_json(&app, req).await;
+                ids.push(todo.data.todo.id.unwrap());
+            }
+
+            let search = |query: &str| {
+                test::TestRequest::get()
+                    .uri(&format!("/api/v1/todos/search?{}", query))
+                    .insert_header(bearer("alice"))
+                    .to_request()
+            };
+            let found: SearchResponse = test::call_and_read_body_json(&app, search("q=milk")).await;
+            assert_eq!(found.results, 2);
+            assert_eq!(found.hits[0].todo.id.as_ref(), Some(&ids[0]));
+            assert_eq!(found.hits[0].highlights.title, "Buy <mark>milk</mark>");
+
+            // The index follows updates made through the API
+            let body = UpdateTodoSchema {
+                title: Some("Write summary".to_string()),
+                ..Default::default()
+            };
+            let req = test::TestRequest::patch()
+                .uri(&format!("/api/v1/todos/{}", ids[1]))
+                .insert_header(bearer("alice"))
+                .set_json(&body)
+                .to_request();
+            test::call_service(&app, req).await;
+            let found: SearchResponse =
+                test::call_and_read_body_json(&app, search("q=%22write+summary%22+quart*")).await;
+            assert_eq!(found.results, 1);
+            assert_eq!(
+                found.hits[0].highlights.content,
+                "<mark>Quarterly</mark> milk numbers"
+            );
+
+            let resp = test::call_service(&app, search("q=")).await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+
+            let req = test::TestRequest::get()
+                .uri("/api/v1/todos/search?q=milk")
+                .insert_header(bearer("bob"))
+                .to_request();
+            let found: SearchResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(found.results, 0);
+        }
+    }
+
+    #[actix_web::test]
+    async fn cursor_pagination_test() {
+        for app_data in app_states() {
+            let mut app_config = Config::default();
+            app_config.pagination.max_page_size = 3;
+            let app = test::init_service(
+                App::new()
+                    .app_data(app_data.clone())
+                    .app_data(web::Data::new(app_config))
+                    .app_data(auth::test_support::authenticator())
+                    .configure(config),
+            )
+            .await;
+            let create = |title: &str| {
+                let body = CreateTodoSchema {
+                    title: title.to_string(),
+                    content: "Test Todo Content".to_string(),
+                    ..Default::default()
+                };
+                test::TestRequest::post()
+                    .uri("/api/v1/todos")
+                    .insert_header(bearer("alice"))
+                    .set_json(&body)
+                    .to_request()
+            };
+            for title in ["one", "two", "three", "four", "five"] {
+                test::call_service(&app, create(title)).await;
+            }
+
+            // Walk forward while another todo is added part way through
+            let mut titles = Vec::new();
+            let mut uri = "/api/v1/todos?limit=2".to_string();
+            loop {
+                let req = test::TestRequest::get()
+                    .uri(&uri)
+                    .insert_header(bearer("alice"))
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                let link = resp.headers().get(header::LINK).unwrap().to_str().unwrap();
+                let link = link.to_string();
+                let page: TodoListResponse = test::read_body_json(resp).await;
+                titles.extend(page.todos.into_iter().map(|todo| todo.title));
+                if titles.len() == 2 {
+                    test::call_service(&app, create("six")).await;
+                }
+                let Some(cursor) = page.next_cursor else {
+                    assert!(!page.has_more);
+                    assert!(!link.contains("rel=\"next\""));
+                    break;
+                };
+                assert!(page.has_more);
+                uri = format!("/api/v1/todos?limit=2&after={}", cursor);
+                assert!(
+                    link.contains(&format!("<{}>; rel=\"next\"", uri)),
+                    "{}",
+                    link
+                );
+            }
+            assert_eq!(titles, ["one", "two", "three", "four", "five", "six"]);
+
+            // And back again from the end
+            let req = test::TestRequest::get()
+                .uri("/api/v1/todos?limit=2&page=3")
+                .insert_header(bearer("alice"))
+                .to_request();
+            let page: TodoListResponse = test::call_and_read_body_json(&app, req).await;
+            let mut cursor = page.prev_cursor;
+            let mut titles = Vec::new();
+            while let Some(before) = cursor {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/api/v1/todos?limit=2&before={}", before))
+                    .insert_header(bearer("alice"))
+                    .to_request();
+                let page: TodoListResponse = test::call_and_read_body_json(&app, req).await;
+                let mut earlier: Vec<String> =
+                    page.todos.into_iter().map(|todo| todo.title).collect();
+                earlier.extend(titles);
+                titles = earlier;
+                cursor = page.prev_cursor;
+            }
+            assert_eq!(titles, ["one", "two", "three", "four"]);
+
+            // Limits are clamped to the configured maximum
+            let req = test::TestRequest::get()
+                .uri("/api/v1/todos?limit=1000")
+                .insert_header(bearer("alice"))
+                .to_request();
+            let page: TodoListResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(page.todos.len(), 3);
+            assert_eq!(page.total, 6);
+            let cursor = page.next_cursor.unwrap();
+
+            for query in [
+                "page=0".to_string(),
+                "limit=0".to_string(),
+                "after=not-a-cursor".to_string(),
+                format!("sort=title&after={}", cursor),
+                format!("page=2&after={}", cursor),
+            ] {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/api/v1/todos?{}", query))
+                    .insert_header(bearer("alice"))
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST, "{}", query);
+            }
+        }
+    }
+
+    // Other users' todos look exactly like missing ones
+    #[actix_web::test]
+    async fn todos_are_isolated_per_owner_test() {
+        for app_data in app_states() {
+            let app = api(&app_data).await;
+            let body = CreateTodoSchema {
+                title: "Test Todo".to_string(),
+                content: "Test Todo Content".to_string(),
+                ..Default::default()
+            };
+            let req = test::TestRequest::post()
+                .uri("/api/v1/todos")
+                .insert_header(bearer("alice"))
+                .set_json(&body)
+                .to_request();
+            let todo: SingleTodoResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(todo.data.todo.ownerId.as_deref(), Some("alice"));
+            let uri = format!("/api/v1/todos/{}", todo.data.todo.id.unwrap());
+
+            let req = test::TestRequest::get()
+                .uri("/api/v1/todos")
+                .insert_header(bearer("bob"))
+                .to_request();
+            let todos: TodoListResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(todos.results, 0);
+            assert!(todos.todos.is_empty());
+
+            let update = UpdateTodoSchema {
+                title: Some("Stolen".to_string()),
+                ..Default::default()
+            };
+            let requests = [
+                test::TestRequest::get().uri(&uri),
+                test::TestRequest::patch().uri(&uri).set_json(&update),
+                test::TestRequest::delete().uri(&uri),
+            ];
+            for req in requests {
+                let req = req.insert_header(bearer("bob")).to_request();
+                let resp = test::call_service(&app, req).await;
+                assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+            }
+
+            let req = test::TestRequest::get()
+                .uri("/api/v1/todos")
+                .insert_header(bearer("alice"))
+                .to_request();
+            let todos: TodoListResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(todos.results, 1);
+            assert_eq!(todos.todos[0].title, "Test Todo");
+        }
+    }
+
+    // ROUTES drives the CORS policy, so it must match what config registers.
+    #[actix_web::test]
+    async fn routes_match_config_test() {
+        let app_data = web::Data::new(AppState::init());
+        let app = test::init_service(
             App::new()
-                .app_data(app_data.clone())
-                .service(create_todo)
-                .service(update_todo_by_id),
+                .app_data(app_data)
+                .app_data(auth::test_support::authenticator())
+                .configure(config),
         )
         .await;
         let body = CreateTodoSchema {
             title: "Test Todo".to_string(),
             content: "Test Todo Content".to_string(),
+            ..Default::default()
         };
         let req = test::TestRequest::post()
-            .uri("/todos")
+            .uri("/api/v1/todos")
+            .insert_header(bearer("alice"))
             .set_json(&body)
             .to_request();
-        let resp = test::call_service(&mut app, req).await;
-        assert_eq!(resp.status(), http::StatusCode::OK);
-        let todo: SingleTodoResponse = test::read_body_json(resp).await;
+        let todo: SingleTodoResponse = test::call_and_read_body_json(&app, req).await;
         let id = todo.data.todo.id.unwrap();
-        let body = UpdateTodoSchema {
-          title: None,
-          content: None,
-          completed: Some(true),
-        };
-        let req = test::TestRequest::patch()
-            .uri(&format!("/todos/{}", id))
-            .set_json(&body)
+
+        let methods = ["GET", "POST", "PUT", "PATCH", "DELETE"];
+        let mut paths: Vec<&str> = ROUTES.iter().map(|(_, path)| *path).collect();
+        paths.dedup();
+        for path in paths {
+            let uri = path.replace("{id}", &id);
+            for method in methods {
+                let registered = ROUTES.contains(&(method, path));
+                let req = test::TestRequest::default()
+                    .method(method.parse().unwrap())
+                    .uri(&uri)
+                    .insert_header(bearer("alice"))
+                    .set_json(serde_json::json!({}))
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                // Handlers answer 404 with a JSON body, unmatched paths with none;
+                // a known path with an unregistered method gets 405
+                let status = resp.status();
+                let body = test::read_body(resp).await;
+                let matched = status != http::StatusCode::METHOD_NOT_ALLOWED
+                    && (status != http::StatusCode::NOT_FOUND || !body.is_empty());
+                assert_eq!(matched, registered, "{} {}", method, path);
+            }
+        }
+    }
+
+    // Only the health check is reachable without a bearer token
+    #[actix_web::test]
+    async fn requires_authentication_test() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::init()))
+                .app_data(auth::test_support::authenticator())
+                .configure(config),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/api/v1/health-check")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+
+        let req = test::TestRequest::get().uri("/api/v1/todos").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+
+        let req = test::TestRequest::get()
+            .uri("/api/v1/todos")
+            .insert_header(bearer("alice"))
             .to_request();
-        let resp = test::call_service(&mut app, req).await;
+        let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), http::StatusCode::OK);
     }
 }
@@ -0,0 +1,386 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::prelude::*;
+use uuid::Uuid;
+
+use crate::{
+    auth::Principal,
+    handler::{create_todo_response, todo_list_response},
+    model::{
+        AppState, CreateListSchema, CreateTodoSchema, DeleteListOptions, DeleteMode, List,
+        QueryOptions, Todo, UpdateListSchema,
+    },
+    repository::{RepositoryError, TodoFilter, TodoQuery},
+    response::{error_response, GenericResponse, ListData, ListListResponse, SingleListResponse},
+    subtask, trash,
+};
+
+pub const NOT_FOUND: &str = "List not found.";
+
+const MAX_NAME_LENGTH: usize = 100;
+const MAX_DESCRIPTION_LENGTH: usize = 1000;
+
+fn normalize_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(format!(
+            "List names must be 1-{} characters.",
+            MAX_NAME_LENGTH
+        ));
+    }
+    Ok(name.to_string())
+}
+
+fn check_description(description: &str) -> Result<(), String> {
+    if description.chars().count() > MAX_DESCRIPTION_LENGTH {
+        return Err(format!(
+            "List descriptions must be at most {} characters.",
+            MAX_DESCRIPTION_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+fn list_response(list: List) -> HttpResponse {
+    HttpResponse::Ok().json(SingleListResponse {
+        status: "success".to_string(),
+        data: ListData { list },
+    })
+}
+
+// Every todo of the owner filed under the list
+fn list_todos(app_state: &AppState, owner: &str, id: &str) -> Result<Vec<Todo>, RepositoryError> {
+    let query = TodoQuery {
+        filter: TodoFilter {
+            list_id: Some(id.to_string()),
+            ..TodoFilter::default()
+        },
+        ..TodoQuery::default()
+    };
+    Ok(app_state.todo_db.list(owner, &query)?.items)
+}
+
+// The caller's lists in creation order
+pub async fn get_lists(
+    app_state: web::Data<AppState>,
+    principal: Principal,
+) -> Result<HttpResponse, RepositoryError> {
+    let lists = app_state.list_db.list_lists(&principal.subject)?;
+    let response_json = &ListListResponse {
+        status: "success".to_string(),
+        results: lists.len(),
+        lists,
+    };
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+pub async fn create_list(
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    payload: web::Json<CreateListSchema>,
+) -> Result<HttpResponse, RepositoryError> {
+    let payload = payload.into_inner();
+    let name = match normalize_name(&payload.name) {
+        Ok(name) => name,
+        Err(message) => return Ok(error_response(HttpResponse::BadRequest(), &message)),
+    };
+    if let Err(message) = check_description(&payload.description) {
+        return Ok(error_response(HttpResponse::BadRequest(), &message));
+    }
+    let now = Utc::now();
+    let list = app_state.list_db.create_list(List {
+        id: Uuid::new_v4().to_string(),
+        ownerId: principal.subject,
+        name,
+        description: payload.description,
+        createdAt: now,
+        updatedAt: now,
+    })?;
+    Ok(list_response(list))
+}
+
+pub async fn get_list(
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    path: web::Path<String>,
+) -> Result<HttpResponse, RepositoryError> {
+    match app_state.list_db.get_list(&principal.subject, &path)? {
+        Some(list) => Ok(list_response(list)),
+        None => Ok(error_response(HttpResponse::NotFound(), NOT_FOUND)),
+    }
+}
+
+pub async fn update_list(
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    path: web::Path<String>,
+    payload: web::Json<UpdateListSchema>,
+) -> Result<HttpResponse, RepositoryError> {
+    let payload = payload.into_inner();
+    let name = match payload.name.as_deref().map(normalize_name).transpose() {
+        Ok(name) => name,
+        Err(message) => return Ok(error_response(HttpResponse::BadRequest(), &message)),
+    };
+    if let Some(Err(message)) = payload.description.as_deref().map(check_description) {
+        return Ok(error_response(HttpResponse::BadRequest(), &message));
+    }
+    let list = app_state
+        .list_db
+        .update_list(&principal.subject, &path, &mut |list| {
+            if let Some(name) = &name {
+                list.name = name.clone();
+            }
+            if let Some(description) = &payload.description {
+                list.description = description.clone();
+            }
+            list.updatedAt = Utc::now();
+        })?;
+    match list {
+        Some(list) => Ok(list_response(list)),
+        None => Ok(error_response(HttpResponse::NotFound(), NOT_FOUND)),
+    }
+}
+
+// Delete a list. A list that still has todos is only deleted when `todos`
+// says what happens to them.
+pub async fn delete_list(
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    path: web::Path<String>,
+    query: web::Query<DeleteListOptions>,
+) -> Result<HttpResponse, RepositoryError> {
+    let id = path.into_inner();
+    let owner = &principal.subject;
+    if app_state.list_db.get_list(owner, &id)?.is_none() {
+        return Ok(error_response(HttpResponse::NotFound(), NOT_FOUND));
+    }
+
+    let todos = list_todos(&app_state, owner, &id)?;
+    match query.todos.unwrap_or_default() {
+        DeleteMode::Reject => {
+            if !todos.is_empty() {
+                return Ok(error_response(
+                    HttpResponse::Conflict(),
+                    "List has todos; pass todos=orphan or todos=cascade.",
+                ));
+            }
+        }
+        DeleteMode::Orphan => {
+            let now = Utc::now();
+            let todo_ids: Vec<String> = todos.into_iter().filter_map(|todo| todo.id).collect();
+            app_state
+                .todo_db
+                .update_many(owner, &todo_ids, &mut |todo| {
+                    if todo.listId.as_ref() == Some(&id) {
+                        todo.listId = None;
+                        todo.touch(now);
+                    }
+                })?;
+        }
+        DeleteMode::Cascade => {
+            // Subtasks go to the trash with their parent even when filed
+            // elsewhere, so none is left pointing at a deleted todo
+            let mut todo_ids = Vec::new();
+            for todo in todos {
+                let todo_id = todo.id.unwrap_or_default();
+                let descendants = subtask::descendants(&app_state, owner, &todo_id)?;
+                todo_ids.extend(descendants.into_iter().filter_map(|todo| todo.id));
+                todo_ids.push(todo_id);
+            }
+            trash::trash_all(&app_state, owner, &todo_ids, Utc::now())?;
+        }
+    }
+
+    if app_state.list_db.delete_list(owner, &id)? {
+        let response_json = &GenericResponse {
+            status: "success".to_string(),
+            message: "List deleted successfully.".to_string(),
+        };
+        Ok(HttpResponse::Ok().json(response_json))
+    } else {
+        Ok(error_response(HttpResponse::NotFound(), NOT_FOUND))
+    }
+}
+
+// The list's todos, filtered, sorted and paged like /todos
+pub async fn get_list_todos(
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    path: web::Path<String>,
+    query: web::Query<QueryOptions>,
+) -> Result<HttpResponse, RepositoryError> {
+    let id = path.into_inner();
+    if app_state
+        .list_db
+        .get_list(&principal.subject, &id)?
+        .is_none()
+    {
+        return Ok(error_response(HttpResponse::NotFound(), NOT_FOUND));
+    }
+    todo_list_response(&req, &app_state, &principal.subject, &query, |filter| {
+        filter.list_id = Some(id)
+    })
+}
+
+// Create a todo in the list, whatever `listId` the body has
+pub async fn create_list_todo(
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    path: web::Path<String>,
+    payload: web::Json<CreateTodoSchema>,
+) -> Result<HttpResponse, RepositoryError> {
+    let id = path.into_inner();
+    if app_state
+        .list_db
+        .get_list(&principal.subject, &id)?
+        .is_none()
+    {
+        return Ok(error_response(HttpResponse::NotFound(), NOT_FOUND));
+    }
+    let mut payload = payload.into_inner();
+    payload.listId = Some(id);
+    create_todo_response(&app_state, &principal.subject, payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::test_support::bearer;
+    use crate::response::{SingleTodoResponse, TodoListResponse};
+    use crate::test_support::{api, app_states, request};
+    use actix_web::{http, test};
+    use serde_json::json;
+
+    #[actix_web::test]
+    async fn lists_test() {
+        for app_data in app_states() {
+            let app = api(&app_data).await;
+            let get = |uri: &str| {
+                test::TestRequest::get()
+                    .uri(uri)
+                    .insert_header(bearer("alice"))
+                    .to_request()
+            };
+
+            let mut ids = Vec::new();
+            for name in ["Work", " Home "] {
+                let req = request(http::Method::POST, "/api/v1/lists", json!({ "name": name }));
+                let created: SingleListResponse = test::call_and_read_body_json(&app, req).await;
+                ids.push(created.data.list.id);
+            }
+            let (work, home) = (&ids[0], &ids[1]);
+            let req = request(http::Method::POST, "/api/v1/lists", json!({ "name": " " }));
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+
+            let lists: ListListResponse =
+                test::call_and_read_body_json(&app, get("/api/v1/lists")).await;
+            let names: Vec<&str> = lists.lists.iter().map(|list| list.name.as_str()).collect();
+            assert_eq!(names, ["Work", "Home"]);
+
+            let req = request(
+                http::Method::PATCH,
+                &format!("/api/v1/lists/{}", home),
+                json!({ "description": "Chores" }),
+            );
+            let updated: SingleListResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(updated.data.list.name, "Home");
+            assert_eq!(updated.data.list.description, "Chores");
+
+            // Todos go into a list through the nested route or `listId`
+            let mut todo_ids = Vec::new();
+            for (uri, body) in [
+                (
+                    format!("/api/v1/lists/{}/todos", work),
+                    json!({ "title": "Report", "content": "" }),
+                ),
+                (
+                    "/api/v1/todos".to_string(),
+                    json!({ "title": "Slides", "content": "", "listId": work }),
+                ),
+                (
+                    "/api/v1/todos".to_string(),
+                    json!({ "title": "Groceries", "content": "" }),
+                ),
+            ] {
+                let req = request(http::Method::POST, &uri, body);
+                let created: SingleTodoResponse = test::call_and_read_body_json(&app, req).await;
+                todo_ids.push(created.data.todo.id.unwrap());
+            }
+            let req = request(
+                http::Method::POST,
+                "/api/v1/todos",
+                json!({ "title": "Lost", "content": "", "listId": "missing" }),
+            );
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+
+            let titles = |list: TodoListResponse| -> Vec<String> {
+                list.todos.into_iter().map(|todo| todo.title).collect()
+            };
+            let page: TodoListResponse = test::call_and_read_body_json(
+                &app,
+                get(&format!("/api/v1/lists/{}/todos?limit=1", work)),
+            )
+            .await;
+            assert_eq!(page.total, 2);
+            assert!(page.has_more);
+            assert_eq!(titles(page), ["Report"]);
+
+            // Moving a todo to another list
+            let req = request(
+                http::Method::PATCH,
+                &format!("/api/v1/todos/{}", todo_ids[1]),
+                json!({ "listId": home }),
+            );
+            let moved: SingleTodoResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(moved.data.todo.listId.as_ref(), Some(home));
+            let req = request(
+                http::Method::PATCH,
+                &format!("/api/v1/todos/{}", todo_ids[1]),
+                json!({ "listId": "missing" }),
+            );
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+            let in_home: TodoListResponse =
+                test::call_and_read_body_json(&app, get(&format!("/api/v1/lists/{}/todos", home)))
+                    .await;
+            assert_eq!(titles(in_home), ["Slides"]);
+
+            // Other users see neither the lists nor their todos
+            let req = test::TestRequest::get()
+                .uri(&format!("/api/v1/lists/{}/todos", work))
+                .insert_header(bearer("bob"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+
+            let delete = |uri: String| {
+                test::TestRequest::delete()
+                    .uri(&uri)
+                    .insert_header(bearer("alice"))
+                    .to_request()
+            };
+            let resp = test::call_service(&app, delete(format!("/api/v1/lists/{}", work))).await;
+            assert_eq!(resp.status(), http::StatusCode::CONFLICT);
+            let resp =
+                test::call_service(&app, delete(format!("/api/v1/lists/{}?todos=orphan", work)))
+                    .await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            let resp = test::call_service(
+                &app,
+                delete(format!("/api/v1/lists/{}?todos=cascade", home)),
+            )
+            .await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+
+            let lists: ListListResponse =
+                test::call_and_read_body_json(&app, get("/api/v1/lists")).await;
+            assert_eq!(lists.results, 0);
+            let all: TodoListResponse =
+                test::call_and_read_body_json(&app, get("/api/v1/todos")).await;
+            assert!(all.todos.iter().all(|todo| todo.listId.is_none()));
+            assert_eq!(titles(all), ["Report", "Groceries"]);
+        }
+    }
+}
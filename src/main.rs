@@ -1,49 +1,80 @@
+mod account;
+mod api_key;
+mod auth;
+mod conditional;
+mod config;
+mod cors;
 mod handler;
+mod list;
 mod model;
+mod pagination;
+mod patch;
+mod recurrence;
+mod reminder;
+mod repository;
 mod response;
+mod search;
+mod subtask;
+mod tag;
+#[cfg(test)]
+mod test_support;
+mod trash;
 
-use actix_cors::Cors;
 use actix_web::middleware::Logger;
-use actix_web::{http::header, App, HttpServer, web};
-use serde::Serialize;
+use actix_web::{App, HttpServer, web};
+use auth::Authenticator;
+use config::Config;
 use model::AppState;
-
-#[derive(Serialize)]
-pub struct GenericResponse {
-    pub status: String,
-    pub message: String,
-}
+use std::io;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    if std::env::var_os("RUST_LOG").is_none() {
-        std::env::set_var("RUST_LOG", "actix_web=info")
-    }
-    env_logger::init();
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    env_logger::Builder::new()
+        .parse_filters(&config.log.filter)
+        .init();
 
-    let todo_db = AppState::init();
+    let authenticator = match Authenticator::from_config(&config.auth) {
+        Ok(authenticator) => web::Data::new(authenticator),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    let todo_db = AppState::open(&config.storage).map_err(io::Error::other)?;
     let app_data = web::Data::new(todo_db);
+    if config.reminders.enabled {
+        let notifier = reminder::notifier(&config.reminders)?;
+        reminder::Scheduler::new(&app_data, &config.reminders, notifier)
+            .start(std::time::Duration::from_secs(config.reminders.interval));
+    }
+    trash::Purger::new(&app_data, &config.trash)
+        .start(std::time::Duration::from_secs(config.trash.purge_interval));
+    let config_data = web::Data::new(config.clone());
 
     println!("Starting Actix Server Boilerplate...");
 
-    HttpServer::new(move || {
-        let cors = Cors::default()
-            .allowed_origin("http://localhost:8000")
-            .allowed_origin("http://localhost:8000/")
-            .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
-            .allowed_headers(vec![
-                header::CONTENT_TYPE,
-                header::AUTHORIZATION,
-                header::ACCEPT,
-            ])
-            .supports_credentials();
+    let mut server = HttpServer::new(move || {
+        let cors = cors::cors(&config_data.cors);
         App::new()
             .app_data(app_data.clone())
+            .app_data(config_data.clone())
+            .app_data(authenticator.clone())
             .configure(handler::config)
             .wrap(cors)
             .wrap(Logger::default())
-    })
-    .bind(("127.0.0.1", 8000))?
-    .run()
-    .await
-}
\ No newline at end of file
+    });
+    if let Some(workers) = config.server.workers {
+        server = server.workers(workers);
+    }
+    server
+        .bind((config.server.host.as_str(), config.server.port))?
+        .run()
+        .await
+}
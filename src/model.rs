@@ -1,10 +1,19 @@
 use chrono::prelude::*;
+use chrono::TimeDelta;
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+
+use crate::config::{PaginationConfig, StorageBackend, StorageConfig};
+use crate::pagination::decode_cursor;
+use crate::repository::{
+  ApiKeyRepository, ListRepository, MemoryStore, ReminderRepository, RepositoryError, SortKey, SqliteStore,
+  TodoFilter, TodoQuery, TodoRepository, UserRepository, WalStore,
+};
+use crate::search::SearchIndexedStore;
 // use std::fmt;
 
 #[allow(non_snake_case)]
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct Todo {
   pub id: Option<String>,
   pub title: String,
@@ -12,21 +21,123 @@ pub struct Todo {
   pub completed: Option<bool>,
   pub createdAt: Option<DateTime<Utc>>,
   pub updatedAt: Option<DateTime<Utc>>,
+  // Id of the user who created the todo
+  #[serde(default)]
+  pub ownerId: Option<String>,
+  // Lowercase labels, each at most once
+  #[serde(default)]
+  pub tags: Vec<String>,
+  #[serde(default)]
+  pub dueAt: Option<DateTime<Utc>>,
+  #[serde(default)]
+  pub priority: Option<Priority>,
+  // RFC 5545 RRULE such as "FREQ=WEEKLY;BYDAY=SA". The series starts at dueAt.
+  #[serde(default)]
+  pub recurrence: Option<String>,
+  // The todo this one is a subtask of
+  #[serde(default)]
+  pub parentId: Option<String>,
+  // The list (project) the todo is filed under
+  #[serde(default)]
+  pub listId: Option<String>,
+  // Goes up by one with every change and doubles as the todo's ETag
+  #[serde(default)]
+  pub version: u64,
+  // When the todo was moved to the trash; it is purged for good once the
+  // retention period has passed
+  #[serde(default)]
+  pub deletedAt: Option<DateTime<Utc>>,
+}
+
+impl Todo {
+  // Records a change made at `now`
+  pub fn touch(&mut self, now: DateTime<Utc>) {
+    self.updatedAt = Some(now);
+    self.version += 1;
+  }
+
+  // Past due and still open
+  pub fn is_overdue(&self, now: DateTime<Utc>) -> bool {
+    !self.completed.unwrap_or(false) && self.dueAt.is_some_and(|due_at| due_at < now)
+  }
+}
+
+// Lowest first, so sorting by priority descending puts urgent todos on top
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+  Low,
+  Normal,
+  High,
+  Urgent,
+}
+
+impl Priority {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Priority::Low => "low",
+      Priority::Normal => "normal",
+      Priority::High => "high",
+      Priority::Urgent => "urgent",
+    }
+  }
+
+  pub fn parse(priority: &str) -> Option<Priority> {
+    match priority {
+      "low" => Some(Priority::Low),
+      "normal" => Some(Priority::Normal),
+      "high" => Some(Priority::High),
+      "urgent" => Some(Priority::Urgent),
+      _ => None,
+    }
+  }
 }
 
 #[allow(non_snake_case)]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct CreateTodoSchema {
   pub title: String,
   pub content: String,
+  #[serde(default)]
+  pub tags: Vec<String>,
+  pub dueAt: Option<DateTime<Utc>>,
+  pub priority: Option<Priority>,
+  pub recurrence: Option<String>,
+  pub parentId: Option<String>,
+  pub listId: Option<String>,
 }
 
 #[allow(non_snake_case)]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct UpdateTodoSchema {
   pub title: Option<String>,
   pub content: Option<String>,
   pub completed: Option<bool>,
+  pub tags: Option<Vec<String>>,
+  pub dueAt: Option<DateTime<Utc>>,
+  pub priority: Option<Priority>,
+  pub recurrence: Option<String>,
+  pub parentId: Option<String>,
+  pub listId: Option<String>,
+}
+
+// A PUT body, which replaces every field a client can set. Leaving out an
+// optional field clears it.
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReplaceTodoSchema {
+  // Must match the id in the path when given
+  pub id: Option<String>,
+  pub title: String,
+  pub content: String,
+  pub completed: bool,
+  #[serde(default)]
+  pub tags: Vec<String>,
+  pub dueAt: Option<DateTime<Utc>>,
+  pub priority: Option<Priority>,
+  pub recurrence: Option<String>,
+  pub parentId: Option<String>,
+  pub listId: Option<String>,
 }
 
 impl From<CreateTodoSchema> for Todo {
@@ -39,24 +150,492 @@ impl From<CreateTodoSchema> for Todo {
       completed: None,
       createdAt: Some(now),
       updatedAt: Some(now),
+      ownerId: None,
+      tags: todo.tags,
+      dueAt: todo.dueAt,
+      priority: todo.priority,
+      recurrence: todo.recurrence,
+      parentId: todo.parentId,
+      listId: todo.listId,
+      version: 1,
+      deletedAt: None,
+    }
+  }
+}
+
+impl From<ReplaceTodoSchema> for Todo {
+  fn from(todo: ReplaceTodoSchema) -> Self {
+    let now = Utc::now();
+    Todo {
+      id: todo.id,
+      title: todo.title,
+      content: todo.content,
+      completed: Some(todo.completed),
+      createdAt: Some(now),
+      updatedAt: Some(now),
+      ownerId: None,
+      tags: todo.tags,
+      dueAt: todo.dueAt,
+      priority: todo.priority,
+      recurrence: todo.recurrence,
+      parentId: todo.parentId,
+      listId: todo.listId,
+      version: 1,
+      deletedAt: None,
+    }
+  }
+}
+
+// How many of a todo's direct subtasks there are and how many are done
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Subtasks {
+  pub total: usize,
+  pub completed: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TagCount {
+  pub name: String,
+  // Todos using the tag
+  pub count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RenameTagSchema {
+  pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MergeTagSchema {
+  // The tag the merged one is replaced with
+  pub into: String,
+}
+
+// A named group of todos, such as a project
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct List {
+  pub id: String,
+  pub ownerId: String,
+  pub name: String,
+  #[serde(default)]
+  pub description: String,
+  pub createdAt: DateTime<Utc>,
+  pub updatedAt: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateListSchema {
+  pub name: String,
+  #[serde(default)]
+  pub description: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateListSchema {
+  pub name: Option<String>,
+  pub description: Option<String>,
+}
+
+// A registered account. Only ever serialized for storage; API responses use
+// `UserProfile` so the password hash never leaves the server.
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+  pub id: String,
+  pub username: String,
+  pub passwordHash: String,
+  pub createdAt: DateTime<Utc>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserProfile {
+  pub id: String,
+  pub username: String,
+  pub createdAt: DateTime<Utc>,
+}
+
+impl From<User> for UserProfile {
+  fn from(user: User) -> Self {
+    UserProfile {
+      id: user.id,
+      username: user.username,
+      createdAt: user.createdAt,
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RegisterUserSchema {
+  pub username: String,
+  pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoginUserSchema {
+  pub username: String,
+  pub password: String,
+}
+
+// What an API key may do. User tokens are not limited by scopes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+  #[serde(rename = "todos:read")]
+  TodosRead,
+  #[serde(rename = "todos:write")]
+  TodosWrite,
+}
+
+impl Scope {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Scope::TodosRead => "todos:read",
+      Scope::TodosWrite => "todos:write",
     }
   }
 }
 
+// A key for scripts and bots. Only the SHA-256 hash of the key is stored;
+// API responses use `ApiKeyInfo`.
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKey {
+  pub id: String,
+  pub ownerId: String,
+  pub name: String,
+  // The first characters of the key, to tell keys apart
+  pub prefix: String,
+  pub keyHash: String,
+  pub scopes: Vec<Scope>,
+  pub createdAt: DateTime<Utc>,
+  pub expiresAt: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+  pub fn is_expired(&self) -> bool {
+    self.expiresAt.is_some_and(|expires_at| expires_at <= Utc::now())
+  }
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKeyInfo {
+  pub id: String,
+  pub name: String,
+  pub prefix: String,
+  pub scopes: Vec<Scope>,
+  pub createdAt: DateTime<Utc>,
+  pub expiresAt: Option<DateTime<Utc>>,
+}
+
+impl From<ApiKey> for ApiKeyInfo {
+  fn from(key: ApiKey) -> Self {
+    ApiKeyInfo {
+      id: key.id,
+      name: key.name,
+      prefix: key.prefix,
+      scopes: key.scopes,
+      createdAt: key.createdAt,
+      expiresAt: key.expiresAt,
+    }
+  }
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateApiKeySchema {
+  pub name: String,
+  pub scopes: Vec<Scope>,
+  pub expiresAt: Option<DateTime<Utc>>,
+}
+
+// A reminder that went out. Reminders are told apart by their todo, the due
+// time they were for and how long before it they fire, so moving a due date
+// arms them again.
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SentReminder {
+  pub todoId: String,
+  pub dueAt: DateTime<Utc>,
+  // Minutes before `dueAt`
+  pub offsetMinutes: u64,
+  pub sentAt: DateTime<Utc>,
+}
+
+impl SentReminder {
+  pub fn key(todo_id: &str, due_at: DateTime<Utc>, offset_minutes: u64) -> String {
+    format!("{}/{}/{}", todo_id, due_at.to_rfc3339_opts(SecondsFormat::Nanos, true), offset_minutes)
+  }
+}
+
 pub struct AppState {
-  pub todo_db: Arc<Mutex<Vec<Todo>>>,
+  // Writes go through the search index so it stays in step with the store
+  pub todo_db: Arc<dyn TodoRepository>,
+  pub search: Arc<SearchIndexedStore>,
+  pub user_db: Arc<dyn UserRepository>,
+  pub api_key_db: Arc<dyn ApiKeyRepository>,
+  pub reminder_db: Arc<dyn ReminderRepository>,
+  pub list_db: Arc<dyn ListRepository>,
 }
 
 impl AppState {
   pub fn init() -> AppState {
+    AppState::new(Arc::new(MemoryStore::new()))
+  }
+
+  // Todos, lists, users, API keys and sent reminders live in the same store
+  pub fn new<S>(store: Arc<S>) -> AppState
+  where
+    S: TodoRepository + ListRepository + UserRepository + ApiKeyRepository + ReminderRepository + 'static,
+  {
+    let search = Arc::new(SearchIndexedStore::new(store.clone()));
     AppState {
-      todo_db: Arc::new(Mutex::new(Vec::new())),
+      todo_db: search.clone(),
+      search,
+      user_db: store.clone(),
+      api_key_db: store.clone(),
+      reminder_db: store.clone(),
+      list_db: store,
     }
   }
+
+  // Opens the configured store. Persistent stores load their existing data,
+  // so this is where a restart picks up where the last run left off.
+  pub fn open(config: &StorageConfig) -> Result<AppState, RepositoryError> {
+    match config.backend {
+      StorageBackend::Memory => Ok(AppState::init()),
+      StorageBackend::Wal => {
+        let store = WalStore::open(&config.wal_dir, config.wal_compact_every)?;
+        adopt_legacy_todos(config, |owner| store.adopt_unowned_todos(owner))?;
+        Ok(AppState::new(Arc::new(store)))
+      }
+      StorageBackend::Sqlite => {
+        let store = SqliteStore::open(&config.sqlite_path)?;
+        adopt_legacy_todos(config, |owner| store.adopt_unowned_todos(owner))?;
+        Ok(AppState::new(Arc::new(store)))
+      }
+      #[cfg(feature = "kv")]
+      StorageBackend::Kv => {
+        let store = crate::repository::KvStore::open(&config.kv_path)?;
+        adopt_legacy_todos(config, |owner| store.adopt_unowned_todos(owner))?;
+        Ok(AppState::new(Arc::new(store)))
+      }
+      #[cfg(not(feature = "kv"))]
+      StorageBackend::Kv => Err(RepositoryError::Storage(
+        "the kv store needs a build with the kv feature".to_string(),
+      )),
+    }
+  }
+}
+
+// Hands the todos saved before todos had owners to `storage.legacy_owner`
+fn adopt_legacy_todos(
+  config: &StorageConfig,
+  adopt: impl FnOnce(&str) -> Result<usize, RepositoryError>,
+) -> Result<(), RepositoryError> {
+  if let Some(owner) = &config.legacy_owner {
+    let adopted = adopt(owner)?;
+    if adopted > 0 {
+      log::info!("Gave {} todos without an owner to {}", adopted, owner);
+    }
+  }
+  Ok(())
 }
 
 #[derive(Debug, Deserialize)]
 pub struct QueryOptions {
   pub limit: Option<usize>,
   pub page: Option<usize>,
+  // Opaque cursors from `next_cursor` and `prev_cursor`
+  pub after: Option<String>,
+  pub before: Option<String>,
+  pub completed: Option<bool>,
+  pub q: Option<String>,
+  pub created_after: Option<DateTime<Utc>>,
+  pub created_before: Option<DateTime<Utc>>,
+  pub updated_since: Option<DateTime<Utc>>,
+  // Comma separated sort keys, each optionally prefixed with '-'
+  pub sort: Option<String>,
+  pub order: Option<SortOrder>,
+  // Comma separated tags; `tag_mode` says whether a todo needs any or all
+  pub tag: Option<String>,
+  pub tag_mode: Option<TagMode>,
+  // Due date views. `due_within` takes a span such as "12h", "7d" or "2w";
+  // `due_today` uses the `tz` offset (e.g. "+02:00"), UTC by default.
+  pub overdue: Option<bool>,
+  pub due_today: Option<bool>,
+  pub due_within: Option<String>,
+  pub tz: Option<String>,
+}
+
+// Parses spans such as "30m", "12h", "7d" or "2w" into a positive duration
+fn parse_span(span: &str) -> Result<TimeDelta, String> {
+  let invalid = || format!("Invalid duration {:?}; use e.g. 12h, 7d or 2w.", span);
+  let split = span.len().saturating_sub(1);
+  let count: i64 = span.get(..split).and_then(|count| count.parse().ok()).ok_or_else(invalid)?;
+  let delta = match span.get(split..) {
+    Some("m") => TimeDelta::try_minutes(count),
+    Some("h") => TimeDelta::try_hours(count),
+    Some("d") => TimeDelta::try_days(count),
+    Some("w") => TimeDelta::try_weeks(count),
+    _ => None,
+  };
+  delta.filter(|delta| *delta > TimeDelta::zero()).ok_or_else(invalid)
+}
+
+// Parses a UTC offset such as "+02:00". A '+' sent unencoded in a query string
+// arrives as a space, so a bare "02:00" counts as positive.
+fn parse_offset(tz: &str) -> Result<FixedOffset, String> {
+  let tz = tz.trim();
+  let tz = if tz.starts_with(|c: char| c.is_ascii_digit()) {
+    format!("+{}", tz)
+  } else {
+    tz.to_string()
+  };
+  tz.parse().map_err(|_| format!("Invalid tz {:?}; use an offset such as +02:00.", tz))
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TagMode {
+  Any,
+  All,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchOptions {
+  pub q: Option<String>,
+  pub limit: Option<usize>,
+}
+
+// What deleting a todo does to its subtasks, or deleting a list to its todos
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DeleteMode {
+  // Refuse while there are any
+  #[default]
+  Reject,
+  // Keep them, as top-level todos or outside any list
+  Orphan,
+  // Delete them too
+  Cascade,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteOptions {
+  pub children: Option<DeleteMode>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteListOptions {
+  pub todos: Option<DeleteMode>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OccurrenceOptions {
+  pub from: Option<DateTime<Utc>>,
+  // Exclusive
+  pub to: Option<DateTime<Utc>>,
+  pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+  Asc,
+  Desc,
+}
+
+impl QueryOptions {
+  // Whether the results move with the clock as well as with the todos
+  pub fn is_time_relative(&self) -> bool {
+    self.overdue.is_some() || self.due_today.is_some() || self.due_within.is_some()
+  }
+
+  pub fn filter(&self) -> Result<TodoFilter, String> {
+    let now = Utc::now();
+    let mut due_from = None;
+    let mut due_until = None;
+    if self.due_today == Some(true) {
+      let offset = match &self.tz {
+        Some(tz) => parse_offset(tz)?,
+        None => Utc.fix(),
+      };
+      let today = now.with_timezone(&offset).date_naive();
+      let midnight = |day: NaiveDate| day.and_time(NaiveTime::MIN).and_local_timezone(offset).single();
+      due_from = midnight(today).map(|start| start.to_utc());
+      due_until = today.succ_opt().and_then(midnight).map(|end| end.to_utc());
+    }
+    if let Some(span) = &self.due_within {
+      let until = now.checked_add_signed(parse_span(span)?).unwrap_or(DateTime::<Utc>::MAX_UTC);
+      due_from = due_from.max(Some(now));
+      due_until = Some(due_until.map_or(until, |end| end.min(until)));
+    }
+
+    Ok(TodoFilter {
+      completed: self.completed,
+      text: self.q.clone().filter(|q| !q.is_empty()),
+      created_after: self.created_after,
+      created_before: self.created_before,
+      updated_since: self.updated_since,
+      tags: self
+        .tag
+        .iter()
+        .flat_map(|tags| tags.split(','))
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect(),
+      all_tags: self.tag_mode == Some(TagMode::All),
+      due_from,
+      due_until,
+      overdue: self.overdue,
+      now: Some(now),
+      parent_id: None,
+      list_id: None,
+      deleted: false,
+    })
+  }
+
+  // `order` applies to the keys without their own direction
+  pub fn sort_keys(&self) -> Result<Vec<SortKey>, String> {
+    let descending = self.order == Some(SortOrder::Desc);
+    match &self.sort {
+      Some(sort) => SortKey::parse_list(sort, descending),
+      None => Ok(Vec::new()),
+    }
+  }
+
+  // The listing these options ask for. Limits above the configured maximum
+  // are clamped; a zero limit or page, or mixing cursors with each other or
+  // with `page`, is an error.
+  pub fn todo_query(&self, pagination: &PaginationConfig) -> Result<TodoQuery, String> {
+    let limit = self.limit.unwrap_or(pagination.default_page_size);
+    if limit == 0 {
+      return Err("limit must be at least 1.".to_string());
+    }
+    let limit = limit.min(pagination.max_page_size);
+    if self.page == Some(0) {
+      return Err("page must be at least 1.".to_string());
+    }
+    let cursors = usize::from(self.after.is_some()) + usize::from(self.before.is_some());
+    if cursors + usize::from(self.page.is_some()) > 1 {
+      return Err("Use only one of after, before and page.".to_string());
+    }
+
+    let mut query = TodoQuery {
+      filter: self.filter()?,
+      sort: self.sort_keys()?,
+      offset: (self.page.unwrap_or(1) - 1).saturating_mul(limit),
+      limit,
+      ..TodoQuery::default()
+    };
+    let sort = query.sort_keys().to_vec();
+    let decode = |cursor: &Option<String>| {
+      cursor.as_deref().map(|cursor| decode_cursor(cursor, &sort)).transpose()
+    };
+    query.after = decode(&self.after)?;
+    query.before = decode(&self.before)?;
+    Ok(query)
+  }
 }
\ No newline at end of file
@@ -0,0 +1,246 @@
+use actix_web::HttpRequest;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::model::Todo;
+use crate::repository::{SortField, SortKey};
+
+// What a cursor holds: the sort it was made for, and the id and sort values
+// of the todo it points at. Clients only ever see it base64 encoded.
+#[derive(Serialize, Deserialize)]
+struct CursorData {
+    sort: String,
+    id: String,
+    values: Vec<Value>,
+}
+
+fn sort_spec(sort: &[SortKey]) -> String {
+    let keys: Vec<String> = sort
+        .iter()
+        .map(|key| {
+            let direction = if key.descending { "-" } else { "" };
+            format!("{}{}", direction, key.name())
+        })
+        .collect();
+    keys.join(",")
+}
+
+// A cursor pointing at `todo` in a listing sorted by `sort`
+pub fn encode_cursor(todo: &Todo, sort: &[SortKey]) -> String {
+    let values = sort
+        .iter()
+        .map(|key| match key.field {
+            SortField::CreatedAt => serde_json::json!(todo.createdAt),
+            SortField::UpdatedAt => serde_json::json!(todo.updatedAt),
+            SortField::Title => serde_json::json!(todo.title),
+            SortField::Completed => serde_json::json!(todo.completed.unwrap_or(false)),
+            SortField::DueAt => serde_json::json!(todo.dueAt),
+            SortField::Priority => serde_json::json!(todo.priority),
+        })
+        .collect();
+    let data = CursorData {
+        sort: sort_spec(sort),
+        id: todo.id.clone().unwrap_or_default(),
+        values,
+    };
+    let json = serde_json::to_vec(&data).unwrap_or_default();
+    URL_SAFE_NO_PAD.encode(json)
+}
+
+// Turns a cursor back into a todo carrying just the id and sort values, for
+// `TodoQuery::after` and `before`. Fails if the cursor is malformed or was made
+// for a different sort.
+pub fn decode_cursor(cursor: &str, sort: &[SortKey]) -> Result<Todo, String> {
+    fn invalid() -> String {
+        "Invalid cursor.".to_string()
+    }
+    fn parse<T: DeserializeOwned>(value: Value) -> Result<T, String> {
+        serde_json::from_value(value).map_err(|_| invalid())
+    }
+
+    let json = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+    let data: CursorData = serde_json::from_slice(&json).map_err(|_| invalid())?;
+    if data.sort != sort_spec(sort) || data.values.len() != sort.len() {
+        return Err("Cursor was made for a different sort order.".to_string());
+    }
+
+    let mut todo = Todo {
+        id: Some(data.id),
+        ..Todo::default()
+    };
+    for (key, value) in sort.iter().zip(data.values) {
+        match key.field {
+            SortField::CreatedAt => todo.createdAt = parse(value)?,
+            SortField::UpdatedAt => todo.updatedAt = parse(value)?,
+            SortField::Title => todo.title = parse(value)?,
+            SortField::Completed => todo.completed = Some(parse(value)?),
+            SortField::DueAt => todo.dueAt = parse(value)?,
+            SortField::Priority => todo.priority = parse(value)?,
+        }
+    }
+    Ok(todo)
+}
+
+// An RFC 8288 Link header value. Each link is the request URL with its paging
+// parameters replaced by `params`.
+pub fn link_header(req: &HttpRequest, links: &[(&str, Option<(&str, &str)>)]) -> String {
+    let kept: Vec<&str> = req
+        .query_string()
+        .split('&')
+        .filter(|pair| {
+            let name = pair.split('=').next().unwrap_or_default();
+            !pair.is_empty() && !["after", "before", "page"].contains(&name)
+        })
+        .collect();
+    links
+        .iter()
+        .map(|(rel, param)| {
+            let mut query = kept.clone();
+            let param = param.map(|(name, value)| format!("{}={}", name, value));
+            query.extend(param.as_deref());
+            let mut url = req.path().to_string();
+            if !query.is_empty() {
+                url.push('?');
+                url.push_str(&query.join("&"));
+            }
+            format!("<{}>; rel=\"{}\"", url, rel)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::model::Priority;
+
+    fn sorted_by(spec: &str) -> Vec<SortKey> {
+        SortKey::parse_list(spec, false).unwrap()
+    }
+
+    #[test]
+    fn cursor_round_trip_test() {
+        let todo = Todo {
+            id: Some("todo-1".to_string()),
+            title: "Buy milk".to_string(),
+            content: "Not part of any cursor".to_string(),
+            completed: Some(true),
+            createdAt: Some(Utc.with_ymd_and_hms(2030, 1, 1, 9, 0, 0).unwrap()),
+            updatedAt: Some(Utc.with_ymd_and_hms(2030, 1, 2, 9, 0, 0).unwrap()),
+            dueAt: Some(Utc.with_ymd_and_hms(2030, 1, 3, 9, 0, 0).unwrap()),
+            priority: Some(Priority::High),
+            ..Todo::default()
+        };
+        let cases = [
+            (
+                "createdAt",
+                Todo {
+                    createdAt: todo.createdAt,
+                    ..Todo::default()
+                },
+            ),
+            (
+                "-updatedAt",
+                Todo {
+                    updatedAt: todo.updatedAt,
+                    ..Todo::default()
+                },
+            ),
+            (
+                "title,-priority",
+                Todo {
+                    title: todo.title.clone(),
+                    priority: todo.priority,
+                    ..Todo::default()
+                },
+            ),
+            (
+                "completed,dueAt",
+                Todo {
+                    completed: Some(true),
+                    dueAt: todo.dueAt,
+                    ..Todo::default()
+                },
+            ),
+        ];
+        for (spec, expected) in cases {
+            let sort = sorted_by(spec);
+            let cursor = encode_cursor(&todo, &sort);
+            let expected = Todo {
+                id: todo.id.clone(),
+                ..expected
+            };
+            assert_eq!(decode_cursor(&cursor, &sort), Ok(expected), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn bad_cursor_test() {
+        let sort = sorted_by("title");
+        let invalid = Err("Invalid cursor.".to_string());
+        let wrong_sort = Err("Cursor was made for a different sort order.".to_string());
+
+        assert_eq!(decode_cursor("", &sort), invalid);
+        assert_eq!(decode_cursor("not a cursor!", &sort), invalid);
+        let not_json = URL_SAFE_NO_PAD.encode("{\"sort\":");
+        assert_eq!(decode_cursor(&not_json, &sort), invalid);
+        let wrong_type = URL_SAFE_NO_PAD.encode(r#"{"sort":"title","id":"a","values":[7]}"#);
+        assert_eq!(decode_cursor(&wrong_type, &sort), invalid);
+        let short = URL_SAFE_NO_PAD.encode(r#"{"sort":"title","id":"a","values":[]}"#);
+        assert_eq!(decode_cursor(&short, &sort), wrong_sort);
+
+        let todo = Todo {
+            id: Some("a".to_string()),
+            title: "Buy milk".to_string(),
+            ..Todo::default()
+        };
+        let cursor = encode_cursor(&todo, &sort);
+        assert_eq!(decode_cursor(&cursor, &sorted_by("-title")), wrong_sort);
+        assert_eq!(
+            decode_cursor(&cursor, &sorted_by("title,dueAt")),
+            wrong_sort
+        );
+        let truncated = &cursor[..cursor.len() - 2];
+        assert_eq!(decode_cursor(truncated, &sort), invalid);
+    }
+
+    #[test]
+    fn link_header_test() {
+        let req = TestRequest::get()
+            .uri("/api/v1/todos?tag=home&after=abc&limit=2&page=3&before=xyz")
+            .to_http_request();
+        let base = "/api/v1/todos?tag=home&limit=2";
+        let first = format!("<{}>; rel=\"first\"", base);
+        let prev = format!("<{}&before=p1>; rel=\"prev\"", base);
+        let next = format!("<{}&after=n1>; rel=\"next\"", base);
+
+        let first_page = link_header(&req, &[("first", None), ("next", Some(("after", "n1")))]);
+        assert_eq!(first_page, format!("{}, {}", first, next));
+        let middle_page = link_header(
+            &req,
+            &[
+                ("first", None),
+                ("prev", Some(("before", "p1"))),
+                ("next", Some(("after", "n1"))),
+            ],
+        );
+        assert_eq!(middle_page, format!("{}, {}, {}", first, prev, next));
+        let last_page = link_header(&req, &[("first", None), ("prev", Some(("before", "p1")))]);
+        assert_eq!(last_page, format!("{}, {}", first, prev));
+
+        let bare = TestRequest::get()
+            .uri("/api/v1/todos?page=2")
+            .to_http_request();
+        let links = link_header(&bare, &[("first", None), ("prev", Some(("page", "1")))]);
+        assert_eq!(
+            links,
+            "</api/v1/todos>; rel=\"first\", </api/v1/todos?page=1>; rel=\"prev\""
+        );
+    }
+}
@@ -0,0 +1,402 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::model::{Todo, UpdateTodoSchema};
+
+pub const MERGE_PATCH: &str = "application/merge-patch+json";
+pub const JSON_PATCH: &str = "application/json-patch+json";
+
+// Fields a patch may change, and fields it may only `test`
+const EDITABLE: [&str; 9] = [
+    "title",
+    "content",
+    "completed",
+    "tags",
+    "dueAt",
+    "priority",
+    "recurrence",
+    "parentId",
+    "listId",
+];
+const READ_ONLY: [&str; 6] = [
+    "id",
+    "ownerId",
+    "createdAt",
+    "updatedAt",
+    "version",
+    "deletedAt",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    // The body's media type is not one PATCH accepts
+    Unsupported,
+    // A malformed patch, or one that cannot be applied
+    Invalid(String),
+    // A JSON Patch `test` operation did not match
+    TestFailed(String),
+}
+
+// One RFC 6902 operation. Paths are JSON Pointers (RFC 6901).
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+#[derive(Debug)]
+pub enum Patch {
+    // RFC 7396: members set to null are removed, objects merge recursively
+    Merge(Value),
+    // RFC 6902: operations applied in order, all or nothing
+    Json(Vec<Operation>),
+}
+
+impl Patch {
+    // Reads a PATCH body by its Content-Type. Plain JSON bodies are
+    // `UpdateTodoSchema`s, where null means "leave as is", so they become
+    // merge patches without their null members.
+    pub fn parse(content_type: Option<&str>, body: &[u8]) -> Result<Patch, PatchError> {
+        let invalid = |err: serde_json::Error| PatchError::Invalid(err.to_string());
+        let media_type = content_type
+            .and_then(|content_type| content_type.split(';').next())
+            .map(|media_type| media_type.trim().to_ascii_lowercase());
+        match media_type.as_deref() {
+            Some("application/json") => {
+                let update: UpdateTodoSchema = serde_json::from_slice(body).map_err(invalid)?;
+                let mut fields = match serde_json::to_value(update).map_err(invalid)? {
+                    Value::Object(fields) => fields,
+                    _ => Map::new(),
+                };
+                fields.retain(|_, value| !value.is_null());
+                Ok(Patch::Merge(Value::Object(fields)))
+            }
+            Some(MERGE_PATCH) => Ok(Patch::Merge(serde_json::from_slice(body).map_err(invalid)?)),
+            Some(JSON_PATCH) => Ok(Patch::Json(serde_json::from_slice(body).map_err(invalid)?)),
+            _ => Err(PatchError::Unsupported),
+        }
+    }
+
+    pub fn apply(&self, target: &mut Value) -> Result<(), PatchError> {
+        match self {
+            Patch::Merge(patch) => {
+                merge(target, patch);
+                Ok(())
+            }
+            Patch::Json(operations) => {
+                // Work on a copy so a failing operation leaves `target` untouched
+                let mut patched = target.clone();
+                for operation in operations {
+                    apply_operation(&mut patched, operation)?;
+                }
+                *target = patched;
+                Ok(())
+            }
+        }
+    }
+}
+
+// The todo with `patch` applied to its JSON form. Only the fields in
+// `EDITABLE` may change.
+pub fn patch_todo(todo: &Todo, patch: &Patch) -> Result<Todo, PatchError> {
+    let invalid = |err: serde_json::Error| PatchError::Invalid(err.to_string());
+    let original = serde_json::to_value(todo).map_err(invalid)?;
+    let mut patched = original.clone();
+    patch.apply(&mut patched)?;
+
+    let Value::Object(fields) = &patched else {
+        return Err(PatchError::Invalid(
+            "A todo must be a JSON object.".to_string(),
+        ));
+    };
+    if let Some(unknown) = fields
+        .keys()
+        .find(|key| !EDITABLE.contains(&key.as_str()) && !READ_ONLY.contains(&key.as_str()))
+    {
+        return Err(PatchError::Invalid(format!("Unknown field {:?}.", unknown)));
+    }
+    if let Some(field) = READ_ONLY
+        .iter()
+        .find(|field| fields.get(**field) != original.get(**field))
+    {
+        return Err(PatchError::Invalid(format!("{} cannot be changed.", field)));
+    }
+    serde_json::from_value(patched).map_err(invalid)
+}
+
+fn merge(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let Value::Object(target) = target else {
+        unreachable!("replaced by an object above");
+    };
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+fn apply_operation(doc: &mut Value, operation: &Operation) -> Result<(), PatchError> {
+    match operation {
+        Operation::Add { path, value } => add(doc, path, value.clone()),
+        Operation::Remove { path } => remove(doc, path).map(|_| ()),
+        Operation::Replace { path, value } => {
+            *target_mut(doc, path)? = value.clone();
+            Ok(())
+        }
+        Operation::Move { from, path } => {
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(PatchError::Invalid(format!(
+                    "Cannot move {:?} into one of its children.",
+                    from
+                )));
+            }
+            let value = remove(doc, from)?;
+            add(doc, path, value)
+        }
+        Operation::Copy { from, path } => {
+            let value = target(doc, from)?.clone();
+            add(doc, path, value)
+        }
+        Operation::Test { path, value } => {
+            if target(doc, path)? == value {
+                Ok(())
+            } else {
+                Err(PatchError::TestFailed(format!(
+                    "Test failed: {:?} does not match.",
+                    path
+                )))
+            }
+        }
+    }
+}
+
+fn no_such_path(path: &str) -> PatchError {
+    PatchError::Invalid(format!("Path {:?} does not exist.", path))
+}
+
+fn target<'a>(doc: &'a Value, path: &str) -> Result<&'a Value, PatchError> {
+    doc.pointer(path).ok_or_else(|| no_such_path(path))
+}
+
+fn target_mut<'a>(doc: &'a mut Value, path: &str) -> Result<&'a mut Value, PatchError> {
+    doc.pointer_mut(path).ok_or_else(|| no_such_path(path))
+}
+
+// The container a pointer points into and the unescaped last token
+fn parent<'a>(doc: &'a mut Value, path: &str) -> Result<(&'a mut Value, String), PatchError> {
+    let Some(split) = path.rfind('/') else {
+        return Err(PatchError::Invalid(format!(
+            "{:?} is not a JSON Pointer.",
+            path
+        )));
+    };
+    let token = path[split + 1..].replace("~1", "/").replace("~0", "~");
+    let parent = target_mut(doc, &path[..split])?;
+    Ok((parent, token))
+}
+
+// Array indexes are plain decimal numbers without leading zeros
+fn index(token: &str, len: usize, path: &str) -> Result<usize, PatchError> {
+    let valid =
+        token == "0" || (!token.starts_with('0') && token.bytes().all(|b| b.is_ascii_digit()));
+    match token.parse::<usize>() {
+        Ok(index) if valid && index <= len => Ok(index),
+        _ => Err(no_such_path(path)),
+    }
+}
+
+fn add(doc: &mut Value, path: &str, value: Value) -> Result<(), PatchError> {
+    if path.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+    match parent(doc, path)? {
+        (Value::Object(fields), token) => {
+            fields.insert(token, value);
+        }
+        (Value::Array(items), token) if token == "-" => items.push(value),
+        (Value::Array(items), token) => {
+            let index = index(&token, items.len(), path)?;
+            items.insert(index, value);
+        }
+        _ => return Err(no_such_path(path)),
+    }
+    Ok(())
+}
+
+fn remove(doc: &mut Value, path: &str) -> Result<Value, PatchError> {
+    if path.is_empty() {
+        return Err(PatchError::Invalid(
+            "Cannot remove the whole todo.".to_string(),
+        ));
+    }
+    match parent(doc, path)? {
+        (Value::Object(fields), token) => fields.remove(&token).ok_or_else(|| no_such_path(path)),
+        (Value::Array(items), token) => {
+            let index = index(&token, items.len(), path)?;
+            if index == items.len() {
+                return Err(no_such_path(path));
+            }
+            Ok(items.remove(index))
+        }
+        _ => Err(no_such_path(path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn json_patch(doc: Value, operations: Value) -> Result<Value, PatchError> {
+        let mut doc = doc;
+        let patch = Patch::Json(serde_json::from_value(operations).unwrap());
+        patch.apply(&mut doc).map(|_| doc)
+    }
+
+    #[test]
+    fn merge_patch_test() {
+        // Examples from RFC 7396, appendix A
+        for (target, patch, result) in [
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (
+                json!({"a": "b"}),
+                json!({"b": "c"}),
+                json!({"a": "b", "b": "c"}),
+            ),
+            (json!({"a": "b"}), json!({"a": null}), json!({})),
+            (
+                json!({"a": {"b": "c"}}),
+                json!({"a": {"b": "d", "c": null}}),
+                json!({"a": {"b": "d"}}),
+            ),
+            (
+                json!({"a": [{"b": "c"}]}),
+                json!({"a": [1]}),
+                json!({"a": [1]}),
+            ),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a": "foo"}), json!("bar"), json!("bar")),
+            (
+                json!({"e": null}),
+                json!({"a": 1}),
+                json!({"e": null, "a": 1}),
+            ),
+            (
+                json!([1, 2]),
+                json!({"a": "b", "c": null}),
+                json!({"a": "b"}),
+            ),
+            (
+                json!({}),
+                json!({"a": {"bb": {"ccc": null}}}),
+                json!({"a": {"bb": {}}}),
+            ),
+        ] {
+            let mut target = target;
+            Patch::Merge(patch).apply(&mut target).unwrap();
+            assert_eq!(target, result);
+        }
+    }
+
+    #[test]
+    fn json_patch_test() {
+        // Examples from RFC 6902, appendix A
+        assert_eq!(
+            json_patch(
+                json!({"foo": ["bar", "baz"]}),
+                json!([{"op": "add", "path": "/foo/1", "value": "qux"}])
+            ),
+            Ok(json!({"foo": ["bar", "qux", "baz"]}))
+        );
+        assert_eq!(
+            json_patch(
+                json!({"foo": {"bar": "baz", "waldo": "fred"}, "qux": {"corge": "grault"}}),
+                json!([{"op": "move", "from": "/foo/waldo", "path": "/qux/thud"}])
+            ),
+            Ok(json!({"foo": {"bar": "baz"}, "qux": {"corge": "grault", "thud": "fred"}}))
+        );
+        assert_eq!(
+            json_patch(
+                json!({"foo": ["all", "grass", "cows", "eat"]}),
+                json!([{"op": "move", "from": "/foo/1", "path": "/foo/3"}])
+            ),
+            Ok(json!({"foo": ["all", "cows", "eat", "grass"]}))
+        );
+        assert_eq!(
+            json_patch(
+                json!({"/": 9, "~1": 10}),
+                json!([{"op": "test", "path": "/~01", "value": 10}])
+            ),
+            Ok(json!({"/": 9, "~1": 10}))
+        );
+        assert_eq!(
+            json_patch(
+                json!({"foo": ["bar"]}),
+                json!([
+                    {"op": "add", "path": "/foo/-", "value": ["abc", "def"]},
+                    {"op": "copy", "from": "/foo/0", "path": "/baz"},
+                    {"op": "replace", "path": "/foo/0", "value": "qux"},
+                    {"op": "remove", "path": "/foo/1/0"}
+                ])
+            ),
+            Ok(json!({"foo": ["qux", ["def"]], "baz": "bar"}))
+        );
+
+        // Nothing is applied when an operation fails
+        for (operations, error) in [
+            (
+                json!([{"op": "test", "path": "/baz", "value": "bar"}]),
+                PatchError::TestFailed("Test failed: \"/baz\" does not match.".to_string()),
+            ),
+            (
+                json!([{"op": "remove", "path": "/foo/01"}]),
+                no_such_path("/foo/01"),
+            ),
+            (
+                json!([{"op": "add", "path": "/foo/2", "value": 1}]),
+                no_such_path("/foo/2"),
+            ),
+            (
+                json!([{"op": "replace", "path": "/bar", "value": 1}]),
+                no_such_path("/bar"),
+            ),
+            (
+                json!([{"op": "move", "from": "/foo", "path": "/foo/0"}]),
+                PatchError::Invalid("Cannot move \"/foo\" into one of its children.".to_string()),
+            ),
+        ] {
+            assert_eq!(
+                json_patch(json!({"baz": "qux", "foo": ["bar"]}), operations),
+                Err(error)
+            );
+        }
+    }
+
+    #[test]
+    fn patch_todo_fields_test() {
+        // Every field of a todo is either editable or read-only
+        let todo = serde_json::to_value(Todo::default()).unwrap();
+        for key in todo.as_object().unwrap().keys() {
+            assert!(
+                EDITABLE.contains(&key.as_str()) || READ_ONLY.contains(&key.as_str()),
+                "{}",
+                key
+            );
+        }
+    }
+}
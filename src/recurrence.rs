@@ -0,0 +1,929 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::prelude::*;
+use chrono::{Days, Months};
+
+use crate::{
+    auth::Principal,
+    config::Config,
+    handler::NOT_FOUND,
+    model::{AppState, OccurrenceOptions},
+    repository::RepositoryError,
+    response::{error_response, OccurrenceListResponse},
+    trash::live_todo,
+};
+
+// A rule with no occurrence for this long is taken to have none left, e.g.
+// FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=30
+const MAX_GAP_MONTHS: u32 = 12 * 10;
+// How far past a todo's due date occurrences can be previewed. Expansion
+// starts at the due date, so this bounds the work before `from` is reached.
+const MAX_PREVIEW_YEARS: u32 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+impl Frequency {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        }
+    }
+}
+
+// An RFC 5545 recurrence rule. Supports FREQ from DAILY to YEARLY with
+// INTERVAL, COUNT, UNTIL, BYMONTH, BYMONTHDAY, BYDAY, BYSETPOS and WKST.
+// Rules are evaluated in UTC, at the time of day of the first occurrence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub freq: Frequency,
+    pub interval: u32,
+    // Occurrences in total, the first included
+    pub count: Option<u32>,
+    // Last possible occurrence, inclusive
+    pub until: Option<DateTime<Utc>>,
+    pub by_month: Vec<u32>,
+    // Negative days count from the end of the month
+    pub by_month_day: Vec<i32>,
+    // Weekdays with an optional ordinal, e.g. -1FR for the last Friday
+    pub by_day: Vec<(Option<i32>, Weekday)>,
+    pub by_set_pos: Vec<i32>,
+    pub week_start: Weekday,
+}
+
+fn parse_weekday(day: &str) -> Result<Weekday, String> {
+    match day {
+        "MO" => Ok(Weekday::Mon),
+        "TU" => Ok(Weekday::Tue),
+        "WE" => Ok(Weekday::Wed),
+        "TH" => Ok(Weekday::Thu),
+        "FR" => Ok(Weekday::Fri),
+        "SA" => Ok(Weekday::Sat),
+        "SU" => Ok(Weekday::Sun),
+        _ => Err(format!("Unknown weekday {:?} in recurrence rule.", day)),
+    }
+}
+
+fn weekday_name(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+// Parses a comma separated list of numbers whose magnitude is 1..=`max`
+fn parse_numbers(name: &str, value: &str, max: i32, signed: bool) -> Result<Vec<i32>, String> {
+    let invalid = || {
+        format!(
+            "{} must list numbers from {}1 to {}.",
+            name,
+            if signed { "-" } else { "" },
+            max
+        )
+    };
+    value
+        .split(',')
+        .map(|number| {
+            let number: i32 = number.parse().map_err(|_| invalid())?;
+            if number == 0 || number.abs() > max || (number < 0 && !signed) {
+                return Err(invalid());
+            }
+            Ok(number)
+        })
+        .collect()
+}
+
+fn parse_positive(name: &str, value: &str) -> Result<u32, String> {
+    match value.parse() {
+        Ok(number) if number > 0 => Ok(number),
+        _ => Err(format!("{} must be a positive number.", name)),
+    }
+}
+
+// UNTIL is either a UTC date-time or a date, which includes the whole day
+fn parse_until(value: &str) -> Result<DateTime<Utc>, String> {
+    let value = value.strip_suffix('Z').unwrap_or(value);
+    if let Ok(until) = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S") {
+        return Ok(until.and_utc());
+    }
+    NaiveDate::parse_from_str(value, "%Y%m%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(23, 59, 59))
+        .map(|until| until.and_utc())
+        .ok_or_else(|| "UNTIL must look like 20301231 or 20301231T170000Z.".to_string())
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(rule: &str) -> Result<Rule, String> {
+        if !rule.is_ascii() {
+            return Err("Malformed recurrence rule.".to_string());
+        }
+        let rule = rule.trim().to_ascii_uppercase();
+        let rule = rule.strip_prefix("RRULE:").unwrap_or(&rule);
+        let mut freq = None;
+        let mut parsed = Rule {
+            freq: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_month: Vec::new(),
+            by_month_day: Vec::new(),
+            by_day: Vec::new(),
+            by_set_pos: Vec::new(),
+            week_start: Weekday::Mon,
+        };
+        let mut seen = HashSet::new();
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let Some((name, value)) = part.split_once('=') else {
+                return Err(format!("Malformed recurrence rule part {:?}.", part));
+            };
+            if !seen.insert(name) {
+                return Err(format!("{} appears more than once.", name));
+            }
+            match name {
+                "FREQ" => {
+                    freq = Some(match value {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        "SECONDLY" | "MINUTELY" | "HOURLY" => {
+                            return Err(format!("FREQ={} is not supported.", value))
+                        }
+                        _ => return Err(format!("Unknown FREQ {:?}.", value)),
+                    })
+                }
+                "INTERVAL" => parsed.interval = parse_positive(name, value)?,
+                "COUNT" => parsed.count = Some(parse_positive(name, value)?),
+                "UNTIL" => parsed.until = Some(parse_until(value)?),
+                "BYMONTH" => {
+                    parsed.by_month = parse_numbers(name, value, 12, false)?
+                        .into_iter()
+                        .map(|month| month as u32)
+                        .collect()
+                }
+                "BYMONTHDAY" => parsed.by_month_day = parse_numbers(name, value, 31, true)?,
+                "BYSETPOS" => parsed.by_set_pos = parse_numbers(name, value, 366, true)?,
+                "BYDAY" => {
+                    for day in value.split(',') {
+                        let split = day.len().saturating_sub(2);
+                        let (ordinal, weekday) = day.split_at(split);
+                        let ordinal = match ordinal {
+                            "" => None,
+                            ordinal => Some(
+                                parse_numbers(name, ordinal.trim_start_matches('+'), 53, true)?[0],
+                            ),
+                        };
+                        parsed.by_day.push((ordinal, parse_weekday(weekday)?));
+                    }
+                }
+                "WKST" => parsed.week_start = parse_weekday(value)?,
+                _ => return Err(format!("Recurrence rule part {} is not supported.", name)),
+            }
+        }
+
+        parsed.freq = freq.ok_or("A recurrence rule needs a FREQ.")?;
+        if parsed.count.is_some() && parsed.until.is_some() {
+            return Err("COUNT and UNTIL cannot be combined.".to_string());
+        }
+        if parsed.freq == Frequency::Weekly && !parsed.by_month_day.is_empty() {
+            return Err("BYMONTHDAY cannot be used with FREQ=WEEKLY.".to_string());
+        }
+        let ordinals = parsed.by_day.iter().any(|(ordinal, _)| ordinal.is_some());
+        if ordinals && !matches!(parsed.freq, Frequency::Monthly | Frequency::Yearly) {
+            return Err("BYDAY ordinals need FREQ=MONTHLY or FREQ=YEARLY.".to_string());
+        }
+        if !parsed.by_set_pos.is_empty()
+            && parsed.by_month.is_empty()
+            && parsed.by_month_day.is_empty()
+            && parsed.by_day.is_empty()
+        {
+            return Err("BYSETPOS needs BYMONTH, BYMONTHDAY or BYDAY.".to_string());
+        }
+        Ok(parsed)
+    }
+}
+
+// Writes the rule in a fixed part order, leaving out defaults
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join =
+            |numbers: &mut dyn Iterator<Item = String>| numbers.collect::<Vec<_>>().join(",");
+        write!(f, "FREQ={}", self.freq.as_str())?;
+        if self.interval > 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until.format("%Y%m%dT%H%M%SZ"))?;
+        }
+        if !self.by_month.is_empty() {
+            let months = join(&mut self.by_month.iter().map(u32::to_string));
+            write!(f, ";BYMONTH={}", months)?;
+        }
+        if !self.by_month_day.is_empty() {
+            let days = join(&mut self.by_month_day.iter().map(i32::to_string));
+            write!(f, ";BYMONTHDAY={}", days)?;
+        }
+        if !self.by_day.is_empty() {
+            let days = join(&mut self.by_day.iter().map(|(ordinal, day)| match ordinal {
+                Some(ordinal) => format!("{}{}", ordinal, weekday_name(*day)),
+                None => weekday_name(*day).to_string(),
+            }));
+            write!(f, ";BYDAY={}", days)?;
+        }
+        if !self.by_set_pos.is_empty() {
+            let positions = join(&mut self.by_set_pos.iter().map(i32::to_string));
+            write!(f, ";BYSETPOS={}", positions)?;
+        }
+        if self.week_start != Weekday::Mon {
+            write!(f, ";WKST={}", weekday_name(self.week_start))?;
+        }
+        Ok(())
+    }
+}
+
+// Checks a rule and returns it in the form it is stored in
+pub fn normalize_recurrence(rule: &str) -> Result<String, String> {
+    Ok(rule.parse::<Rule>()?.to_string())
+}
+
+fn days_in_month(date: NaiveDate) -> u32 {
+    let first = date.with_day(1).unwrap_or(date);
+    first.checked_add_months(Months::new(1)).map_or(31, |next| {
+        next.signed_duration_since(first).num_days() as u32
+    })
+}
+
+fn days_in_year(date: NaiveDate) -> u32 {
+    if date.leap_year() {
+        366
+    } else {
+        365
+    }
+}
+
+impl Rule {
+    // Every occurrence of the series whose first occurrence is `start`
+    pub fn occurrences(&self, start: DateTime<Utc>) -> Occurrences<'_> {
+        Occurrences {
+            rule: self,
+            start,
+            last: start.date_naive(),
+            period: 0,
+            pending: VecDeque::new(),
+            emitted: 0,
+            done: false,
+        }
+    }
+
+    // The occurrence after the one due at `due_at`, with the rule that
+    // continues the series from there. None when the series ends.
+    pub fn next_after(&self, due_at: DateTime<Utc>) -> Option<(Rule, DateTime<Utc>)> {
+        let next = self.occurrences(due_at).nth(1)?;
+        let mut rule = self.clone();
+        rule.count = rule.count.map(|count| count - 1);
+        Some((rule, next))
+    }
+
+    // The days of the `index`th period after the one `start` falls in
+    fn period(&self, start: NaiveDate, index: u32) -> Option<Vec<NaiveDate>> {
+        let step = index.checked_mul(self.interval)?;
+        let (first, days) = match self.freq {
+            Frequency::Daily => (start.checked_add_days(Days::new(step.into()))?, 1),
+            Frequency::Weekly => {
+                let into_week = (7 + start.weekday().num_days_from_monday()
+                    - self.week_start.num_days_from_monday())
+                    % 7;
+                let first = start
+                    .checked_sub_days(Days::new(into_week.into()))?
+                    .checked_add_days(Days::new(u64::from(step) * 7))?;
+                (first, 7)
+            }
+            Frequency::Monthly => {
+                let first = start.with_day(1)?.checked_add_months(Months::new(step))?;
+                (first, days_in_month(first))
+            }
+            Frequency::Yearly => {
+                let year = start.year().checked_add(i32::try_from(step).ok()?)?;
+                let first = NaiveDate::from_ymd_opt(year, 1, 1)?;
+                (first, days_in_year(first))
+            }
+        };
+        Some(first.iter_days().take(days as usize).collect())
+    }
+
+    fn matches(&self, date: NaiveDate, start: NaiveDate) -> bool {
+        if !self.by_month.is_empty() && !self.by_month.contains(&date.month()) {
+            return false;
+        }
+        // Without day rules a series repeats on the day it started
+        if self.by_month_day.is_empty() && self.by_day.is_empty() {
+            return match self.freq {
+                Frequency::Daily => true,
+                Frequency::Weekly => date.weekday() == start.weekday(),
+                Frequency::Monthly => date.day() == start.day(),
+                Frequency::Yearly => {
+                    (!self.by_month.is_empty() || date.month() == start.month())
+                        && date.day() == start.day()
+                }
+            };
+        }
+        if !self.by_month_day.is_empty() {
+            let day = date.day() as i32;
+            let from_end = day - days_in_month(date) as i32 - 1;
+            if !self
+                .by_month_day
+                .iter()
+                .any(|&wanted| wanted == day || wanted == from_end)
+            {
+                return false;
+            }
+        }
+        self.by_day.is_empty()
+            || self.by_day.iter().any(|&(ordinal, weekday)| {
+                weekday == date.weekday()
+                    && ordinal.is_none_or(|ordinal| self.is_nth_weekday(date, ordinal))
+            })
+    }
+
+    // Whether `date` is the `ordinal`th of its weekday in its month, or in its
+    // year for yearly rules without BYMONTH
+    fn is_nth_weekday(&self, date: NaiveDate, ordinal: i32) -> bool {
+        let in_month = self.freq == Frequency::Monthly || !self.by_month.is_empty();
+        let (day, days) = match in_month {
+            true => (date.day(), days_in_month(date)),
+            false => (date.ordinal(), days_in_year(date)),
+        };
+        match ordinal > 0 {
+            true => ((day - 1) / 7 + 1) as i32 == ordinal,
+            false => ((days - day) / 7 + 1) as i32 == -ordinal,
+        }
+    }
+}
+
+pub struct Occurrences<'a> {
+    rule: &'a Rule,
+    start: DateTime<Utc>,
+    // Day of the latest occurrence handed out
+    last: NaiveDate,
+    // Next period to expand
+    period: u32,
+    pending: VecDeque<DateTime<Utc>>,
+    emitted: u32,
+    done: bool,
+}
+
+impl Occurrences<'_> {
+    // Queues the occurrences of the next period. False once there are none.
+    fn expand(&mut self) -> bool {
+        let start = self.start.date_naive();
+        let Some(days) = self.rule.period(start, self.period) else {
+            return false;
+        };
+        self.period += 1;
+        let gave_up = self.last.checked_add_months(Months::new(MAX_GAP_MONTHS));
+        if gave_up.is_none_or(|gave_up| days[0] > gave_up) {
+            return false;
+        }
+
+        let mut days: Vec<NaiveDate> = days
+            .into_iter()
+            .filter(|day| self.rule.matches(*day, start))
+            .collect();
+        if !self.rule.by_set_pos.is_empty() {
+            let len = days.len() as i32;
+            let mut picked: Vec<NaiveDate> = self
+                .rule
+                .by_set_pos
+                .iter()
+                .map(|&position| {
+                    if position > 0 {
+                        position - 1
+                    } else {
+                        len + position
+                    }
+                })
+                .filter(|index| (0..len).contains(index))
+                .map(|index| days[index as usize])
+                .collect();
+            picked.sort();
+            picked.dedup();
+            days = picked;
+        }
+        self.pending.extend(
+            days.into_iter()
+                .map(|day| day.and_time(self.start.time()).and_utc())
+                .filter(|at| *at > self.start),
+        );
+        true
+    }
+}
+
+impl Iterator for Occurrences<'_> {
+    type Item = DateTime<Utc>;
+
+    fn next(&mut self) -> Option<DateTime<Utc>> {
+        while !self.done {
+            if self.rule.count.is_some_and(|count| self.emitted >= count) {
+                break;
+            }
+            // The first occurrence is the start, whether or not it fits the rule
+            let next = match self.emitted {
+                0 => Some(self.start),
+                _ => self.pending.pop_front(),
+            };
+            let Some(next) = next else {
+                self.done = !self.expand();
+                continue;
+            };
+            if self.rule.until.is_some_and(|until| next > until) {
+                break;
+            }
+            self.emitted += 1;
+            self.last = next.date_naive();
+            return Some(next);
+        }
+        self.done = true;
+        None
+    }
+}
+
+// Preview when a todo comes due in [from, to), from now on by default. A todo
+// without a rule occurs once, at its due time.
+pub async fn get_occurrences(
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    path: web::Path<String>,
+    query: web::Query<OccurrenceOptions>,
+) -> Result<HttpResponse, RepositoryError> {
+    let pagination = req
+        .app_data::<web::Data<Config>>()
+        .map(|config| config.pagination.clone())
+        .unwrap_or_default();
+    let limit = query.limit.unwrap_or(pagination.default_page_size);
+    if limit == 0 {
+        return Ok(error_response(
+            HttpResponse::BadRequest(),
+            "limit must be at least 1.",
+        ));
+    }
+    let limit = limit.min(pagination.max_page_size);
+    let from = query.from.unwrap_or_else(Utc::now);
+    if query.to.is_some_and(|to| to < from) {
+        return Ok(error_response(
+            HttpResponse::BadRequest(),
+            "to must not be before from.",
+        ));
+    }
+    let Some(todo) = live_todo(&app_state, &principal.subject, &path)? else {
+        return Ok(error_response(HttpResponse::NotFound(), NOT_FOUND));
+    };
+    let horizon = todo
+        .dueAt
+        .and_then(|due_at| due_at.checked_add_months(Months::new(MAX_PREVIEW_YEARS * 12)));
+    if horizon.is_some_and(|horizon| from > horizon) {
+        return Ok(error_response(
+            HttpResponse::BadRequest(),
+            &format!(
+                "from must be within {} years of the todo's due date.",
+                MAX_PREVIEW_YEARS
+            ),
+        ));
+    }
+
+    let rule = match todo.recurrence.as_deref().map(str::parse::<Rule>) {
+        Some(Ok(rule)) => rule,
+        Some(Err(message)) => return Ok(error_response(HttpResponse::BadRequest(), &message)),
+        // A single occurrence
+        None => Rule {
+            count: Some(1),
+            ..Rule::from_str("FREQ=DAILY").expect("valid rule")
+        },
+    };
+    let mut occurrences: Vec<DateTime<Utc>> = todo
+        .dueAt
+        .map(|due_at| {
+            rule.occurrences(due_at)
+                .skip_while(|at| *at < from)
+                .take_while(|at| query.to.is_none_or(|to| *at < to))
+                .take(limit + 1)
+                .collect()
+        })
+        .unwrap_or_default();
+    let has_more = occurrences.len() > limit;
+    occurrences.truncate(limit);
+
+    let response_json = &OccurrenceListResponse {
+        status: "success".to_string(),
+        results: occurrences.len(),
+        has_more,
+        recurrence: todo.recurrence,
+        occurrences,
+    };
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::test_support::bearer;
+    use crate::response::{SingleListResponse, SingleTodoResponse, TodoListResponse};
+    use crate::test_support::{api, app_states, racing, request};
+    use actix_web::{http, test};
+    use serde_json::json;
+
+    fn at(value: &str) -> DateTime<Utc> {
+        value.parse().unwrap()
+    }
+
+    #[actix_web::test]
+    async fn parse_rule_test() {
+        assert_eq!(
+            normalize_recurrence("rrule:freq=weekly;byday=sa;interval=1").unwrap(),
+            "FREQ=WEEKLY;BYDAY=SA"
+        );
+        assert_eq!(
+            normalize_recurrence("FREQ=MONTHLY;BYDAY=+2MO,-1FR;UNTIL=20301231;WKST=SU").unwrap(),
+            "FREQ=MONTHLY;UNTIL=20301231T235959Z;BYDAY=2MO,-1FR;WKST=SU"
+        );
+        for rule in [
+            "",
+            "BYDAY=MO",
+            "FREQ=HOURLY",
+            "FREQ=FORTNIGHTLY",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=DAILY;COUNT=2;UNTIL=20301231",
+            "FREQ=DAILY;FREQ=WEEKLY",
+            "FREQ=WEEKLY;BYDAY=1MO",
+            "FREQ=WEEKLY;BYMONTHDAY=1",
+            "FREQ=MONTHLY;BYMONTHDAY=32",
+            "FREQ=MONTHLY;BYDAY=XX",
+            "FREQ=MONTHLY;BYSETPOS=1",
+            "FREQ=DAILY;BYHOUR=9",
+            "FREQ=DAILY;UNTIL=tomorrow",
+            "FREQ=DAILY;BYDAY=MÖ",
+        ] {
+            assert!(rule.parse::<Rule>().is_err(), "{}", rule);
+        }
+    }
+
+    #[actix_web::test]
+    async fn occurrences_test() {
+        let cases = [
+            (
+                "2030-01-05T09:00:00Z",
+                "FREQ=WEEKLY;BYDAY=SA",
+                vec![
+                    "2030-01-05",
+                    "2030-01-12",
+                    "2030-01-19",
+                    "2030-01-26",
+                    "2030-02-02",
+                ],
+            ),
+            (
+                "2030-01-02T09:00:00Z",
+                "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,WE",
+                vec![
+                    "2030-01-02",
+                    "2030-01-14",
+                    "2030-01-16",
+                    "2030-01-28",
+                    "2030-01-30",
+                ],
+            ),
+            // Months without a 31st are skipped
+            (
+                "2030-01-31T09:00:00Z",
+                "FREQ=MONTHLY",
+                vec![
+                    "2030-01-31",
+                    "2030-03-31",
+                    "2030-05-31",
+                    "2030-07-31",
+                    "2030-08-31",
+                ],
+            ),
+            (
+                "2030-01-25T09:00:00Z",
+                "FREQ=MONTHLY;BYDAY=-1FR",
+                vec![
+                    "2030-01-25",
+                    "2030-02-22",
+                    "2030-03-29",
+                    "2030-04-26",
+                    "2030-05-31",
+                ],
+            ),
+            // Last weekday of the month
+            (
+                "2030-01-31T09:00:00Z",
+                "FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1",
+                vec![
+                    "2030-01-31",
+                    "2030-02-28",
+                    "2030-03-29",
+                    "2030-04-30",
+                    "2030-05-31",
+                ],
+            ),
+            (
+                "2028-02-29T09:00:00Z",
+                "FREQ=YEARLY",
+                vec![
+                    "2028-02-29",
+                    "2032-02-29",
+                    "2036-02-29",
+                    "2040-02-29",
+                    "2044-02-29",
+                ],
+            ),
+            (
+                "2030-03-31T09:00:00Z",
+                "FREQ=YEARLY;BYMONTH=3,9;BYMONTHDAY=-1",
+                vec![
+                    "2030-03-31",
+                    "2030-09-30",
+                    "2031-03-31",
+                    "2031-09-30",
+                    "2032-03-31",
+                ],
+            ),
+            (
+                "2030-05-20T09:00:00Z",
+                "FREQ=YEARLY;BYDAY=20MO",
+                vec![
+                    "2030-05-20",
+                    "2031-05-19",
+                    "2032-05-17",
+                    "2033-05-16",
+                    "2034-05-15",
+                ],
+            ),
+            (
+                "2030-01-01T09:00:00Z",
+                "FREQ=DAILY;INTERVAL=3;UNTIL=20300110",
+                vec!["2030-01-01", "2030-01-04", "2030-01-07", "2030-01-10"],
+            ),
+            (
+                "2030-01-01T09:00:00Z",
+                "FREQ=DAILY;COUNT=3",
+                vec!["2030-01-01", "2030-01-02", "2030-01-03"],
+            ),
+            // The start counts even when it does not fit the rule
+            (
+                "2030-01-01T09:00:00Z",
+                "FREQ=WEEKLY;BYDAY=SA;COUNT=2",
+                vec!["2030-01-01", "2030-01-05"],
+            ),
+            (
+                "2030-01-01T09:00:00Z",
+                "FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=30",
+                vec!["2030-01-01"],
+            ),
+        ];
+        for (start, rule, days) in cases {
+            let rule: Rule = rule.parse().unwrap();
+            let found: Vec<DateTime<Utc>> = rule.occurrences(at(start)).take(5).collect();
+            let expected: Vec<DateTime<Utc>> = days
+                .iter()
+                .map(|day| at(&format!("{}T09:00:00Z", day)))
+                .collect();
+            assert_eq!(found, expected, "{}", rule);
+        }
+
+        let rule: Rule = "FREQ=WEEKLY;COUNT=2".parse().unwrap();
+        let (next, due_at) = rule.next_after(at("2030-01-01T09:00:00Z")).unwrap();
+        assert_eq!(due_at, at("2030-01-08T09:00:00Z"));
+        assert_eq!(next.to_string(), "FREQ=WEEKLY;COUNT=1");
+        assert!(next.next_after(due_at).is_none());
+    }
+
+    #[actix_web::test]
+    async fn recurring_todos_test() {
+        for app_data in app_states() {
+            let app = api(&app_data).await;
+            let post = |body: serde_json::Value| {
+                test::TestRequest::post()
+                    .uri("/api/v1/todos")
+                    .insert_header(bearer("alice"))
+                    .set_json(body)
+                    .to_request()
+            };
+            for body in [
+                json!({ "title": "t", "content": "", "recurrence": "FREQ=WEEKLY" }),
+                json!({
+                    "title": "t", "content": "", "recurrence": "FREQ=SOMETIMES",
+                    "dueAt": "2030-01-05T09:00:00Z"
+                }),
+            ] {
+                let resp = test::call_service(&app, post(body)).await;
+                assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+            }
+
+            let created: SingleTodoResponse = test::call_and_read_body_json(
+                &app,
+                post(json!({
+                    "title": "Chores",
+                    "content": "",
+                    "tags": ["home"],
+                    "dueAt": "2030-01-05T09:00:00Z",
+                    "recurrence": "freq=weekly;byday=sa;count=3",
+                })),
+            )
+            .await;
+            let todo = created.data.todo;
+            let id = todo.id.clone().unwrap();
+            assert_eq!(
+                todo.recurrence.as_deref(),
+                Some("FREQ=WEEKLY;COUNT=3;BYDAY=SA")
+            );
+
+            let req = test::TestRequest::get()
+                .uri(&format!(
+                    "/api/v1/todos/{}/occurrences?from=2030-01-06T00:00:00Z&limit=5",
+                    id
+                ))
+                .insert_header(bearer("alice"))
+                .to_request();
+            let preview: OccurrenceListResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(
+                preview.occurrences,
+                [at("2030-01-12T09:00:00Z"), at("2030-01-19T09:00:00Z")]
+            );
+            assert!(!preview.has_more);
+            let req = test::TestRequest::get()
+                .uri(&format!(
+                    "/api/v1/todos/{}/occurrences?from=2030-01-01T00:00:00Z&to=2030-01-19T09:00:00Z&limit=1",
+                    id
+                ))
+                .insert_header(bearer("alice"))
+                .to_request();
+            let preview: OccurrenceListResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(preview.occurrences, [at("2030-01-05T09:00:00Z")]);
+            assert!(preview.has_more);
+
+            // Completing an occurrence creates the next one, twice
+            let complete = |id: &str| {
+                test::TestRequest::patch()
+                    .uri(&format!("/api/v1/todos/{}", id))
+                    .insert_header(bearer("alice"))
+                    .set_json(json!({ "completed": true }))
+                    .to_request()
+            };
+            let mut id = id;
+            for due_at in ["2030-01-12T09:00:00Z", "2030-01-19T09:00:00Z"] {
+                let updated: SingleTodoResponse =
+                    test::call_and_read_body_json(&app, complete(&id)).await;
+                assert_eq!(updated.data.todo.recurrence, None);
+                let next = updated.data.next_occurrence.unwrap();
+                assert_eq!(next.dueAt, Some(at(due_at)));
+                assert_eq!(next.completed, None);
+                assert_eq!(next.tags, ["home"]);
+                assert_ne!(next.id.as_deref(), Some(id.as_str()));
+                id = next.id.unwrap();
+            }
+            let updated: SingleTodoResponse =
+                test::call_and_read_body_json(&app, complete(&id)).await;
+            assert!(updated.data.next_occurrence.is_none());
+            // Completing it again does not repeat it
+            let updated: SingleTodoResponse =
+                test::call_and_read_body_json(&app, complete(&id)).await;
+            assert!(updated.data.next_occurrence.is_none());
+
+            let req = test::TestRequest::get()
+                .uri("/api/v1/todos?tag=home")
+                .insert_header(bearer("alice"))
+                .to_request();
+            let list: TodoListResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(list.total, 3);
+
+            // A rule needs a due date to start from
+            let plain: SingleTodoResponse = test::call_and_read_body_json(
+                &app,
+                post(json!({ "title": "Plain", "content": "" })),
+            )
+            .await;
+            let req = test::TestRequest::patch()
+                .uri(&format!("/api/v1/todos/{}", plain.data.todo.id.unwrap()))
+                .insert_header(bearer("alice"))
+                .set_json(json!({ "recurrence": "FREQ=DAILY" }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+
+            for query in ["limit=0", "from=9999-01-01T00:00:00Z"] {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/api/v1/todos/{}/occurrences?{}", id, query))
+                    .insert_header(bearer("alice"))
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST, "{}", query);
+            }
+            let req = test::TestRequest::get()
+                .uri("/api/v1/todos/missing/occurrences")
+                .insert_header(bearer("alice"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[actix_web::test]
+    async fn next_occurrence_test() {
+        for app_data in app_states() {
+            let app = api(&app_data).await;
+            let post = |uri: &str, body: serde_json::Value| request(http::Method::POST, uri, body);
+            let list: SingleListResponse = test::call_and_read_body_json(
+                &app,
+                post("/api/v1/lists", json!({ "name": "Home" })),
+            )
+            .await;
+            let list_id = list.data.list.id;
+            let parent: SingleTodoResponse = test::call_and_read_body_json(
+                &app,
+                post("/api/v1/todos", json!({ "title": "House", "content": "" })),
+            )
+            .await;
+            let parent_id = parent.data.todo.id.unwrap();
+            let created: SingleTodoResponse = test::call_and_read_body_json(
+                &app,
+                post(
+                    "/api/v1/todos",
+                    json!({
+                        "title": "Bins",
+                        "content": "Both of them",
+                        "tags": ["home"],
+                        "priority": "high",
+                        "dueAt": "2030-01-05T09:00:00Z",
+                        "recurrence": "FREQ=WEEKLY",
+                        "parentId": parent_id,
+                        "listId": list_id,
+                    }),
+                ),
+            )
+            .await;
+            let todo = created.data.todo;
+            let id = todo.id.clone().unwrap();
+            let uri = format!("/api/v1/todos/{}", id);
+            let complete = || request(http::Method::PATCH, &uri, json!({ "completed": true }));
+
+            // A request that changes the todo first keeps the completion, and
+            // the occurrence it would have started, out
+            let racing_id = id.clone();
+            let raced = racing(&app_data, move |todo_db| {
+                todo_db
+                    .update("alice", &racing_id, &mut |todo| todo.touch(Utc::now()))
+                    .unwrap();
+            });
+            let resp = test::call_service(&api(&raced).await, complete()).await;
+            assert_eq!(resp.status(), http::StatusCode::CONFLICT);
+            let req = request(http::Method::GET, "/api/v1/todos?tag=home", json!({}));
+            let list: TodoListResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(list.total, 1);
+            let stored = app_data.todo_db.get("alice", &id).unwrap().unwrap();
+            assert_eq!(stored.recurrence.as_deref(), Some("FREQ=WEEKLY"));
+
+            // The next occurrence stays where the series is filed and starts
+            // out open and unchanged
+            let updated: SingleTodoResponse = test::call_and_read_body_json(&app, complete()).await;
+            let next = updated.data.next_occurrence.unwrap();
+            assert_eq!(next.title, "Bins");
+            assert_eq!(next.content, "Both of them");
+            assert_eq!(next.tags, ["home"]);
+            assert_eq!(next.priority, todo.priority);
+            assert_eq!(next.parentId.as_ref(), Some(&parent_id));
+            assert_eq!(next.listId.as_ref(), Some(&list_id));
+            assert_eq!(next.ownerId.as_deref(), Some("alice"));
+            assert_eq!(next.completed, None);
+            assert_eq!(next.version, 1);
+            assert_eq!(next.deletedAt, None);
+            assert_eq!(next.dueAt, Some(at("2030-01-12T09:00:00Z")));
+            assert!(next.createdAt > todo.createdAt);
+            assert_eq!(next.updatedAt, next.createdAt);
+            assert_eq!(next.updatedAt, updated.data.todo.updatedAt);
+        }
+    }
+}
@@ -0,0 +1,569 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::web;
+use chrono::prelude::*;
+use chrono::TimeDelta;
+use serde_json::json;
+
+use crate::config::{NotifierKind, ReminderConfig, SmtpConfig};
+use crate::model::{AppState, SentReminder, Todo};
+use crate::repository::{ReminderRepository, RepositoryError, TodoFilter, TodoRepository};
+
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// A reminder about to go out
+#[derive(Debug, Clone)]
+pub struct Reminder {
+    pub todo: Todo,
+    pub due_at: DateTime<Utc>,
+    // Minutes before `due_at` the reminder was set for
+    pub offset_minutes: u64,
+}
+
+impl Reminder {
+    pub fn summary(&self) -> String {
+        let when = match self.offset_minutes {
+            0 => "now".to_string(),
+            1 => "in 1 minute".to_string(),
+            minutes => format!("in {} minutes", minutes),
+        };
+        format!("\"{}\" is due {}", self.todo.title, when)
+    }
+}
+
+// Delivers reminders. Called from a blocking thread, so implementations may
+// do blocking I/O.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, reminder: &Reminder) -> io::Result<()>;
+}
+
+// Writes reminders to the server log
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn notify(&self, reminder: &Reminder) -> io::Result<()> {
+        log::info!(
+            "Reminder for {}: {} ({})",
+            reminder.todo.ownerId.as_deref().unwrap_or_default(),
+            reminder.summary(),
+            reminder.due_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+        );
+        Ok(())
+    }
+}
+
+fn connect(host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut last_err = io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", host));
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, NETWORK_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(NETWORK_TIMEOUT))?;
+                stream.set_write_timeout(Some(NETWORK_TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+// POSTs each reminder as JSON to a plain http:// URL and expects a 2xx reply
+pub struct WebhookNotifier {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str) -> io::Result<WebhookNotifier> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("bad webhook URL {:?}", url),
+            )
+        };
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(WebhookNotifier {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&self, reminder: &Reminder) -> io::Result<()> {
+        let body = json!({
+            "event": "reminder",
+            "message": reminder.summary(),
+            "dueAt": reminder.due_at,
+            "offsetMinutes": reminder.offset_minutes,
+            "todo": reminder.todo,
+        })
+        .to_string();
+        let mut stream = connect(&self.host, self.port)?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            self.port,
+            body.len(),
+            body
+        )?;
+        stream.flush()?;
+
+        let mut status_line = String::new();
+        BufReader::new(&stream).read_line(&mut status_line)?;
+        let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+        if !status.starts_with('2') || status.len() != 3 {
+            return Err(io::Error::other(format!(
+                "webhook answered {:?}",
+                status_line.trim_end()
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Mails each reminder through an SMTP relay that needs no authentication or
+// TLS, such as a local MTA or a test server
+pub struct SmtpNotifier {
+    config: SmtpConfig,
+}
+
+impl SmtpNotifier {
+    pub fn new(config: SmtpConfig) -> SmtpNotifier {
+        SmtpNotifier { config }
+    }
+}
+
+// Reads one reply, which may span several lines, and checks its code
+fn expect_reply(reader: &mut impl BufRead, code: &str) -> io::Result<()> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "SMTP server hung up",
+            ));
+        }
+        if !line.starts_with(code) {
+            return Err(io::Error::other(format!(
+                "SMTP server answered {:?}, expected {}",
+                line.trim_end(),
+                code
+            )));
+        }
+        // "250-..." continues the reply, "250 ..." ends it
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+impl Notifier for SmtpNotifier {
+    fn notify(&self, reminder: &Reminder) -> io::Result<()> {
+        let stream = connect(&self.config.host, self.config.port)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        expect_reply(&mut reader, "220")?;
+        let mut command = |line: String, code: &str| -> io::Result<()> {
+            writer.write_all(line.as_bytes())?;
+            writer.write_all(b"\r\n")?;
+            writer.flush()?;
+            expect_reply(&mut reader, code)
+        };
+
+        command("HELO localhost".to_string(), "250")?;
+        command(format!("MAIL FROM:<{}>", self.config.from), "250")?;
+        for to in &self.config.to {
+            command(format!("RCPT TO:<{}>", to), "25")?;
+        }
+        command("DATA".to_string(), "354")?;
+
+        let body = format!(
+            "{}\r\n\r\nDue: {}\r\n\r\n{}",
+            reminder.summary(),
+            reminder.due_at.to_rfc2822(),
+            reminder.todo.content
+        );
+        // Lines starting with a dot are escaped so they cannot end the message
+        let body: Vec<String> = body
+            .replace("\r\n", "\n")
+            .split('\n')
+            .map(|line| match line.starts_with('.') {
+                true => format!(".{}", line),
+                false => line.to_string(),
+            })
+            .collect();
+        let message = format!(
+            "From: <{}>\r\nTo: {}\r\nSubject: Reminder: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n.",
+            self.config.from,
+            self.config
+                .to
+                .iter()
+                .map(|to| format!("<{}>", to))
+                .collect::<Vec<_>>()
+                .join(", "),
+            reminder.todo.title.replace(['\r', '\n'], " "),
+            Utc::now().to_rfc2822(),
+            body.join("\r\n")
+        );
+        command(message, "250")?;
+        command("QUIT".to_string(), "221")
+    }
+}
+
+// The notifier `config` asks for. Fails on webhook URLs it cannot parse.
+pub fn notifier(config: &ReminderConfig) -> io::Result<Box<dyn Notifier>> {
+    Ok(match config.notifier {
+        NotifierKind::Log => Box::new(LogNotifier),
+        NotifierKind::Webhook => Box::new(WebhookNotifier::new(
+            config.webhook_url.as_deref().unwrap_or_default(),
+        )?),
+        NotifierKind::Smtp => Box::new(SmtpNotifier::new(config.smtp.clone())),
+    })
+}
+
+// Finds todos coming due and sends their reminders, each once. What was sent
+// is recorded in the store, so restarts do not send reminders again.
+pub struct Scheduler {
+    todo_db: Arc<dyn TodoRepository>,
+    reminder_db: Arc<dyn ReminderRepository>,
+    notifier: Box<dyn Notifier>,
+    offsets: Vec<u64>,
+    max_delay: TimeDelta,
+}
+
+impl Scheduler {
+    pub fn new(
+        app_state: &AppState,
+        config: &ReminderConfig,
+        notifier: Box<dyn Notifier>,
+    ) -> Scheduler {
+        Scheduler {
+            todo_db: app_state.todo_db.clone(),
+            reminder_db: app_state.reminder_db.clone(),
+            notifier,
+            offsets: config.offsets.clone(),
+            max_delay: i64::try_from(config.max_delay)
+                .ok()
+                .and_then(TimeDelta::try_seconds)
+                .unwrap_or(TimeDelta::MAX),
+        }
+    }
+
+    // Sends the reminders due at `now` that have not gone out yet and returns
+    // how many were sent. A reminder that fails to send is retried on the
+    // next scan until it is more than `max_delay` late.
+    pub fn run_once(&self, now: DateTime<Utc>) -> Result<usize, RepositoryError> {
+        // Out of range arithmetic leaves the window open on that side rather
+        // than panicking
+        let minutes = |offset: u64| {
+            i64::try_from(offset)
+                .ok()
+                .and_then(TimeDelta::try_minutes)
+                .unwrap_or(TimeDelta::MAX)
+        };
+        let earliest = self.offsets.iter().copied().min().unwrap_or_default();
+        let latest = self.offsets.iter().copied().max().unwrap_or_default();
+        // A reminder fires from `dueAt - offset` until `max_delay` after that
+        let filter = TodoFilter {
+            completed: Some(false),
+            due_from: now
+                .checked_sub_signed(self.max_delay)
+                .and_then(|from| from.checked_add_signed(minutes(earliest))),
+            due_until: now
+                .checked_add_signed(minutes(latest))
+                .and_then(|until| until.checked_add_signed(TimeDelta::seconds(1))),
+            ..TodoFilter::default()
+        };
+
+        let mut sent = 0;
+        for todo in self.todo_db.list_all(&filter)? {
+            let (Some(id), Some(due_at)) = (todo.id.clone(), todo.dueAt) else {
+                continue;
+            };
+            for &offset in &self.offsets {
+                let Some(fires_at) = due_at.checked_sub_signed(minutes(offset)) else {
+                    continue;
+                };
+                if fires_at > now || now - fires_at > self.max_delay {
+                    continue;
+                }
+                if self
+                    .reminder_db
+                    .is_reminder_sent(&SentReminder::key(&id, due_at, offset))?
+                {
+                    continue;
+                }
+                let reminder = Reminder {
+                    todo: todo.clone(),
+                    due_at,
+                    offset_minutes: offset,
+                };
+                if let Err(err) = self.notifier.notify(&reminder) {
+                    log::warn!("Could not send reminder for todo {}: {}", id, err);
+                    continue;
+                }
+                self.reminder_db.record_reminder(SentReminder {
+                    todoId: id.clone(),
+                    dueAt: due_at,
+                    offsetMinutes: offset,
+                    sentAt: Utc::now(),
+                })?;
+                sent += 1;
+            }
+        }
+        // Nothing due before this can fire any more
+        if let Some(before) = now.checked_sub_signed(self.max_delay) {
+            self.reminder_db.forget_reminders(before)?;
+        }
+        Ok(sent)
+    }
+
+    // Scans every `interval` on the current actix runtime. Scans run on the
+    // blocking thread pool since stores and notifiers block.
+    pub fn start(self, interval: Duration) {
+        let scheduler = Arc::new(self);
+        actix_web::rt::spawn(async move {
+            let mut ticks = actix_web::rt::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                let scheduler = scheduler.clone();
+                match web::block(move || scheduler.run_once(Utc::now())).await {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(sent)) => log::debug!("Sent {} reminders", sent),
+                    Ok(Err(err)) => log::error!("Reminder scan failed: {}", err),
+                    Err(err) => log::error!("Reminder scan panicked: {}", err),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+    use std::thread;
+
+    use super::*;
+    use crate::repository::SqliteStore;
+
+    // Remembers what it was asked to send
+    #[derive(Clone, Default)]
+    struct Recorder {
+        sent: Arc<Mutex<Vec<(String, u64)>>>,
+    }
+
+    impl Notifier for Recorder {
+        fn notify(&self, reminder: &Reminder) -> io::Result<()> {
+            self.sent.lock().unwrap().push((
+                reminder.todo.id.clone().unwrap_or_default(),
+                reminder.offset_minutes,
+            ));
+            Ok(())
+        }
+    }
+
+    fn todo(id: &str, due_at: DateTime<Utc>, completed: bool) -> Todo {
+        Todo {
+            id: Some(id.to_string()),
+            title: format!("Todo {}", id),
+            content: "Details".to_string(),
+            completed: Some(completed),
+            ownerId: Some("alice".to_string()),
+            dueAt: Some(due_at),
+            ..Todo::default()
+        }
+    }
+
+    fn reminder() -> Reminder {
+        Reminder {
+            todo: todo(
+                "1",
+                Utc.with_ymd_and_hms(2030, 1, 1, 9, 0, 0).unwrap(),
+                false,
+            ),
+            due_at: Utc.with_ymd_and_hms(2030, 1, 1, 9, 0, 0).unwrap(),
+            offset_minutes: 15,
+        }
+    }
+
+    #[test]
+    fn scheduler_sends_each_reminder_once_test() {
+        let sqlite = SqliteStore::open(":memory:").unwrap();
+        for app_state in [AppState::init(), AppState::new(Arc::new(sqlite))] {
+            let now = Utc.with_ymd_and_hms(2030, 1, 1, 9, 0, 0).unwrap();
+            let minutes = TimeDelta::minutes;
+            for todo in [
+                todo("soon", now + minutes(30), false),
+                todo("later", now + minutes(90), false),
+                todo("done", now + minutes(30), true),
+                todo("missed", now - minutes(120), false),
+            ] {
+                app_state.todo_db.create(todo).unwrap();
+            }
+            let config = ReminderConfig::default();
+            let recorder = Recorder::default();
+            let scheduler = Scheduler::new(&app_state, &config, Box::new(recorder.clone()));
+
+            // The hour-before reminder for "soon" is due, "later" is not yet,
+            // "missed" is past `max_delay`
+            assert_eq!(scheduler.run_once(now).unwrap(), 1);
+            assert_eq!(scheduler.run_once(now + minutes(1)).unwrap(), 0);
+
+            // A new scheduler on the same store, as after a restart
+            let scheduler = Scheduler::new(&app_state, &config, Box::new(recorder.clone()));
+            assert_eq!(scheduler.run_once(now + minutes(2)).unwrap(), 0);
+            assert_eq!(scheduler.run_once(now + minutes(31)).unwrap(), 2);
+            assert_eq!(scheduler.run_once(now + minutes(32)).unwrap(), 0);
+
+            assert_eq!(
+                *recorder.sent.lock().unwrap(),
+                [
+                    ("soon".to_string(), 60),
+                    ("soon".to_string(), 0),
+                    ("later".to_string(), 60),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn scheduler_survives_out_of_range_settings_test() {
+        let app_state = AppState::init();
+        let now = Utc.with_ymd_and_hms(2030, 1, 1, 9, 0, 0).unwrap();
+        let soon = todo("soon", now + TimeDelta::minutes(30), false);
+        app_state.todo_db.create(soon).unwrap();
+        let config = ReminderConfig {
+            offsets: vec![u64::MAX, 30],
+            max_delay: u64::MAX,
+            ..ReminderConfig::default()
+        };
+        let scheduler = Scheduler::new(&app_state, &config, Box::new(Recorder::default()));
+        assert_eq!(scheduler.run_once(now).unwrap(), 1);
+    }
+
+    #[test]
+    fn webhook_notifier_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in ["204 No Content", "500 Internal Server Error"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                let mut buffer = [0; 4096];
+                // Read until the whole JSON body has arrived
+                while !request.ends_with('}') {
+                    let read = stream.read(&mut buffer).unwrap();
+                    request.push_str(std::str::from_utf8(&buffer[..read]).unwrap());
+                }
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+
+        let notifier = WebhookNotifier::new(&format!("http://127.0.0.1:{}/hooks", port)).unwrap();
+        notifier.notify(&reminder()).unwrap();
+        assert!(notifier.notify(&reminder()).is_err());
+
+        let requests = server.join().unwrap();
+        let (head, body) = requests[0].split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("POST /hooks HTTP/1.1\r\n"));
+        assert!(head.contains("Content-Type: application/json"));
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["event"], "reminder");
+        assert_eq!(body["offsetMinutes"], 15);
+        assert_eq!(body["todo"]["id"], "1");
+
+        assert!(WebhookNotifier::new("https://example.com/hooks").is_err());
+        assert!(WebhookNotifier::new("http://:80/").is_err());
+    }
+
+    #[test]
+    fn smtp_notifier_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut transcript = Vec::new();
+            writer
+                .write_all(b"220-test.local ESMTP\r\n220 ready\r\n")
+                .unwrap();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_end_matches("\r\n").to_string();
+                let reply: &[u8] = if in_data {
+                    if line == "." {
+                        in_data = false;
+                        b"250 queued\r\n"
+                    } else {
+                        b""
+                    }
+                } else if line == "DATA" {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line == "QUIT" {
+                    b"221 bye\r\n"
+                } else {
+                    b"250 ok\r\n"
+                };
+                writer.write_all(reply).unwrap();
+                transcript.push(line);
+            }
+            transcript
+        });
+
+        let notifier = SmtpNotifier::new(SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            from: "todos@test.local".to_string(),
+            to: vec!["alice@test.local".to_string(), "bob@test.local".to_string()],
+        });
+        let mut reminder = reminder();
+        reminder.todo.content = "First line\n.hidden dot".to_string();
+        notifier.notify(&reminder).unwrap();
+
+        let transcript = server.join().unwrap();
+        assert_eq!(
+            transcript[..5],
+            [
+                "HELO localhost",
+                "MAIL FROM:<todos@test.local>",
+                "RCPT TO:<alice@test.local>",
+                "RCPT TO:<bob@test.local>",
+                "DATA",
+            ]
+        );
+        assert!(transcript.contains(&"Subject: Reminder: Todo 1".to_string()));
+        assert!(transcript.contains(&"\"Todo 1\" is due in 15 minutes".to_string()));
+        assert!(transcript.contains(&"..hidden dot".to_string()));
+        assert_eq!(transcript[transcript.len() - 2..], [".", "QUIT"]);
+    }
+}
@@ -0,0 +1,552 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+
+use crate::model::{ApiKey, List, SentReminder, Todo, User};
+
+#[cfg(feature = "kv")]
+pub mod kv;
+pub mod memory;
+pub mod sqlite;
+pub mod wal;
+
+#[cfg(feature = "kv")]
+pub use kv::KvStore;
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+pub use wal::WalStore;
+
+#[derive(Debug)]
+pub enum RepositoryError {
+    // A uniqueness rule was violated, e.g. a username that is already taken
+    Conflict(String),
+    Storage(String),
+}
+
+impl fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepositoryError::Conflict(message) => write!(f, "{}", message),
+            RepositoryError::Storage(message) => write!(f, "Storage error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for RepositoryError {}
+
+impl RepositoryError {
+    pub fn username_taken(username: &str) -> Self {
+        RepositoryError::Conflict(format!("Username {:?} is already taken.", username))
+    }
+
+    pub fn todo_id_taken(id: &str) -> Self {
+        RepositoryError::Conflict(format!("Todo id {:?} is already taken.", id))
+    }
+}
+
+impl From<std::io::Error> for RepositoryError {
+    fn from(err: std::io::Error) -> Self {
+        RepositoryError::Storage(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for RepositoryError {
+    fn from(err: serde_json::Error) -> Self {
+        RepositoryError::Storage(err.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for RepositoryError {
+    fn from(err: rusqlite::Error) -> Self {
+        RepositoryError::Storage(err.to_string())
+    }
+}
+
+// redb reports each stage (open, transaction, table, storage, commit) with
+// its own error type.
+#[cfg(feature = "kv")]
+macro_rules! impl_from_redb_error {
+    ($($err:ty),*) => {
+        $(
+            impl From<$err> for RepositoryError {
+                fn from(err: $err) -> Self {
+                    RepositoryError::Storage(err.to_string())
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "kv")]
+impl_from_redb_error!(
+    redb::DatabaseError,
+    redb::TransactionError,
+    redb::TableError,
+    redb::StorageError,
+    redb::CommitError
+);
+
+// A window of todos plus the number of todos matching the filter in total
+#[derive(Debug)]
+pub struct Page {
+    pub items: Vec<Todo>,
+    pub total: usize,
+    // Whether matching todos come after the window
+    pub has_more: bool,
+    // Whether matching todos come before the window
+    pub has_previous: bool,
+}
+
+impl Page {
+    // Filters, sorts and windows `todos`. Every backend lists through this, so
+    // a query returns the same todos in the same order whichever store is in
+    // use.
+    pub fn collect<I>(todos: I, query: &TodoQuery) -> Result<Page, RepositoryError>
+    where
+        I: IntoIterator<Item = Result<Todo, RepositoryError>>,
+    {
+        let mut matching = Vec::new();
+        for todo in todos {
+            let todo = todo?;
+            if query.filter.matches(&todo) {
+                matching.push(todo);
+            }
+        }
+        matching.sort_by(|a, b| query.compare(a, b));
+
+        let total = matching.len();
+        let (start, end) = match (&query.after, &query.before) {
+            (Some(after), _) => {
+                let start = matching.partition_point(|todo| query.compare(todo, after).is_le());
+                (start, start.saturating_add(query.limit).min(total))
+            }
+            (None, Some(before)) => {
+                let end = matching.partition_point(|todo| query.compare(todo, before).is_lt());
+                (end.saturating_sub(query.limit), end)
+            }
+            (None, None) => {
+                let start = query.offset.min(total);
+                (start, start.saturating_add(query.limit).min(total))
+            }
+        };
+        let items = matching.drain(start..end).collect();
+        Ok(Page {
+            items,
+            total,
+            has_more: end < total,
+            has_previous: start > 0,
+        })
+    }
+}
+
+// Creation order, the default when no sort is asked for
+const CREATION_ORDER: &[SortKey] = &[SortKey {
+    field: SortField::CreatedAt,
+    descending: false,
+}];
+
+// What to list: which todos, in what order, and which window of them
+#[derive(Debug, Clone)]
+pub struct TodoQuery {
+    pub filter: TodoFilter,
+    // Empty for creation order
+    pub sort: Vec<SortKey>,
+    pub offset: usize,
+    pub limit: usize,
+    // Keyset bounds decoded from a cursor: the window starts right after
+    // `after`, or ends right before `before`, and `offset` is ignored. Only
+    // the id and the sorted-on fields of these todos are meaningful.
+    pub after: Option<Todo>,
+    pub before: Option<Todo>,
+}
+
+impl TodoQuery {
+    pub fn sort_keys(&self) -> &[SortKey] {
+        if self.sort.is_empty() {
+            CREATION_ORDER
+        } else {
+            &self.sort
+        }
+    }
+
+    // The listing order: the sort keys, then id so the order is total
+    pub fn compare(&self, a: &Todo, b: &Todo) -> Ordering {
+        self.sort_keys()
+            .iter()
+            .map(|key| key.compare(a, b))
+            .fold(Ordering::Equal, Ordering::then)
+            .then_with(|| a.id.cmp(&b.id))
+    }
+}
+
+impl Default for TodoQuery {
+    fn default() -> Self {
+        TodoQuery {
+            filter: TodoFilter::default(),
+            sort: Vec::new(),
+            offset: 0,
+            limit: usize::MAX,
+            after: None,
+            before: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    CreatedAt,
+    UpdatedAt,
+    Title,
+    Completed,
+    DueAt,
+    Priority,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+impl SortKey {
+    // Parses a comma separated list such as "-completed,createdAt". A leading
+    // '-' sorts that key descending; bare keys use `descending`.
+    pub fn parse_list(spec: &str, descending: bool) -> Result<Vec<SortKey>, String> {
+        spec.split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(|key| {
+                let (name, descending) = match key.strip_prefix('-') {
+                    Some(name) => (name, true),
+                    None => (key, descending),
+                };
+                let field = match name {
+                    "createdAt" => SortField::CreatedAt,
+                    "updatedAt" => SortField::UpdatedAt,
+                    "title" => SortField::Title,
+                    "completed" => SortField::Completed,
+                    "dueAt" => SortField::DueAt,
+                    "priority" => SortField::Priority,
+                    _ => return Err(format!("Unknown sort field {:?}.", name)),
+                };
+                Ok(SortKey { field, descending })
+            })
+            .collect()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.field {
+            SortField::CreatedAt => "createdAt",
+            SortField::UpdatedAt => "updatedAt",
+            SortField::Title => "title",
+            SortField::Completed => "completed",
+            SortField::DueAt => "dueAt",
+            SortField::Priority => "priority",
+        }
+    }
+
+    // Missing timestamps and priorities sort before present ones; titles compare
+    // case-insensitively first so "apple" and "Banana" sort as expected.
+    fn compare(&self, a: &Todo, b: &Todo) -> Ordering {
+        let ordering = match self.field {
+            SortField::CreatedAt => a.createdAt.cmp(&b.createdAt),
+            SortField::UpdatedAt => a.updatedAt.cmp(&b.updatedAt),
+            SortField::Title => a
+                .title
+                .to_lowercase()
+                .cmp(&b.title.to_lowercase())
+                .then_with(|| a.title.cmp(&b.title)),
+            SortField::Completed => a
+                .completed
+                .unwrap_or(false)
+                .cmp(&b.completed.unwrap_or(false)),
+            SortField::DueAt => a.dueAt.cmp(&b.dueAt),
+            SortField::Priority => a.priority.cmp(&b.priority),
+        };
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+// Narrows a todo listing. Unset fields match everything; a todo without the
+// timestamp a range asks about never matches it.
+#[derive(Debug, Default, Clone)]
+pub struct TodoFilter {
+    pub completed: Option<bool>,
+    // Case-insensitive substring of the title or content
+    pub text: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_since: Option<DateTime<Utc>>,
+    // A todo needs any of these tags, or all of them with `all_tags`
+    pub tags: Vec<String>,
+    pub all_tags: bool,
+    // Due at or after `due_from` and before `due_until`
+    pub due_from: Option<DateTime<Utc>>,
+    pub due_until: Option<DateTime<Utc>>,
+    // Whether the todo is overdue at `now`, or at the time of the check
+    pub overdue: Option<bool>,
+    pub now: Option<DateTime<Utc>>,
+    // Direct subtasks of this todo
+    pub parent_id: Option<String>,
+    // Todos filed under this list
+    pub list_id: Option<String>,
+    // Todos in the trash instead of the live ones
+    pub deleted: bool,
+}
+
+impl TodoFilter {
+    pub fn matches(&self, todo: &Todo) -> bool {
+        if todo.deletedAt.is_some() != self.deleted {
+            return false;
+        }
+        if let Some(completed) = self.completed {
+            if todo.completed.unwrap_or(false) != completed {
+                return false;
+            }
+        }
+        if let Some(text) = &self.text {
+            let text = text.to_lowercase();
+            if !todo.title.to_lowercase().contains(&text)
+                && !todo.content.to_lowercase().contains(&text)
+            {
+                return false;
+            }
+        }
+        if let Some(parent_id) = &self.parent_id {
+            if todo.parentId.as_ref() != Some(parent_id) {
+                return false;
+            }
+        }
+        if let Some(list_id) = &self.list_id {
+            if todo.listId.as_ref() != Some(list_id) {
+                return false;
+            }
+        }
+        if !self.tags.is_empty() {
+            let tagged = |tag: &String| todo.tags.contains(tag);
+            let tagged = if self.all_tags {
+                self.tags.iter().all(tagged)
+            } else {
+                self.tags.iter().any(tagged)
+            };
+            if !tagged {
+                return false;
+            }
+        }
+        if let Some(overdue) = self.overdue {
+            if todo.is_overdue(self.now.unwrap_or_else(Utc::now)) != overdue {
+                return false;
+            }
+        }
+        let within = |bound: Option<DateTime<Utc>>,
+                      value: Option<DateTime<Utc>>,
+                      ok: fn(&DateTime<Utc>, &DateTime<Utc>) -> bool| {
+            bound.is_none_or(|bound| value.is_some_and(|value| ok(&value, &bound)))
+        };
+        within(self.created_after, todo.createdAt, |value, bound| {
+            value > bound
+        }) && within(self.created_before, todo.createdAt, |value, bound| {
+            value < bound
+        }) && within(self.updated_since, todo.updatedAt, |value, bound| {
+            value >= bound
+        }) && within(self.due_from, todo.dueAt, |value, bound| value >= bound)
+            && within(self.due_until, todo.dueAt, |value, bound| value < bound)
+    }
+}
+
+// Storage used by the todo handlers. Implementations own their locking, so
+// handlers never hold a lock across calls.
+//
+// Every read and write is scoped to `owner`: a todo owned by someone else
+// behaves exactly like one that does not exist.
+pub trait TodoRepository: Send + Sync {
+    // Fails with `Conflict` when any owner already has a todo with this id.
+    fn create(&self, todo: Todo) -> Result<Todo, RepositoryError>;
+
+    fn get(&self, owner: &str, id: &str) -> Result<Option<Todo>, RepositoryError>;
+
+    // Lists the owner's todos as `query` asks, by way of `Page::collect`.
+    fn list(&self, owner: &str, query: &TodoQuery) -> Result<Page, RepositoryError>;
+
+    // Applies `apply` to the stored todo atomically and returns the result,
+    // or `None` if `owner` has no todo with the given id.
+    fn update(
+        &self,
+        owner: &str,
+        id: &str,
+        apply: &mut dyn FnMut(&mut Todo),
+    ) -> Result<Option<Todo>, RepositoryError>;
+
+    // Applies `apply` to each of the owner's todos in `ids`, in that order, as
+    // one atomic write and returns the results in the same order, skipping ids
+    // the owner has no todo for.
+    fn update_many(
+        &self,
+        owner: &str,
+        ids: &[String],
+        apply: &mut dyn FnMut(&mut Todo),
+    ) -> Result<Vec<Todo>, RepositoryError>;
+
+    // Returns whether `owner` had a todo with the given id.
+    fn delete(&self, owner: &str, id: &str) -> Result<bool, RepositoryError>;
+
+    // Deletes the todo only if it went into the trash at or before `cutoff`,
+    // checked in the same step as the delete, so a todo restored in between
+    // stays. Returns whether it was deleted.
+    fn delete_if_deleted_before(
+        &self,
+        owner: &str,
+        id: &str,
+        cutoff: DateTime<Utc>,
+    ) -> Result<bool, RepositoryError>;
+
+    // The todos of every owner matching `filter`, for background jobs such as
+    // reminders. Request handlers use the owner-scoped calls above.
+    fn list_all(&self, filter: &TodoFilter) -> Result<Vec<Todo>, RepositoryError>;
+
+    // The latest updatedAt among the owner's todos, counting those in the
+    // trash, without reading the todos themselves. Stores that track it as
+    // todos are written may keep reporting it after the todo is purged.
+    fn max_updated_at(&self, owner: &str) -> Result<Option<DateTime<Utc>>, RepositoryError>;
+}
+
+// Storage for lists, each owned by one user. Lists come back in creation order.
+pub trait ListRepository: Send + Sync {
+    fn create_list(&self, list: List) -> Result<List, RepositoryError>;
+
+    fn get_list(&self, owner: &str, id: &str) -> Result<Option<List>, RepositoryError>;
+
+    fn list_lists(&self, owner: &str) -> Result<Vec<List>, RepositoryError>;
+
+    // Applies `apply` to the stored list atomically, like `TodoRepository::update`.
+    fn update_list(
+        &self,
+        owner: &str,
+        id: &str,
+        apply: &mut dyn FnMut(&mut List),
+    ) -> Result<Option<List>, RepositoryError>;
+
+    // Returns whether `owner` had a list with the given id.
+    fn delete_list(&self, owner: &str, id: &str) -> Result<bool, RepositoryError>;
+}
+
+// Storage for accounts and for tokens revoked before they expire.
+pub trait UserRepository: Send + Sync {
+    // Fails with `Conflict` when the username is taken.
+    fn create_user(&self, user: User) -> Result<User, RepositoryError>;
+
+    fn get_user(&self, id: &str) -> Result<Option<User>, RepositoryError>;
+
+    fn find_user_by_username(&self, username: &str) -> Result<Option<User>, RepositoryError>;
+
+    // Records a token id as revoked. Entries may be forgotten once
+    // `expires_at` has passed, since the token is rejected anyway by then.
+    fn revoke_token(
+        &self,
+        token_id: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError>;
+
+    fn is_token_revoked(&self, token_id: &str) -> Result<bool, RepositoryError>;
+}
+
+// Storage for API keys, looked up by the hash of the key.
+pub trait ApiKeyRepository: Send + Sync {
+    fn create_api_key(&self, key: ApiKey) -> Result<ApiKey, RepositoryError>;
+
+    fn list_api_keys(&self, owner: &str) -> Result<Vec<ApiKey>, RepositoryError>;
+
+    fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, RepositoryError>;
+
+    // Returns whether `owner` had a key with the given id.
+    fn delete_api_key(&self, owner: &str, id: &str) -> Result<bool, RepositoryError>;
+}
+
+// Storage for the reminders that have been sent, so a restart does not send
+// them again. Reminders are looked up by `SentReminder::key`.
+pub trait ReminderRepository: Send + Sync {
+    fn is_reminder_sent(&self, key: &str) -> Result<bool, RepositoryError>;
+
+    fn record_reminder(&self, reminder: SentReminder) -> Result<(), RepositoryError>;
+
+    // Drops the records of reminders for todos due before `before`, once
+    // those reminders can no longer fire.
+    fn forget_reminders(&self, before: DateTime<Utc>) -> Result<(), RepositoryError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_breaks_ties_on_id_test() {
+        let todo = |id: &str, completed: bool| Todo {
+            id: Some(id.to_string()),
+            title: "Same".to_string(),
+            content: "Test Todo Content".to_string(),
+            completed: Some(completed),
+            ..Todo::default()
+        };
+        let todos = [todo("c", false), todo("a", true), todo("b", false)];
+        let query = TodoQuery {
+            sort: SortKey::parse_list("title,completed", false).unwrap(),
+            ..TodoQuery::default()
+        };
+        let page = Page::collect(todos.into_iter().map(Ok), &query).unwrap();
+        let ids: Vec<&str> = page
+            .items
+            .iter()
+            .filter_map(|todo| todo.id.as_deref())
+            .collect();
+        assert_eq!(ids, ["b", "c", "a"]);
+        assert_eq!(page.total, 3);
+    }
+
+    #[test]
+    fn max_updated_at_test() {
+        use chrono::{Duration, TimeZone};
+
+        let at = |minutes| Utc.with_ymd_and_hms(2030, 1, 1, 9, minutes, 0).unwrap();
+        #[allow(unused_mut)]
+        let mut stores: Vec<Box<dyn TodoRepository>> = vec![
+            Box::new(MemoryStore::new()),
+            Box::new(SqliteStore::open(":memory:").unwrap()),
+        ];
+        #[cfg(feature = "kv")]
+        stores.push(Box::new(
+            KvStore::open(
+                std::env::temp_dir().join(format!("todos-{}.redb", uuid::Uuid::new_v4())),
+            )
+            .unwrap(),
+        ));
+        for store in stores {
+            assert_eq!(store.max_updated_at("alice").unwrap(), None);
+            for (id, owner, minutes) in [("a", "alice", 5), ("b", "alice", 1), ("c", "bob", 9)] {
+                let todo = Todo {
+                    id: Some(id.to_string()),
+                    ownerId: Some(owner.to_string()),
+                    updatedAt: Some(at(minutes)),
+                    ..Todo::default()
+                };
+                store.create(todo).unwrap();
+            }
+            assert_eq!(store.max_updated_at("alice").unwrap(), Some(at(5)));
+
+            // Trashing counts as a change, down to a fraction of a second
+            let trashed_at = at(5) + Duration::milliseconds(500);
+            store
+                .update("alice", "b", &mut |todo| {
+                    todo.deletedAt = Some(trashed_at);
+                    todo.updatedAt = Some(trashed_at);
+                })
+                .unwrap();
+            assert_eq!(store.max_updated_at("alice").unwrap(), Some(trashed_at));
+            assert_eq!(store.max_updated_at("bob").unwrap(), Some(at(9)));
+        }
+    }
+}
@@ -0,0 +1,780 @@
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use redb::{Database, ReadableTable, ReadableTableMetadata, TableDefinition, WriteTransaction};
+use serde::{Deserialize, Serialize};
+
+use crate::model::{ApiKey, List, SentReminder, Todo, User};
+use crate::repository::{
+    ApiKeyRepository, ListRepository, Page, ReminderRepository, RepositoryError, TodoFilter,
+    TodoQuery, TodoRepository, UserRepository,
+};
+
+// id -> JSON encoded StoredTodo
+const TODOS: TableDefinition<&str, &[u8]> = TableDefinition::new("todos");
+// insertion sequence -> id, which hands out the next sequence number
+const TODO_ORDER: TableDefinition<u64, &str> = TableDefinition::new("todo_order");
+// (owner, insertion sequence) -> id, so paging walks one user's todos in creation order
+const TODOS_BY_OWNER: TableDefinition<(&str, u64), &str> = TableDefinition::new("todos_by_owner");
+// owner -> latest updatedAt written for their todos, in unix microseconds
+const LATEST_UPDATES: TableDefinition<&str, i64> = TableDefinition::new("latest_updates");
+// id -> JSON encoded List
+const LISTS: TableDefinition<&str, &[u8]> = TableDefinition::new("lists");
+// id -> JSON encoded User
+const USERS: TableDefinition<&str, &[u8]> = TableDefinition::new("users");
+// username -> id, which also keeps usernames unique
+const USERNAMES: TableDefinition<&str, &str> = TableDefinition::new("usernames");
+// revoked token id -> expiry as a unix timestamp
+const REVOKED_TOKENS: TableDefinition<&str, i64> = TableDefinition::new("revoked_tokens");
+// id -> JSON encoded ApiKey
+const API_KEYS: TableDefinition<&str, &[u8]> = TableDefinition::new("api_keys");
+// key hash -> id
+const API_KEY_HASHES: TableDefinition<&str, &str> = TableDefinition::new("api_key_hashes");
+// SentReminder::key -> JSON encoded SentReminder
+const SENT_REMINDERS: TableDefinition<&str, &[u8]> = TableDefinition::new("sent_reminders");
+
+#[derive(Serialize, Deserialize)]
+struct StoredTodo {
+    seq: u64,
+    todo: Todo,
+}
+
+// Persists todos, lists, users and API keys in a redb file keyed by id.
+// Every mutation is one write transaction and redb only acknowledges a commit
+// once it is durable on disk, so a process stopped part way through a
+// mutation leaves the file at the last committed state.
+pub struct KvStore {
+    db: Database,
+}
+
+impl KvStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, RepositoryError> {
+        let db = Database::create(path)?;
+        let txn = db.begin_write()?;
+        txn.open_table(TODOS)?;
+        txn.open_table(TODO_ORDER)?;
+        txn.open_table(TODOS_BY_OWNER)?;
+        txn.open_table(LISTS)?;
+        txn.open_table(USERS)?;
+        txn.open_table(USERNAMES)?;
+        txn.open_table(REVOKED_TOKENS)?;
+        txn.open_table(API_KEYS)?;
+        txn.open_table(API_KEY_HASHES)?;
+        txn.open_table(SENT_REMINDERS)?;
+        // Files from before the table existed start it off from their todos
+        if txn.open_table(LATEST_UPDATES)?.is_empty()? {
+            let todos = txn.open_table(TODOS)?;
+            for entry in todos.iter()? {
+                let (_, bytes) = entry?;
+                note_update(&txn, &decode(bytes.value())?.todo)?;
+            }
+        }
+        txn.commit()?;
+        Ok(KvStore { db })
+    }
+
+    // Gives `owner` the todos saved before todos had owners and returns how
+    // many there were
+    pub fn adopt_unowned_todos(&self, owner: &str) -> Result<usize, RepositoryError> {
+        let txn = self.db.begin_write()?;
+        let adopted = {
+            let mut todos = txn.open_table(TODOS)?;
+            let mut unowned = Vec::new();
+            for entry in todos.iter()? {
+                let (_, bytes) = entry?;
+                let stored = decode(bytes.value())?;
+                if stored.todo.ownerId.is_none() {
+                    unowned.push(stored);
+                }
+            }
+            let mut by_owner = txn.open_table(TODOS_BY_OWNER)?;
+            for stored in &mut unowned {
+                stored.todo.ownerId = Some(owner.to_string());
+                let id = todo_id(&stored.todo)?;
+                todos.insert(id, encode(stored)?.as_slice())?;
+                by_owner.insert((owner, stored.seq), id)?;
+                note_update(&txn, &stored.todo)?;
+            }
+            unowned.len()
+        };
+        txn.commit()?;
+        Ok(adopted)
+    }
+}
+
+fn decode(bytes: &[u8]) -> Result<StoredTodo, RepositoryError> {
+    Ok(serde_json::from_slice(bytes)?)
+}
+
+fn encode(stored: &StoredTodo) -> Result<Vec<u8>, RepositoryError> {
+    Ok(serde_json::to_vec(stored)?)
+}
+
+// Reads the todo stored under `id` if it belongs to `owner`
+fn owned_todo(
+    todos: &impl ReadableTable<&'static str, &'static [u8]>,
+    owner: &str,
+    id: &str,
+) -> Result<Option<StoredTodo>, RepositoryError> {
+    let stored = todos
+        .get(id)?
+        .map(|bytes| decode(bytes.value()))
+        .transpose()?;
+    Ok(stored.filter(|stored| stored.todo.ownerId.as_deref() == Some(owner)))
+}
+
+// Moves the owner's latest update up to the todo's, if it is later
+fn note_update(txn: &WriteTransaction, todo: &Todo) -> Result<(), RepositoryError> {
+    let (Some(owner), Some(updated_at)) = (todo.ownerId.as_deref(), todo.updatedAt) else {
+        return Ok(());
+    };
+    let updated_at = updated_at.timestamp_micros();
+    let mut latest = txn.open_table(LATEST_UPDATES)?;
+    let current = latest.get(owner)?.map(|micros| micros.value());
+    if current.is_none_or(|current| current < updated_at) {
+        latest.insert(owner, updated_at)?;
+    }
+    Ok(())
+}
+
+fn todo_id(todo: &Todo) -> Result<&str, RepositoryError> {
+    todo.id
+        .as_deref()
+        .ok_or_else(|| RepositoryError::Storage("todo has no id".to_string()))
+}
+
+impl TodoRepository for KvStore {
+    fn create(&self, todo: Todo) -> Result<Todo, RepositoryError> {
+        let id = todo_id(&todo)?;
+        let txn = self.db.begin_write()?;
+        {
+            if txn.open_table(TODOS)?.get(id)?.is_some() {
+                return Err(RepositoryError::todo_id_taken(id));
+            }
+            let mut order = txn.open_table(TODO_ORDER)?;
+            let seq = match order.last()? {
+                Some((seq, _)) => seq.value() + 1,
+                None => 0,
+            };
+            order.insert(seq, id)?;
+            if let Some(owner) = todo.ownerId.as_deref() {
+                txn.open_table(TODOS_BY_OWNER)?.insert((owner, seq), id)?;
+            }
+            let stored = StoredTodo {
+                seq,
+                todo: todo.clone(),
+            };
+            txn.open_table(TODOS)?
+                .insert(id, encode(&stored)?.as_slice())?;
+            note_update(&txn, &todo)?;
+        }
+        txn.commit()?;
+        Ok(todo)
+    }
+
+    fn get(&self, owner: &str, id: &str) -> Result<Option<Todo>, RepositoryError> {
+        let txn = self.db.begin_read()?;
+        let todos = txn.open_table(TODOS)?;
+        Ok(owned_todo(&todos, owner, id)?.map(|stored| stored.todo))
+    }
+
+    fn list(&self, owner: &str, query: &TodoQuery) -> Result<Page, RepositoryError> {
+        let txn = self.db.begin_read()?;
+        let todos = txn.open_table(TODOS)?;
+        let by_owner = txn.open_table(TODOS_BY_OWNER)?;
+        let owned = by_owner
+            .range((owner, 0)..=(owner, u64::MAX))?
+            .filter_map(|entry| {
+                let todo = entry.map_err(RepositoryError::from).and_then(|(_, id)| {
+                    let bytes = todos.get(id.value())?;
+                    bytes
+                        .map(|bytes| decode(bytes.value()).map(|stored| stored.todo))
+                        .transpose()
+                });
+                todo.transpose()
+            });
+        Page::collect(owned, query)
+    }
+
+    fn update(
+        &self,
+        owner: &str,
+        id: &str,
+        apply: &mut dyn FnMut(&mut Todo),
+    ) -> Result<Option<Todo>, RepositoryError> {
+        let txn = self.db.begin_write()?;
+        let todo = {
+            let mut todos = txn.open_table(TODOS)?;
+            let Some(mut stored) = owned_todo(&todos, owner, id)? else {
+                return Ok(None);
+            };
+            apply(&mut stored.todo);
+            todos.insert(id, encode(&stored)?.as_slice())?;
+            note_update(&txn, &stored.todo)?;
+            stored.todo
+        };
+        txn.commit()?;
+        Ok(Some(todo))
+    }
+
+    fn update_many(
+        &self,
+        owner: &str,
+        ids: &[String],
+        apply: &mut dyn FnMut(&mut Todo),
+    ) -> Result<Vec<Todo>, RepositoryError> {
+        let txn = self.db.begin_write()?;
+        let mut updated = Vec::with_capacity(ids.len());
+        {
+            let mut todos = txn.open_table(TODOS)?;
+            for id in ids {
+                let Some(mut stored) = owned_todo(&todos, owner, id)? else {
+                    continue;
+                };
+                apply(&mut stored.todo);
+                todos.insert(id.as_str(), encode(&stored)?.as_slice())?;
+                note_update(&txn, &stored.todo)?;
+                updated.push(stored.todo);
+            }
+        }
+        txn.commit()?;
+        Ok(updated)
+    }
+
+    fn delete(&self, owner: &str, id: &str) -> Result<bool, RepositoryError> {
+        let txn = self.db.begin_write()?;
+        let deleted = {
+            let mut todos = txn.open_table(TODOS)?;
+            let stored = owned_todo(&todos, owner, id)?;
+            if let Some(stored) = &stored {
+                todos.remove(id)?;
+                txn.open_table(TODO_ORDER)?.remove(stored.seq)?;
+                txn.open_table(TODOS_BY_OWNER)?
+                    .remove((owner, stored.seq))?;
+            }
+            stored.is_some()
+        };
+        txn.commit()?;
+        Ok(deleted)
+    }
+
+    fn delete_if_deleted_before(
+        &self,
+        owner: &str,
+        id: &str,
+        cutoff: DateTime<Utc>,
+    ) -> Result<bool, RepositoryError> {
+        let txn = self.db.begin_write()?;
+        let deleted = {
+            let mut todos = txn.open_table(TODOS)?;
+            let stored = owned_todo(&todos, owner, id)?.filter(|stored| {
+                stored
+                    .todo
+                    .deletedAt
+                    .is_some_and(|deleted_at| deleted_at <= cutoff)
+            });
+            if let Some(stored) = &stored {
+                todos.remove(id)?;
+                txn.open_table(TODO_ORDER)?.remove(stored.seq)?;
+                txn.open_table(TODOS_BY_OWNER)?
+                    .remove((owner, stored.seq))?;
+            }
+            stored.is_some()
+        };
+        txn.commit()?;
+        Ok(deleted)
+    }
+
+    fn list_all(&self, filter: &TodoFilter) -> Result<Vec<Todo>, RepositoryError> {
+        let txn = self.db.begin_read()?;
+        let order = txn.open_table(TODO_ORDER)?;
+        let todos = txn.open_table(TODOS)?;
+        let mut matching = Vec::new();
+        for entry in order.iter()? {
+            let (_, id) = entry?;
+            if let Some(bytes) = todos.get(id.value())? {
+                let todo = decode(bytes.value())?.todo;
+                if filter.matches(&todo) {
+                    matching.push(todo);
+                }
+            }
+        }
+        Ok(matching)
+    }
+
+    fn max_updated_at(&self, owner: &str) -> Result<Option<DateTime<Utc>>, RepositoryError> {
+        let txn = self.db.begin_read()?;
+        let latest = txn.open_table(LATEST_UPDATES)?;
+        let micros = latest.get(owner)?.map(|micros| micros.value());
+        Ok(micros.and_then(DateTime::from_timestamp_micros))
+    }
+}
+
+// Reads the list stored under `id` if it belongs to `owner`
+fn owned_list(
+    lists: &impl ReadableTable<&'static str, &'static [u8]>,
+    owner: &str,
+    id: &str,
+) -> Result<Option<List>, RepositoryError> {
+    let list: Option<List> = lists
+        .get(id)?
+        .map(|bytes| serde_json::from_slice(bytes.value()))
+        .transpose()?;
+    Ok(list.filter(|list| list.ownerId == owner))
+}
+
+impl ListRepository for KvStore {
+    fn create_list(&self, list: List) -> Result<List, RepositoryError> {
+        let txn = self.db.begin_write()?;
+        {
+            let bytes = serde_json::to_vec(&list)?;
+            txn.open_table(LISTS)?
+                .insert(list.id.as_str(), bytes.as_slice())?;
+        }
+        txn.commit()?;
+        Ok(list)
+    }
+
+    fn get_list(&self, owner: &str, id: &str) -> Result<Option<List>, RepositoryError> {
+        let txn = self.db.begin_read()?;
+        let lists = txn.open_table(LISTS)?;
+        owned_list(&lists, owner, id)
+    }
+
+    // Like API keys, lists are few per user and are scanned rather than indexed
+    fn list_lists(&self, owner: &str) -> Result<Vec<List>, RepositoryError> {
+        let txn = self.db.begin_read()?;
+        let lists = txn.open_table(LISTS)?;
+        let mut owned = Vec::new();
+        for entry in lists.iter()? {
+            let (_, bytes) = entry?;
+            let list: List = serde_json::from_slice(bytes.value())?;
+            if list.ownerId == owner {
+                owned.push(list);
+            }
+        }
+        owned.sort_by(|a, b| a.createdAt.cmp(&b.createdAt).then_with(|| a.id.cmp(&b.id)));
+        Ok(owned)
+    }
+
+    fn update_list(
+        &self,
+        owner: &str,
+        id: &str,
+        apply: &mut dyn FnMut(&mut List),
+    ) -> Result<Option<List>, RepositoryError> {
+        let txn = self.db.begin_write()?;
+        let list = {
+            let mut lists = txn.open_table(LISTS)?;
+            let Some(mut list) = owned_list(&lists, owner, id)? else {
+                return Ok(None);
+            };
+            apply(&mut list);
+            let bytes = serde_json::to_vec(&list)?;
+            lists.insert(id, bytes.as_slice())?;
+            list
+        };
+        txn.commit()?;
+        Ok(Some(list))
+    }
+
+    fn delete_list(&self, owner: &str, id: &str) -> Result<bool, RepositoryError> {
+        let txn = self.db.begin_write()?;
+        let deleted = {
+            let mut lists = txn.open_table(LISTS)?;
+            let owned = owned_list(&lists, owner, id)?.is_some();
+            if owned {
+                lists.remove(id)?;
+            }
+            owned
+        };
+        txn.commit()?;
+        Ok(deleted)
+    }
+}
+
+impl UserRepository for KvStore {
+    fn create_user(&self, user: User) -> Result<User, RepositoryError> {
+        let txn = self.db.begin_write()?;
+        {
+            let mut usernames = txn.open_table(USERNAMES)?;
+            if usernames.get(user.username.as_str())?.is_some() {
+                return Err(RepositoryError::username_taken(&user.username));
+            }
+            usernames.insert(user.username.as_str(), user.id.as_str())?;
+            let bytes = serde_json::to_vec(&user)?;
+            txn.open_table(USERS)?
+                .insert(user.id.as_str(), bytes.as_slice())?;
+        }
+        txn.commit()?;
+        Ok(user)
+    }
+
+    fn get_user(&self, id: &str) -> Result<Option<User>, RepositoryError> {
+        let txn = self.db.begin_read()?;
+        let users = txn.open_table(USERS)?;
+        let user = users.get(id)?;
+        Ok(user
+            .map(|bytes| serde_json::from_slice(bytes.value()))
+            .transpose()?)
+    }
+
+    fn find_user_by_username(&self, username: &str) -> Result<Option<User>, RepositoryError> {
+        let id = {
+            let txn = self.db.begin_read()?;
+            let usernames = txn.open_table(USERNAMES)?;
+            let id = usernames.get(username)?;
+            id.map(|id| id.value().to_string())
+        };
+        match id {
+            Some(id) => self.get_user(&id),
+            None => Ok(None),
+        }
+    }
+
+    fn revoke_token(
+        &self,
+        token_id: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        let now = Utc::now().timestamp();
+        let txn = self.db.begin_write()?;
+        {
+            let mut revoked = txn.open_table(REVOKED_TOKENS)?;
+            revoked.retain(|_, expires_at| expires_at > now)?;
+            revoked.insert(token_id, expires_at.timestamp())?;
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn is_token_revoked(&self, token_id: &str) -> Result<bool, RepositoryError> {
+        let txn = self.db.begin_read()?;
+        let revoked = txn.open_table(REVOKED_TOKENS)?;
+        let revoked = revoked.get(token_id)?.is_some();
+        Ok(revoked)
+    }
+}
+
+impl ApiKeyRepository for KvStore {
+    fn create_api_key(&self, key: ApiKey) -> Result<ApiKey, RepositoryError> {
+        let txn = self.db.begin_write()?;
+        {
+            let bytes = serde_json::to_vec(&key)?;
+            txn.open_table(API_KEYS)?
+                .insert(key.id.as_str(), bytes.as_slice())?;
+            txn.open_table(API_KEY_HASHES)?
+                .insert(key.keyHash.as_str(), key.id.as_str())?;
+        }
+        txn.commit()?;
+        Ok(key)
+    }
+
+    // Keys are few per user, so this scans them all rather than keeping an
+    // owner index.
+    fn list_api_keys(&self, owner: &str) -> Result<Vec<ApiKey>, RepositoryError> {
+        let txn = self.db.begin_read()?;
+        let keys = txn.open_table(API_KEYS)?;
+        let mut owned = Vec::new();
+        for entry in keys.iter()? {
+            let (_, bytes) = entry?;
+            let key: ApiKey = serde_json::from_slice(bytes.value())?;
+            if key.ownerId == owner {
+                owned.push(key);
+            }
+        }
+        owned.sort_by(|a, b| a.createdAt.cmp(&b.createdAt).then_with(|| a.id.cmp(&b.id)));
+        Ok(owned)
+    }
+
+    fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, RepositoryError> {
+        let txn = self.db.begin_read()?;
+        let hashes = txn.open_table(API_KEY_HASHES)?;
+        let Some(id) = hashes.get(key_hash)? else {
+            return Ok(None);
+        };
+        let keys = txn.open_table(API_KEYS)?;
+        let key = keys.get(id.value())?;
+        Ok(key
+            .map(|bytes| serde_json::from_slice(bytes.value()))
+            .transpose()?)
+    }
+
+    fn delete_api_key(&self, owner: &str, id: &str) -> Result<bool, RepositoryError> {
+        let txn = self.db.begin_write()?;
+        let deleted = {
+            let mut keys = txn.open_table(API_KEYS)?;
+            let key: Option<ApiKey> = keys
+                .get(id)?
+                .map(|bytes| serde_json::from_slice(bytes.value()))
+                .transpose()?;
+            match key.filter(|key| key.ownerId == owner) {
+                Some(key) => {
+                    keys.remove(id)?;
+                    txn.open_table(API_KEY_HASHES)?
+                        .remove(key.keyHash.as_str())?;
+                    true
+                }
+                None => false,
+            }
+        };
+        txn.commit()?;
+        Ok(deleted)
+    }
+}
+
+impl ReminderRepository for KvStore {
+    fn is_reminder_sent(&self, key: &str) -> Result<bool, RepositoryError> {
+        let txn = self.db.begin_read()?;
+        let sent = txn.open_table(SENT_REMINDERS)?;
+        let sent = sent.get(key)?.is_some();
+        Ok(sent)
+    }
+
+    fn record_reminder(&self, reminder: SentReminder) -> Result<(), RepositoryError> {
+        let key = SentReminder::key(&reminder.todoId, reminder.dueAt, reminder.offsetMinutes);
+        let txn = self.db.begin_write()?;
+        {
+            let bytes = serde_json::to_vec(&reminder)?;
+            txn.open_table(SENT_REMINDERS)?
+                .insert(key.as_str(), bytes.as_slice())?;
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    // Records that fail to decode are kept rather than failing the scan
+    fn forget_reminders(&self, before: DateTime<Utc>) -> Result<(), RepositoryError> {
+        let txn = self.db.begin_write()?;
+        txn.open_table(SENT_REMINDERS)?.retain(|_, bytes| {
+            serde_json::from_slice::<SentReminder>(bytes)
+                .map_or(true, |reminder| reminder.dueAt >= before)
+        })?;
+        txn.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use std::path::PathBuf;
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("todos-{}.redb", uuid::Uuid::new_v4()))
+    }
+
+    fn todo(id: &str, owner: Option<&str>) -> Todo {
+        Todo {
+            id: Some(id.to_string()),
+            title: id.to_string(),
+            updatedAt: Some(Utc::now()),
+            ownerId: owner.map(str::to_string),
+            version: 1,
+            ..Todo::default()
+        }
+    }
+
+    fn ids(todos: &[Todo]) -> Vec<&str> {
+        todos.iter().filter_map(|todo| todo.id.as_deref()).collect()
+    }
+
+    #[test]
+    fn todos_survive_reopen_test() {
+        let path = temp_path();
+        let repository = KvStore::open(&path).unwrap();
+        for id in ["first", "second", "third"] {
+            repository.create(todo(id, Some("alice"))).unwrap();
+        }
+        repository.delete("alice", "second").unwrap();
+        // Stopped part way through a mutation: the write never commits
+        let txn = repository.db.begin_write().unwrap();
+        txn.open_table(TODOS).unwrap().remove("first").unwrap();
+        drop(txn);
+        drop(repository);
+
+        let reopened = KvStore::open(&path).unwrap();
+        let page = reopened.list("alice", &TodoQuery::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(ids(&page.items), ["first", "third"]);
+        assert_eq!(page.total, 2);
+    }
+
+    #[test]
+    fn owners_are_kept_apart_test() {
+        let path = temp_path();
+        let repository = KvStore::open(&path).unwrap();
+        repository.create(todo("mine", Some("alice"))).unwrap();
+        repository.create(todo("yours", Some("bob"))).unwrap();
+        let taken = repository.create(todo("mine", Some("bob")));
+
+        let page = repository.list("bob", &TodoQuery::default()).unwrap();
+        let got = repository.get("bob", "mine").unwrap();
+        let updated = repository
+            .update("bob", "mine", &mut |todo| todo.title = "Taken".to_string())
+            .unwrap();
+        let ids_taken = ["mine".to_string()];
+        let updated_many = repository
+            .update_many("bob", &ids_taken, &mut |todo| {
+                todo.title = "Taken".to_string()
+            })
+            .unwrap();
+        let deleted = repository.delete("bob", "mine").unwrap();
+        let mine = repository.get("alice", "mine").unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(taken, Err(RepositoryError::Conflict(_))));
+        assert_eq!(ids(&page.items), ["yours"]);
+        assert!(got.is_none());
+        assert!(updated.is_none());
+        assert!(updated_many.is_empty());
+        assert!(!deleted);
+        assert_eq!(mine.unwrap().title, "mine");
+    }
+
+    #[test]
+    fn update_many_test() {
+        let path = temp_path();
+        let repository = KvStore::open(&path).unwrap();
+        for id in ["a", "b", "c"] {
+            repository.create(todo(id, Some("alice"))).unwrap();
+        }
+        let updated = repository
+            .update("alice", "a", &mut |todo| todo.completed = Some(true))
+            .unwrap();
+        let ids_wanted = ["c".to_string(), "missing".to_string(), "a".to_string()];
+        let mut seen = Vec::new();
+        let updated_many = repository
+            .update_many("alice", &ids_wanted, &mut |todo| {
+                seen.push(todo.id.clone().unwrap());
+                todo.title.make_ascii_uppercase();
+            })
+            .unwrap();
+        drop(repository);
+        let reopened = KvStore::open(&path).unwrap();
+        let page = reopened.list("alice", &TodoQuery::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(updated.unwrap().completed, Some(true));
+        assert_eq!(seen, ["c", "a"]);
+        assert_eq!(ids(&updated_many), ["c", "a"]);
+        let titles: Vec<&str> = page.items.iter().map(|todo| todo.title.as_str()).collect();
+        assert_eq!(titles, ["A", "b", "C"]);
+        assert_eq!(page.items[0].completed, Some(true));
+    }
+
+    #[test]
+    fn delete_if_deleted_before_test() {
+        let path = temp_path();
+        let repository = KvStore::open(&path).unwrap();
+        let cutoff = Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap();
+        for (id, deleted_at) in [
+            ("live", None),
+            ("old", Some(cutoff - Duration::days(1))),
+            ("recent", Some(cutoff + Duration::days(1))),
+        ] {
+            let todo = Todo {
+                deletedAt: deleted_at,
+                ..todo(id, Some("alice"))
+            };
+            repository.create(todo).unwrap();
+        }
+
+        let purged: Vec<bool> = ["live", "old", "recent", "old"]
+            .iter()
+            .map(|id| {
+                repository
+                    .delete_if_deleted_before("alice", id, cutoff)
+                    .unwrap()
+            })
+            .collect();
+        let other_owner = repository
+            .delete_if_deleted_before("bob", "recent", cutoff + Duration::days(2))
+            .unwrap();
+        let query = TodoQuery {
+            filter: TodoFilter {
+                deleted: true,
+                ..TodoFilter::default()
+            },
+            ..TodoQuery::default()
+        };
+        let trash = repository.list("alice", &query).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(purged, [false, true, false, false]);
+        assert!(!other_owner);
+        assert_eq!(ids(&trash.items), ["recent"]);
+    }
+
+    #[test]
+    fn lists_and_keys_made_together_keep_their_order_test() {
+        let path = temp_path();
+        let repository = KvStore::open(&path).unwrap();
+        let now = Utc::now();
+        for id in ["c", "a", "b"] {
+            let list = List {
+                id: id.to_string(),
+                ownerId: "alice".to_string(),
+                name: id.to_string(),
+                description: String::new(),
+                createdAt: now,
+                updatedAt: now,
+            };
+            repository.create_list(list).unwrap();
+            let key = ApiKey {
+                id: id.to_string(),
+                ownerId: "alice".to_string(),
+                name: id.to_string(),
+                prefix: id.to_string(),
+                keyHash: format!("hash-{}", id),
+                scopes: Vec::new(),
+                createdAt: now,
+                expiresAt: None,
+            };
+            repository.create_api_key(key).unwrap();
+        }
+        let earlier = List {
+            id: "z".to_string(),
+            ownerId: "alice".to_string(),
+            name: "z".to_string(),
+            description: String::new(),
+            createdAt: now - Duration::seconds(1),
+            updatedAt: now,
+        };
+        repository.create_list(earlier).unwrap();
+
+        let lists = repository.list_lists("alice").unwrap();
+        let keys = repository.list_api_keys("alice").unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let list_ids: Vec<&str> = lists.iter().map(|list| list.id.as_str()).collect();
+        assert_eq!(list_ids, ["z", "a", "b", "c"]);
+        let key_ids: Vec<&str> = keys.iter().map(|key| key.id.as_str()).collect();
+        assert_eq!(key_ids, ["a", "b", "c"]);
+    }
+    #[test]
+    fn adopt_unowned_todos_test() {
+        let path = temp_path();
+        let repository = KvStore::open(&path).unwrap();
+        for (id, owner) in [("legacy", None), ("owned", Some("alice"))] {
+            repository.create(todo(id, owner)).unwrap();
+        }
+        assert!(repository.get("bob", "legacy").unwrap().is_none());
+
+        assert_eq!(repository.adopt_unowned_todos("bob").unwrap(), 1);
+        assert_eq!(repository.adopt_unowned_todos("carol").unwrap(), 0);
+        drop(repository);
+        let reopened = KvStore::open(&path).unwrap();
+        let page = reopened.list("bob", &TodoQuery::default()).unwrap();
+        let owned = reopened.get("alice", "owned").unwrap();
+        let latest = reopened.max_updated_at("bob").unwrap();
+        drop(reopened);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(ids(&page.items), ["legacy"]);
+        assert!(owned.is_some());
+        assert!(latest.is_some());
+    }
+}
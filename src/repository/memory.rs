@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::model::{ApiKey, List, SentReminder, Todo, User};
+use crate::repository::{
+    ApiKeyRepository, ListRepository, Page, ReminderRepository, RepositoryError, TodoFilter,
+    TodoQuery, TodoRepository, UserRepository,
+};
+
+// Everything the memory store holds. Todos, lists, users and API keys are
+// kept in insertion order.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct MemoryState {
+    pub todos: Vec<Todo>,
+    #[serde(default)]
+    pub lists: Vec<List>,
+    #[serde(default)]
+    pub users: Vec<User>,
+    #[serde(default)]
+    pub revoked_tokens: HashMap<String, DateTime<Utc>>,
+    #[serde(default)]
+    pub api_keys: Vec<ApiKey>,
+    // Keyed by `SentReminder::key`
+    #[serde(default)]
+    pub sent_reminders: HashMap<String, SentReminder>,
+    // The latest updatedAt written for each owner's todos, rebuilt from
+    // `todos` on load rather than persisted
+    #[serde(skip)]
+    latest_updates: HashMap<String, DateTime<Utc>>,
+}
+
+impl MemoryState {
+    fn note_update(&mut self, todo: &Todo) {
+        let (Some(owner), Some(updated_at)) = (&todo.ownerId, todo.updatedAt) else {
+            return;
+        };
+        let latest = self
+            .latest_updates
+            .entry(owner.clone())
+            .or_insert(updated_at);
+        *latest = updated_at.max(*latest);
+    }
+}
+
+// Keeps everything in memory behind a single lock. Nothing survives a restart.
+#[derive(Default)]
+pub struct MemoryStore {
+    state: Mutex<MemoryState>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A copy of the whole state, for persisting it elsewhere.
+    pub fn snapshot(&self) -> Result<MemoryState, RepositoryError> {
+        Ok(self.lock()?.clone())
+    }
+
+    // Whether any recorded reminder was for a todo due before `before`
+    pub fn has_reminders_before(&self, before: DateTime<Utc>) -> Result<bool, RepositoryError> {
+        Ok(self
+            .lock()?
+            .sent_reminders
+            .values()
+            .any(|reminder| reminder.dueAt < before))
+    }
+
+    // The todos saved before todos had owners
+    pub fn unowned_todos(&self) -> Result<Vec<Todo>, RepositoryError> {
+        Ok(self
+            .lock()?
+            .todos
+            .iter()
+            .filter(|todo| todo.ownerId.is_none())
+            .cloned()
+            .collect())
+    }
+
+    // Gives `owner` the todos saved before todos had owners and returns how
+    // many there were
+    pub fn adopt_unowned_todos(&self, owner: &str) -> Result<usize, RepositoryError> {
+        let mut state = self.lock()?;
+        let mut adopted = Vec::new();
+        for todo in state.todos.iter_mut() {
+            if todo.ownerId.is_none() {
+                todo.ownerId = Some(owner.to_string());
+                adopted.push(todo.clone());
+            }
+        }
+        for todo in &adopted {
+            state.note_update(todo);
+        }
+        Ok(adopted.len())
+    }
+
+    // Whether any owner has a todo with this id
+    pub fn contains_todo(&self, id: &str) -> Result<bool, RepositoryError> {
+        Ok(self
+            .lock()?
+            .todos
+            .iter()
+            .any(|todo| todo.id.as_deref() == Some(id)))
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, MemoryState>, RepositoryError> {
+        self.state
+            .lock()
+            .map_err(|_| RepositoryError::Storage("memory store lock poisoned".to_string()))
+    }
+}
+
+impl From<MemoryState> for MemoryStore {
+    fn from(mut state: MemoryState) -> Self {
+        let todos = std::mem::take(&mut state.todos);
+        for todo in &todos {
+            state.note_update(todo);
+        }
+        state.todos = todos;
+        MemoryStore {
+            state: Mutex::new(state),
+        }
+    }
+}
+
+fn is_owned(todo: &Todo, owner: &str) -> bool {
+    todo.ownerId.as_deref() == Some(owner)
+}
+
+fn is_owned_todo(todo: &Todo, owner: &str, id: &str) -> bool {
+    is_owned(todo, owner) && todo.id.as_deref() == Some(id)
+}
+
+impl TodoRepository for MemoryStore {
+    fn create(&self, todo: Todo) -> Result<Todo, RepositoryError> {
+        let mut state = self.lock()?;
+        if let Some(id) = todo.id.as_deref() {
+            if state
+                .todos
+                .iter()
+                .any(|existing| existing.id.as_deref() == Some(id))
+            {
+                return Err(RepositoryError::todo_id_taken(id));
+            }
+        }
+        state.note_update(&todo);
+        state.todos.push(todo.clone());
+        Ok(todo)
+    }
+
+    fn get(&self, owner: &str, id: &str) -> Result<Option<Todo>, RepositoryError> {
+        let state = self.lock()?;
+        Ok(state
+            .todos
+            .iter()
+            .find(|todo| is_owned_todo(todo, owner, id))
+            .cloned())
+    }
+
+    fn list(&self, owner: &str, query: &TodoQuery) -> Result<Page, RepositoryError> {
+        let state = self.lock()?;
+        let owned = state
+            .todos
+            .iter()
+            .filter(|todo| is_owned(todo, owner))
+            .map(|todo| Ok(todo.clone()));
+        Page::collect(owned, query)
+    }
+
+    fn update(
+        &self,
+        owner: &str,
+        id: &str,
+        apply: &mut dyn FnMut(&mut Todo),
+    ) -> Result<Option<Todo>, RepositoryError> {
+        let mut state = self.lock()?;
+        let todo = state
+            .todos
+            .iter_mut()
+            .find(|todo| is_owned_todo(todo, owner, id));
+        let updated = todo.map(|todo| {
+            apply(todo);
+            todo.clone()
+        });
+        if let Some(todo) = &updated {
+            state.note_update(todo);
+        }
+        Ok(updated)
+    }
+
+    fn update_many(
+        &self,
+        owner: &str,
+        ids: &[String],
+        apply: &mut dyn FnMut(&mut Todo),
+    ) -> Result<Vec<Todo>, RepositoryError> {
+        let mut state = self.lock()?;
+        let mut updated = Vec::with_capacity(ids.len());
+        for id in ids {
+            let todo = state
+                .todos
+                .iter_mut()
+                .find(|todo| is_owned_todo(todo, owner, id));
+            if let Some(todo) = todo {
+                apply(todo);
+                updated.push(todo.clone());
+            }
+        }
+        for todo in &updated {
+            state.note_update(todo);
+        }
+        Ok(updated)
+    }
+
+    fn delete(&self, owner: &str, id: &str) -> Result<bool, RepositoryError> {
+        let mut state = self.lock()?;
+        let position = state
+            .todos
+            .iter()
+            .position(|todo| is_owned_todo(todo, owner, id));
+        Ok(position.map(|index| state.todos.remove(index)).is_some())
+    }
+
+    fn delete_if_deleted_before(
+        &self,
+        owner: &str,
+        id: &str,
+        cutoff: DateTime<Utc>,
+    ) -> Result<bool, RepositoryError> {
+        let mut state = self.lock()?;
+        let position = state.todos.iter().position(|todo| {
+            is_owned_todo(todo, owner, id)
+                && todo
+                    .deletedAt
+                    .is_some_and(|deleted_at| deleted_at <= cutoff)
+        });
+        Ok(position.map(|index| state.todos.remove(index)).is_some())
+    }
+
+    fn list_all(&self, filter: &TodoFilter) -> Result<Vec<Todo>, RepositoryError> {
+        let state = self.lock()?;
+        Ok(state
+            .todos
+            .iter()
+            .filter(|todo| filter.matches(todo))
+            .cloned()
+            .collect())
+    }
+
+    fn max_updated_at(&self, owner: &str) -> Result<Option<DateTime<Utc>>, RepositoryError> {
+        Ok(self.lock()?.latest_updates.get(owner).copied())
+    }
+}
+
+fn is_owned_list(list: &List, owner: &str, id: &str) -> bool {
+    list.ownerId == owner && list.id == id
+}
+
+impl ListRepository for MemoryStore {
+    fn create_list(&self, list: List) -> Result<List, RepositoryError> {
+        self.lock()?.lists.push(list.clone());
+        Ok(list)
+    }
+
+    fn get_list(&self, owner: &str, id: &str) -> Result<Option<List>, RepositoryError> {
+        let state = self.lock()?;
+        Ok(state
+            .lists
+            .iter()
+            .find(|list| is_owned_list(list, owner, id))
+            .cloned())
+    }
+
+    fn list_lists(&self, owner: &str) -> Result<Vec<List>, RepositoryError> {
+        let state = self.lock()?;
+        Ok(state
+            .lists
+            .iter()
+            .filter(|list| list.ownerId == owner)
+            .cloned()
+            .collect())
+    }
+
+    fn update_list(
+        &self,
+        owner: &str,
+        id: &str,
+        apply: &mut dyn FnMut(&mut List),
+    ) -> Result<Option<List>, RepositoryError> {
+        let mut state = self.lock()?;
+        let list = state
+            .lists
+            .iter_mut()
+            .find(|list| is_owned_list(list, owner, id));
+        Ok(list.map(|list| {
+            apply(list);
+            list.clone()
+        }))
+    }
+
+    fn delete_list(&self, owner: &str, id: &str) -> Result<bool, RepositoryError> {
+        let mut state = self.lock()?;
+        let position = state
+            .lists
+            .iter()
+            .position(|list| is_owned_list(list, owner, id));
+        Ok(position.map(|index| state.lists.remove(index)).is_some())
+    }
+}
+
+impl UserRepository for MemoryStore {
+    fn create_user(&self, user: User) -> Result<User, RepositoryError> {
+        let mut state = self.lock()?;
+        if state
+            .users
+            .iter()
+            .any(|existing| existing.username == user.username)
+        {
+            return Err(RepositoryError::username_taken(&user.username));
+        }
+        state.users.push(user.clone());
+        Ok(user)
+    }
+
+    fn get_user(&self, id: &str) -> Result<Option<User>, RepositoryError> {
+        let state = self.lock()?;
+        Ok(state.users.iter().find(|user| user.id == id).cloned())
+    }
+
+    fn find_user_by_username(&self, username: &str) -> Result<Option<User>, RepositoryError> {
+        let state = self.lock()?;
+        Ok(state
+            .users
+            .iter()
+            .find(|user| user.username == username)
+            .cloned())
+    }
+
+    fn revoke_token(
+        &self,
+        token_id: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        let mut state = self.lock()?;
+        let now = Utc::now();
+        state
+            .revoked_tokens
+            .retain(|_, expires_at| *expires_at > now);
+        state
+            .revoked_tokens
+            .insert(token_id.to_string(), expires_at);
+        Ok(())
+    }
+
+    fn is_token_revoked(&self, token_id: &str) -> Result<bool, RepositoryError> {
+        Ok(self.lock()?.revoked_tokens.contains_key(token_id))
+    }
+}
+
+impl ApiKeyRepository for MemoryStore {
+    fn create_api_key(&self, key: ApiKey) -> Result<ApiKey, RepositoryError> {
+        self.lock()?.api_keys.push(key.clone());
+        Ok(key)
+    }
+
+    fn list_api_keys(&self, owner: &str) -> Result<Vec<ApiKey>, RepositoryError> {
+        let state = self.lock()?;
+        Ok(state
+            .api_keys
+            .iter()
+            .filter(|key| key.ownerId == owner)
+            .cloned()
+            .collect())
+    }
+
+    fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, RepositoryError> {
+        let state = self.lock()?;
+        Ok(state
+            .api_keys
+            .iter()
+            .find(|key| key.keyHash == key_hash)
+            .cloned())
+    }
+
+    fn delete_api_key(&self, owner: &str, id: &str) -> Result<bool, RepositoryError> {
+        let mut state = self.lock()?;
+        let position = state
+            .api_keys
+            .iter()
+            .position(|key| key.ownerId == owner && key.id == id);
+        Ok(position.map(|index| state.api_keys.remove(index)).is_some())
+    }
+}
+
+impl ReminderRepository for MemoryStore {
+    fn is_reminder_sent(&self, key: &str) -> Result<bool, RepositoryError> {
+        Ok(self.lock()?.sent_reminders.contains_key(key))
+    }
+
+    fn record_reminder(&self, reminder: SentReminder) -> Result<(), RepositoryError> {
+        let key = SentReminder::key(&reminder.todoId, reminder.dueAt, reminder.offsetMinutes);
+        self.lock()?.sent_reminders.insert(key, reminder);
+        Ok(())
+    }
+
+    fn forget_reminders(&self, before: DateTime<Utc>) -> Result<(), RepositoryError> {
+        self.lock()?
+            .sent_reminders
+            .retain(|_, reminder| reminder.dueAt >= before);
+        Ok(())
+    }
+}
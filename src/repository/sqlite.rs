@@ -0,0 +1,741 @@
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::{DateTime, Utc};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, Type, ValueRef};
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension, Row, ToSql};
+use serde::de::DeserializeOwned;
+
+use crate::model::{ApiKey, List, Priority, SentReminder, Todo, User};
+use crate::repository::{
+    ApiKeyRepository, ListRepository, Page, ReminderRepository, RepositoryError, TodoFilter,
+    TodoQuery, TodoRepository, UserRepository,
+};
+
+// Each entry upgrades the schema by one version. Entries are applied in order
+// and must never be edited once released; add a new entry instead.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE todos (
+        id TEXT PRIMARY KEY NOT NULL,
+        title TEXT NOT NULL,
+        content TEXT NOT NULL,
+        completed INTEGER,
+        created_at TEXT,
+        updated_at TEXT
+    );",
+    "CREATE TABLE users (
+        id TEXT PRIMARY KEY NOT NULL,
+        username TEXT NOT NULL UNIQUE,
+        password_hash TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE TABLE revoked_tokens (
+        token_id TEXT PRIMARY KEY NOT NULL,
+        expires_at TEXT NOT NULL
+    );",
+    // Existing todos are left without an owner, which hides them until
+    // `adopt_unowned_todos` hands them to one
+    "ALTER TABLE todos ADD COLUMN owner_id TEXT;
+    CREATE INDEX todos_owner_id ON todos (owner_id);",
+    "CREATE TABLE api_keys (
+        id TEXT PRIMARY KEY NOT NULL,
+        owner_id TEXT NOT NULL,
+        name TEXT NOT NULL,
+        prefix TEXT NOT NULL,
+        key_hash TEXT NOT NULL UNIQUE,
+        scopes TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        expires_at TEXT
+    );
+    CREATE INDEX api_keys_owner_id ON api_keys (owner_id);",
+    "ALTER TABLE todos ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';",
+    "ALTER TABLE todos ADD COLUMN due_at TEXT;
+    ALTER TABLE todos ADD COLUMN priority TEXT;",
+    "CREATE TABLE sent_reminders (
+        key TEXT PRIMARY KEY NOT NULL,
+        todo_id TEXT NOT NULL,
+        due_at INTEGER NOT NULL,
+        offset_minutes INTEGER NOT NULL,
+        sent_at TEXT NOT NULL
+    );
+    CREATE INDEX sent_reminders_due_at ON sent_reminders (due_at);",
+    "ALTER TABLE todos ADD COLUMN recurrence TEXT;",
+    "ALTER TABLE todos ADD COLUMN parent_id TEXT;
+    CREATE INDEX todos_parent_id ON todos (parent_id);",
+    "CREATE TABLE lists (
+        id TEXT PRIMARY KEY NOT NULL,
+        owner_id TEXT NOT NULL,
+        name TEXT NOT NULL,
+        description TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    );
+    CREATE INDEX lists_owner_id ON lists (owner_id);
+    ALTER TABLE todos ADD COLUMN list_id TEXT;
+    CREATE INDEX todos_list_id ON todos (list_id);",
+    "ALTER TABLE todos ADD COLUMN version INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE todos ADD COLUMN deleted_at TEXT;",
+];
+
+const TODO_COLUMNS: &str =
+    "id, title, content, completed, created_at, updated_at, owner_id, tags, due_at, priority, \
+     recurrence, parent_id, list_id, version, deleted_at";
+
+const LIST_COLUMNS: &str = "id, owner_id, name, description, created_at, updated_at";
+
+const USER_COLUMNS: &str = "id, username, password_hash, created_at";
+
+const API_KEY_COLUMNS: &str =
+    "id, owner_id, name, prefix, key_hash, scopes, created_at, expires_at";
+
+// Persists todos, lists, users and API keys in a SQLite database file. Todos
+// and lists are listed in insertion order.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    // Opens (or creates) the database at `path` and runs pending migrations.
+    // Pass ":memory:" for a throwaway database.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, RepositoryError> {
+        let mut conn = Connection::open(path)?;
+        migrate(&mut conn)?;
+        Ok(SqliteStore {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    // Gives `owner` the todos saved before todos had owners and returns how
+    // many there were
+    pub fn adopt_unowned_todos(&self, owner: &str) -> Result<usize, RepositoryError> {
+        let conn = self.lock()?;
+        let adopted = conn.execute(
+            "UPDATE todos SET owner_id = ?1 WHERE owner_id IS NULL",
+            params![owner],
+        )?;
+        Ok(adopted)
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Connection>, RepositoryError> {
+        self.conn
+            .lock()
+            .map_err(|_| RepositoryError::Storage("sqlite connection lock poisoned".to_string()))
+    }
+}
+
+// Brings the schema up to date, tracking the applied version in `user_version`.
+fn migrate(conn: &mut Connection) -> Result<(), RepositoryError> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(RepositoryError::Storage(format!(
+            "database schema version {} is newer than this server supports ({})",
+            version,
+            MIGRATIONS.len()
+        )));
+    }
+
+    let tx = conn.transaction()?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", index + 1)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn todo_from_row(row: &Row) -> rusqlite::Result<Todo> {
+    Ok(Todo {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        content: row.get(2)?,
+        completed: row.get(3)?,
+        createdAt: row.get(4)?,
+        updatedAt: row.get(5)?,
+        ownerId: row.get(6)?,
+        tags: json_column(row, 7)?,
+        dueAt: row.get(8)?,
+        priority: row.get(9)?,
+        recurrence: row.get(10)?,
+        parentId: row.get(11)?,
+        listId: row.get(12)?,
+        version: row.get(13)?,
+        deletedAt: row.get(14)?,
+    })
+}
+
+fn list_from_row(row: &Row) -> rusqlite::Result<List> {
+    Ok(List {
+        id: row.get(0)?,
+        ownerId: row.get(1)?,
+        name: row.get(2)?,
+        description: row.get(3)?,
+        createdAt: row.get(4)?,
+        updatedAt: row.get(5)?,
+    })
+}
+
+fn user_from_row(row: &Row) -> rusqlite::Result<User> {
+    Ok(User {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        passwordHash: row.get(2)?,
+        createdAt: row.get(3)?,
+    })
+}
+
+// Priorities are stored by name
+impl ToSql for Priority {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for Priority {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let text = value.as_str()?;
+        Priority::parse(text)
+            .ok_or_else(|| FromSqlError::Other(format!("unknown priority {:?}", text).into()))
+    }
+}
+
+// Lists are stored as JSON text
+fn json_column<T: DeserializeOwned>(row: &Row, index: usize) -> rusqlite::Result<T> {
+    let text: String = row.get(index)?;
+    serde_json::from_str(&text)
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(err)))
+}
+
+fn api_key_from_row(row: &Row) -> rusqlite::Result<ApiKey> {
+    Ok(ApiKey {
+        id: row.get(0)?,
+        ownerId: row.get(1)?,
+        name: row.get(2)?,
+        prefix: row.get(3)?,
+        keyHash: row.get(4)?,
+        scopes: json_column(row, 5)?,
+        createdAt: row.get(6)?,
+        expiresAt: row.get(7)?,
+    })
+}
+
+fn select_todo(conn: &Connection, owner: &str, id: &str) -> rusqlite::Result<Option<Todo>> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM todos WHERE id = ?1 AND owner_id = ?2",
+            TODO_COLUMNS
+        ),
+        params![id, owner],
+        todo_from_row,
+    )
+    .optional()
+}
+
+// Writes back every column a todo update can change
+fn update_todo(conn: &Connection, id: &str, todo: &Todo) -> Result<(), RepositoryError> {
+    conn.execute(
+        "UPDATE todos SET title = ?2, content = ?3, completed = ?4, created_at = ?5, updated_at = ?6,
+         tags = ?7, due_at = ?8, priority = ?9, recurrence = ?10, parent_id = ?11,
+         list_id = ?12, version = ?13, deleted_at = ?14 WHERE id = ?1",
+        params![
+            id,
+            todo.title,
+            todo.content,
+            todo.completed,
+            todo.createdAt,
+            todo.updatedAt,
+            serde_json::to_string(&todo.tags)?,
+            todo.dueAt,
+            todo.priority,
+            todo.recurrence,
+            todo.parentId,
+            todo.listId,
+            todo.version,
+            todo.deletedAt
+        ],
+    )?;
+    Ok(())
+}
+
+impl TodoRepository for SqliteStore {
+    fn create(&self, todo: Todo) -> Result<Todo, RepositoryError> {
+        let conn = self.lock()?;
+        let inserted = conn.execute(
+            &format!(
+                "INSERT INTO todos ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+                TODO_COLUMNS
+            ),
+            params![
+                todo.id,
+                todo.title,
+                todo.content,
+                todo.completed,
+                todo.createdAt,
+                todo.updatedAt,
+                todo.ownerId,
+                serde_json::to_string(&todo.tags)?,
+                todo.dueAt,
+                todo.priority,
+                todo.recurrence,
+                todo.parentId,
+                todo.listId,
+                todo.version,
+                todo.deletedAt
+            ],
+        );
+        match inserted {
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == ErrorCode::ConstraintViolation =>
+            {
+                Err(RepositoryError::todo_id_taken(
+                    todo.id.as_deref().unwrap_or_default(),
+                ))
+            }
+            inserted => {
+                inserted?;
+                Ok(todo)
+            }
+        }
+    }
+
+    fn get(&self, owner: &str, id: &str) -> Result<Option<Todo>, RepositoryError> {
+        let conn = self.lock()?;
+        Ok(select_todo(&conn, owner, id)?)
+    }
+
+    // The owner, trash, completion, parent and list predicates run in SQL. The
+    // text, date, tag and overdue ones, the sort and the page window stay in
+    // `Page::collect`, so they behave exactly as in the other stores, at the
+    // cost of reading every row the SQL predicates let through.
+    fn list(&self, owner: &str, query: &TodoQuery) -> Result<Page, RepositoryError> {
+        let filter = &query.filter;
+        let mut conditions = vec!["owner_id = ?"];
+        let mut values: Vec<&dyn ToSql> = vec![&owner];
+        conditions.push(if filter.deleted {
+            "deleted_at IS NOT NULL"
+        } else {
+            "deleted_at IS NULL"
+        });
+        if let Some(completed) = &filter.completed {
+            conditions.push("IFNULL(completed, 0) = ?");
+            values.push(completed);
+        }
+        if let Some(parent_id) = &filter.parent_id {
+            conditions.push("parent_id = ?");
+            values.push(parent_id);
+        }
+        if let Some(list_id) = &filter.list_id {
+            conditions.push("list_id = ?");
+            values.push(list_id);
+        }
+
+        let conn = self.lock()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM todos WHERE {} ORDER BY rowid",
+            TODO_COLUMNS,
+            conditions.join(" AND ")
+        ))?;
+        let owned = stmt
+            .query_map(values.as_slice(), todo_from_row)?
+            .map(|todo| todo.map_err(RepositoryError::from));
+        Page::collect(owned, query)
+    }
+
+    fn update(
+        &self,
+        owner: &str,
+        id: &str,
+        apply: &mut dyn FnMut(&mut Todo),
+    ) -> Result<Option<Todo>, RepositoryError> {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+        let Some(mut todo) = select_todo(&tx, owner, id)? else {
+            return Ok(None);
+        };
+        apply(&mut todo);
+        update_todo(&tx, id, &todo)?;
+        tx.commit()?;
+        Ok(Some(todo))
+    }
+
+    fn update_many(
+        &self,
+        owner: &str,
+        ids: &[String],
+        apply: &mut dyn FnMut(&mut Todo),
+    ) -> Result<Vec<Todo>, RepositoryError> {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+        let mut updated = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(mut todo) = select_todo(&tx, owner, id)? {
+                apply(&mut todo);
+                update_todo(&tx, id, &todo)?;
+                updated.push(todo);
+            }
+        }
+        tx.commit()?;
+        Ok(updated)
+    }
+
+    fn delete(&self, owner: &str, id: &str) -> Result<bool, RepositoryError> {
+        let conn = self.lock()?;
+        let deleted = conn.execute(
+            "DELETE FROM todos WHERE id = ?1 AND owner_id = ?2",
+            params![id, owner],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    fn delete_if_deleted_before(
+        &self,
+        owner: &str,
+        id: &str,
+        cutoff: DateTime<Utc>,
+    ) -> Result<bool, RepositoryError> {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+        let due = select_todo(&tx, owner, id)?
+            .and_then(|todo| todo.deletedAt)
+            .is_some_and(|deleted_at| deleted_at <= cutoff);
+        if due {
+            tx.execute(
+                "DELETE FROM todos WHERE id = ?1 AND owner_id = ?2",
+                params![id, owner],
+            )?;
+        }
+        tx.commit()?;
+        Ok(due)
+    }
+
+    fn list_all(&self, filter: &TodoFilter) -> Result<Vec<Todo>, RepositoryError> {
+        let conn = self.lock()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM todos ORDER BY rowid",
+            TODO_COLUMNS
+        ))?;
+        let mut todos = Vec::new();
+        for todo in stmt.query_map([], todo_from_row)? {
+            let todo = todo?;
+            if filter.matches(&todo) {
+                todos.push(todo);
+            }
+        }
+        Ok(todos)
+    }
+
+    // Timestamps are stored as UTC text, which sorts in time order
+    fn max_updated_at(&self, owner: &str) -> Result<Option<DateTime<Utc>>, RepositoryError> {
+        let conn = self.lock()?;
+        Ok(conn.query_row(
+            "SELECT MAX(updated_at) FROM todos WHERE owner_id = ?1",
+            params![owner],
+            |row| row.get(0),
+        )?)
+    }
+}
+
+fn select_list(conn: &Connection, owner: &str, id: &str) -> rusqlite::Result<Option<List>> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM lists WHERE id = ?1 AND owner_id = ?2",
+            LIST_COLUMNS
+        ),
+        params![id, owner],
+        list_from_row,
+    )
+    .optional()
+}
+
+impl ListRepository for SqliteStore {
+    fn create_list(&self, list: List) -> Result<List, RepositoryError> {
+        let conn = self.lock()?;
+        conn.execute(
+            &format!(
+                "INSERT INTO lists ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                LIST_COLUMNS
+            ),
+            params![
+                list.id,
+                list.ownerId,
+                list.name,
+                list.description,
+                list.createdAt,
+                list.updatedAt
+            ],
+        )?;
+        Ok(list)
+    }
+
+    fn get_list(&self, owner: &str, id: &str) -> Result<Option<List>, RepositoryError> {
+        let conn = self.lock()?;
+        Ok(select_list(&conn, owner, id)?)
+    }
+
+    fn list_lists(&self, owner: &str) -> Result<Vec<List>, RepositoryError> {
+        let conn = self.lock()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM lists WHERE owner_id = ?1 ORDER BY rowid",
+            LIST_COLUMNS
+        ))?;
+        let lists = stmt
+            .query_map(params![owner], list_from_row)?
+            .collect::<rusqlite::Result<Vec<List>>>()?;
+        Ok(lists)
+    }
+
+    fn update_list(
+        &self,
+        owner: &str,
+        id: &str,
+        apply: &mut dyn FnMut(&mut List),
+    ) -> Result<Option<List>, RepositoryError> {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction()?;
+        let Some(mut list) = select_list(&tx, owner, id)? else {
+            return Ok(None);
+        };
+        apply(&mut list);
+        tx.execute(
+            "UPDATE lists SET name = ?2, description = ?3, updated_at = ?4 WHERE id = ?1",
+            params![id, list.name, list.description, list.updatedAt],
+        )?;
+        tx.commit()?;
+        Ok(Some(list))
+    }
+
+    fn delete_list(&self, owner: &str, id: &str) -> Result<bool, RepositoryError> {
+        let conn = self.lock()?;
+        let deleted = conn.execute(
+            "DELETE FROM lists WHERE id = ?1 AND owner_id = ?2",
+            params![id, owner],
+        )?;
+        Ok(deleted > 0)
+    }
+}
+
+impl UserRepository for SqliteStore {
+    fn create_user(&self, user: User) -> Result<User, RepositoryError> {
+        let conn = self.lock()?;
+        let inserted = conn.execute(
+            &format!(
+                "INSERT INTO users ({}) VALUES (?1, ?2, ?3, ?4)",
+                USER_COLUMNS
+            ),
+            params![user.id, user.username, user.passwordHash, user.createdAt],
+        );
+        match inserted {
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == ErrorCode::ConstraintViolation =>
+            {
+                Err(RepositoryError::username_taken(&user.username))
+            }
+            inserted => {
+                inserted?;
+                Ok(user)
+            }
+        }
+    }
+
+    fn get_user(&self, id: &str) -> Result<Option<User>, RepositoryError> {
+        let conn = self.lock()?;
+        Ok(conn
+            .query_row(
+                &format!("SELECT {} FROM users WHERE id = ?1", USER_COLUMNS),
+                params![id],
+                user_from_row,
+            )
+            .optional()?)
+    }
+
+    fn find_user_by_username(&self, username: &str) -> Result<Option<User>, RepositoryError> {
+        let conn = self.lock()?;
+        Ok(conn
+            .query_row(
+                &format!("SELECT {} FROM users WHERE username = ?1", USER_COLUMNS),
+                params![username],
+                user_from_row,
+            )
+            .optional()?)
+    }
+
+    fn revoke_token(
+        &self,
+        token_id: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        let conn = self.lock()?;
+        conn.execute(
+            "DELETE FROM revoked_tokens WHERE expires_at <= ?1",
+            params![Utc::now()],
+        )?;
+        conn.execute(
+            "INSERT OR REPLACE INTO revoked_tokens (token_id, expires_at) VALUES (?1, ?2)",
+            params![token_id, expires_at],
+        )?;
+        Ok(())
+    }
+
+    fn is_token_revoked(&self, token_id: &str) -> Result<bool, RepositoryError> {
+        let conn = self.lock()?;
+        Ok(conn
+            .query_row(
+                "SELECT 1 FROM revoked_tokens WHERE token_id = ?1",
+                params![token_id],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+}
+
+impl ApiKeyRepository for SqliteStore {
+    fn create_api_key(&self, key: ApiKey) -> Result<ApiKey, RepositoryError> {
+        let conn = self.lock()?;
+        conn.execute(
+            &format!(
+                "INSERT INTO api_keys ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                API_KEY_COLUMNS
+            ),
+            params![
+                key.id,
+                key.ownerId,
+                key.name,
+                key.prefix,
+                key.keyHash,
+                serde_json::to_string(&key.scopes)?,
+                key.createdAt,
+                key.expiresAt
+            ],
+        )?;
+        Ok(key)
+    }
+
+    fn list_api_keys(&self, owner: &str) -> Result<Vec<ApiKey>, RepositoryError> {
+        let conn = self.lock()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM api_keys WHERE owner_id = ?1 ORDER BY rowid",
+            API_KEY_COLUMNS
+        ))?;
+        let keys = stmt
+            .query_map(params![owner], api_key_from_row)?
+            .collect::<rusqlite::Result<Vec<ApiKey>>>()?;
+        Ok(keys)
+    }
+
+    fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, RepositoryError> {
+        let conn = self.lock()?;
+        Ok(conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM api_keys WHERE key_hash = ?1",
+                    API_KEY_COLUMNS
+                ),
+                params![key_hash],
+                api_key_from_row,
+            )
+            .optional()?)
+    }
+
+    fn delete_api_key(&self, owner: &str, id: &str) -> Result<bool, RepositoryError> {
+        let conn = self.lock()?;
+        let deleted = conn.execute(
+            "DELETE FROM api_keys WHERE id = ?1 AND owner_id = ?2",
+            params![id, owner],
+        )?;
+        Ok(deleted > 0)
+    }
+}
+
+// `due_at` is kept as a unix timestamp, which is all pruning needs
+impl ReminderRepository for SqliteStore {
+    fn is_reminder_sent(&self, key: &str) -> Result<bool, RepositoryError> {
+        let conn = self.lock()?;
+        let sent = conn
+            .query_row(
+                "SELECT 1 FROM sent_reminders WHERE key = ?1",
+                params![key],
+                |_| Ok(()),
+            )
+            .optional()?;
+        Ok(sent.is_some())
+    }
+
+    fn record_reminder(&self, reminder: SentReminder) -> Result<(), RepositoryError> {
+        let conn = self.lock()?;
+        conn.execute(
+            "INSERT OR REPLACE INTO sent_reminders (key, todo_id, due_at, offset_minutes, sent_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                SentReminder::key(&reminder.todoId, reminder.dueAt, reminder.offsetMinutes),
+                reminder.todoId,
+                reminder.dueAt.timestamp(),
+                reminder.offsetMinutes,
+                reminder.sentAt
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn forget_reminders(&self, before: DateTime<Utc>) -> Result<(), RepositoryError> {
+        let conn = self.lock()?;
+        conn.execute(
+            "DELETE FROM sent_reminders WHERE due_at < ?1",
+            params![before.timestamp()],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn todos_survive_reopen_test() {
+        let path = std::env::temp_dir().join(format!("todos-{}.db", uuid::Uuid::new_v4()));
+        let now = Utc::now();
+        let todo = Todo {
+            id: Some("persisted".to_string()),
+            title: "Test Todo".to_string(),
+            content: "Test Todo Content".to_string(),
+            completed: Some(true),
+            createdAt: Some(now),
+            updatedAt: Some(now),
+            ownerId: Some("alice".to_string()),
+            listId: Some("inbox".to_string()),
+            deletedAt: Some(now),
+            ..Todo::default()
+        };
+
+        SqliteStore::open(&path).unwrap().create(todo).unwrap();
+        let reopened = SqliteStore::open(&path).unwrap();
+        let todo = reopened.get("alice", "persisted").unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(todo.completed, Some(true));
+        assert_eq!(todo.createdAt, Some(now));
+        assert_eq!(todo.updatedAt, Some(now));
+        assert_eq!(todo.listId.as_deref(), Some("inbox"));
+        assert_eq!(todo.deletedAt, Some(now));
+    }
+
+    #[test]
+    fn adopt_unowned_todos_test() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        let conn = store.lock().unwrap();
+        // As left by the migration that added owners
+        conn.execute(
+            "INSERT INTO todos (id, title, content) VALUES ('legacy', 'Old', '')",
+            [],
+        )
+        .unwrap();
+        drop(conn);
+        assert!(store.get("alice", "legacy").unwrap().is_none());
+
+        assert_eq!(store.adopt_unowned_todos("alice").unwrap(), 1);
+        assert_eq!(store.adopt_unowned_todos("bob").unwrap(), 0);
+        let todo = store.get("alice", "legacy").unwrap().unwrap();
+        assert_eq!(todo.title, "Old");
+    }
+}
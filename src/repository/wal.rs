@@ -0,0 +1,706 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::model::{ApiKey, List, SentReminder, Todo, User};
+use crate::repository::memory::MemoryState;
+use crate::repository::{
+    ApiKeyRepository, ListRepository, MemoryStore, Page, ReminderRepository, RepositoryError,
+    TodoFilter, TodoQuery, TodoRepository, UserRepository,
+};
+
+const LOG_FILE: &str = "todos.wal";
+const SNAPSHOT_FILE: &str = "todos.snapshot.json";
+
+// One line of the log. Replaying an entry is idempotent, so entries that were
+// already folded into the snapshot can safely be applied a second time.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum WalEntry {
+    Create {
+        todo: Todo,
+    },
+    Update {
+        todo: Todo,
+    },
+    UpdateMany {
+        todos: Vec<Todo>,
+    },
+    Delete {
+        id: String,
+    },
+    CreateList {
+        list: List,
+    },
+    UpdateList {
+        list: List,
+    },
+    DeleteList {
+        id: String,
+    },
+    CreateUser {
+        user: User,
+    },
+    RevokeToken {
+        token_id: String,
+        expires_at: DateTime<Utc>,
+    },
+    CreateApiKey {
+        api_key: ApiKey,
+    },
+    DeleteApiKey {
+        id: String,
+    },
+    RecordReminder {
+        reminder: SentReminder,
+    },
+    ForgetReminders {
+        before: DateTime<Utc>,
+    },
+}
+
+impl WalEntry {
+    fn apply(self, state: &mut MemoryState) {
+        match self {
+            WalEntry::Create { todo } | WalEntry::Update { todo } => upsert_todo(state, todo),
+            WalEntry::UpdateMany { todos } => {
+                for todo in todos {
+                    upsert_todo(state, todo);
+                }
+            }
+            WalEntry::Delete { id } => state.todos.retain(|todo| todo.id.as_deref() != Some(&id)),
+            WalEntry::CreateList { list } | WalEntry::UpdateList { list } => {
+                match state
+                    .lists
+                    .iter_mut()
+                    .find(|existing| existing.id == list.id)
+                {
+                    Some(existing) => *existing = list,
+                    None => state.lists.push(list),
+                }
+            }
+            WalEntry::DeleteList { id } => state.lists.retain(|list| list.id != id),
+            WalEntry::CreateUser { user } => {
+                match state
+                    .users
+                    .iter_mut()
+                    .find(|existing| existing.id == user.id)
+                {
+                    Some(existing) => *existing = user,
+                    None => state.users.push(user),
+                }
+            }
+            WalEntry::RevokeToken {
+                token_id,
+                expires_at,
+            } => {
+                state.revoked_tokens.insert(token_id, expires_at);
+            }
+            WalEntry::CreateApiKey { api_key } => {
+                match state
+                    .api_keys
+                    .iter_mut()
+                    .find(|existing| existing.id == api_key.id)
+                {
+                    Some(existing) => *existing = api_key,
+                    None => state.api_keys.push(api_key),
+                }
+            }
+            WalEntry::DeleteApiKey { id } => state.api_keys.retain(|key| key.id != id),
+            WalEntry::RecordReminder { reminder } => {
+                let key =
+                    SentReminder::key(&reminder.todoId, reminder.dueAt, reminder.offsetMinutes);
+                state.sent_reminders.insert(key, reminder);
+            }
+            WalEntry::ForgetReminders { before } => state
+                .sent_reminders
+                .retain(|_, reminder| reminder.dueAt >= before),
+        }
+    }
+}
+
+fn upsert_todo(state: &mut MemoryState, todo: Todo) {
+    match state
+        .todos
+        .iter_mut()
+        .find(|existing| existing.id == todo.id)
+    {
+        Some(existing) => *existing = todo,
+        None => state.todos.push(todo),
+    }
+}
+
+struct WriteAheadLog {
+    dir: PathBuf,
+    file: File,
+    entries: usize,
+    compact_every: usize,
+}
+
+// The memory store made durable: every mutation is appended and synced to
+// a log before it is acknowledged, and after `compact_every` entries the full
+// state is written to a snapshot and the log starts over. Opening replays the
+// snapshot and then the log, so a restart reconstructs the exact state.
+pub struct WalStore {
+    memory: MemoryStore,
+    // Held for the whole of every mutation so the log order matches the order
+    // changes were applied in memory.
+    log: Mutex<WriteAheadLog>,
+}
+
+impl WalStore {
+    pub fn open<P: AsRef<Path>>(dir: P, compact_every: usize) -> Result<Self, RepositoryError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut state: MemoryState = match fs::read(dir.join(SNAPSHOT_FILE)) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => MemoryState::default(),
+            Err(err) => return Err(err.into()),
+        };
+        let entries = replay(&dir.join(LOG_FILE), &mut state)?;
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(LOG_FILE))?;
+        Ok(WalStore {
+            memory: MemoryStore::from(state),
+            log: Mutex::new(WriteAheadLog {
+                dir,
+                file,
+                entries,
+                compact_every: compact_every.max(1),
+            }),
+        })
+    }
+
+    // Gives `owner` the todos saved before todos had owners and returns how
+    // many there were
+    pub fn adopt_unowned_todos(&self, owner: &str) -> Result<usize, RepositoryError> {
+        let mut log = self.lock()?;
+        let mut todos = self.memory.unowned_todos()?;
+        if todos.is_empty() {
+            return Ok(0);
+        }
+        for todo in &mut todos {
+            todo.ownerId = Some(owner.to_string());
+        }
+        self.append(&mut log, &WalEntry::UpdateMany { todos })?;
+        let adopted = self.memory.adopt_unowned_todos(owner)?;
+        self.compact_if_due(&mut log);
+        Ok(adopted)
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, WriteAheadLog>, RepositoryError> {
+        self.log
+            .lock()
+            .map_err(|_| RepositoryError::Storage("write-ahead log lock poisoned".to_string()))
+    }
+
+    fn append(&self, log: &mut WriteAheadLog, entry: &WalEntry) -> Result<(), RepositoryError> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        log.file.write_all(&line)?;
+        log.file.sync_data()?;
+        log.entries += 1;
+        Ok(())
+    }
+
+    // Called after a mutation has been applied in memory, still under the log
+    // lock. The mutation is already durable in the log, so a failed compaction
+    // only gets logged and the next write tries again.
+    fn compact_if_due(&self, log: &mut WriteAheadLog) {
+        if log.entries < log.compact_every {
+            return;
+        }
+        if let Err(err) = self.compact(log) {
+            log::warn!(
+                "Compacting {} failed, retrying on the next write: {}",
+                log.dir.display(),
+                err
+            );
+        }
+    }
+
+    fn compact(&self, log: &mut WriteAheadLog) -> Result<(), RepositoryError> {
+        let state = self.memory.snapshot()?;
+        let snapshot = log.dir.join(SNAPSHOT_FILE);
+        let tmp = log.dir.join(format!("{}.tmp", SNAPSHOT_FILE));
+        {
+            let mut writer = BufWriter::new(File::create(&tmp)?);
+            serde_json::to_writer(&mut writer, &state)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+        // The rename is the commit point, once the directory holding it is
+        // synced. Crashing before the truncate below only means the old log is
+        // replayed on top of the new snapshot.
+        fs::rename(&tmp, &snapshot)?;
+        File::open(&log.dir)?.sync_all()?;
+        log.file.set_len(0)?;
+        log.file.sync_all()?;
+        log.entries = 0;
+        Ok(())
+    }
+}
+
+// Applies every complete entry in the log to `state` and returns how many
+// were read. A torn final line from a crash mid-append is cut off the file so
+// new entries start on a clean line.
+fn replay(path: &Path, state: &mut MemoryState) -> Result<usize, RepositoryError> {
+    let file = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut reader = BufReader::new(&file);
+    let mut line = String::new();
+    let mut entries = 0;
+    let mut valid_len = 0;
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 {
+            break;
+        }
+        match serde_json::from_str::<WalEntry>(&line) {
+            Ok(entry) if line.ends_with('\n') => entry.apply(state),
+            result if reader.fill_buf()?.is_empty() => {
+                log::warn!("Discarding incomplete last entry in {}", path.display());
+                if let Err(err) = result {
+                    log::warn!("{}", err);
+                }
+                file.set_len(valid_len)?;
+                break;
+            }
+            Ok(_) => unreachable!("only the last line can lack a newline"),
+            Err(err) => return Err(err.into()),
+        }
+        entries += 1;
+        valid_len += read as u64;
+    }
+    Ok(entries)
+}
+
+impl TodoRepository for WalStore {
+    fn create(&self, todo: Todo) -> Result<Todo, RepositoryError> {
+        let mut log = self.lock()?;
+        // Checked before logging, since replaying a Create overwrites
+        if let Some(id) = todo.id.as_deref() {
+            if self.memory.contains_todo(id)? {
+                return Err(RepositoryError::todo_id_taken(id));
+            }
+        }
+        self.append(&mut log, &WalEntry::Create { todo: todo.clone() })?;
+        let todo = self.memory.create(todo)?;
+        self.compact_if_due(&mut log);
+        Ok(todo)
+    }
+
+    fn get(&self, owner: &str, id: &str) -> Result<Option<Todo>, RepositoryError> {
+        self.memory.get(owner, id)
+    }
+
+    fn list(&self, owner: &str, query: &TodoQuery) -> Result<Page, RepositoryError> {
+        self.memory.list(owner, query)
+    }
+
+    fn update(
+        &self,
+        owner: &str,
+        id: &str,
+        apply: &mut dyn FnMut(&mut Todo),
+    ) -> Result<Option<Todo>, RepositoryError> {
+        let mut log = self.lock()?;
+        let Some(mut todo) = self.memory.get(owner, id)? else {
+            return Ok(None);
+        };
+        let before = todo.clone();
+        apply(&mut todo);
+        // Callers that find nothing to change leave nothing to log
+        if todo == before {
+            return Ok(Some(todo));
+        }
+        self.append(&mut log, &WalEntry::Update { todo: todo.clone() })?;
+        let updated = self
+            .memory
+            .update(owner, id, &mut |stored| *stored = todo.clone())?;
+        self.compact_if_due(&mut log);
+        Ok(updated)
+    }
+
+    fn update_many(
+        &self,
+        owner: &str,
+        ids: &[String],
+        apply: &mut dyn FnMut(&mut Todo),
+    ) -> Result<Vec<Todo>, RepositoryError> {
+        let mut log = self.lock()?;
+        let mut found = Vec::with_capacity(ids.len());
+        let mut changed = Vec::new();
+        for id in ids {
+            if let Some(mut todo) = self.memory.get(owner, id)? {
+                let before = todo.clone();
+                apply(&mut todo);
+                if todo != before {
+                    changed.push(todo.clone());
+                }
+                found.push(todo);
+            }
+        }
+        if changed.is_empty() {
+            return Ok(found);
+        }
+        // One entry, so a crash part way replays all of the changes or none
+        self.append(
+            &mut log,
+            &WalEntry::UpdateMany {
+                todos: changed.clone(),
+            },
+        )?;
+        let by_id: HashMap<_, _> = changed.iter().map(|todo| (&todo.id, todo)).collect();
+        let updated = self.memory.update_many(owner, ids, &mut |stored| {
+            if let Some(todo) = by_id.get(&stored.id) {
+                *stored = (*todo).clone();
+            }
+        })?;
+        self.compact_if_due(&mut log);
+        Ok(updated)
+    }
+
+    fn delete(&self, owner: &str, id: &str) -> Result<bool, RepositoryError> {
+        let mut log = self.lock()?;
+        if self.memory.get(owner, id)?.is_none() {
+            return Ok(false);
+        }
+        self.append(&mut log, &WalEntry::Delete { id: id.to_string() })?;
+        let deleted = self.memory.delete(owner, id)?;
+        self.compact_if_due(&mut log);
+        Ok(deleted)
+    }
+
+    fn delete_if_deleted_before(
+        &self,
+        owner: &str,
+        id: &str,
+        cutoff: DateTime<Utc>,
+    ) -> Result<bool, RepositoryError> {
+        // Every write holds the log, so the todo cannot change after the check
+        let mut log = self.lock()?;
+        let due = self
+            .memory
+            .get(owner, id)?
+            .and_then(|todo| todo.deletedAt)
+            .is_some_and(|deleted_at| deleted_at <= cutoff);
+        if !due {
+            return Ok(false);
+        }
+        self.append(&mut log, &WalEntry::Delete { id: id.to_string() })?;
+        let deleted = self.memory.delete(owner, id)?;
+        self.compact_if_due(&mut log);
+        Ok(deleted)
+    }
+
+    fn list_all(&self, filter: &TodoFilter) -> Result<Vec<Todo>, RepositoryError> {
+        self.memory.list_all(filter)
+    }
+
+    fn max_updated_at(&self, owner: &str) -> Result<Option<DateTime<Utc>>, RepositoryError> {
+        self.memory.max_updated_at(owner)
+    }
+}
+
+impl ListRepository for WalStore {
+    fn create_list(&self, list: List) -> Result<List, RepositoryError> {
+        let mut log = self.lock()?;
+        self.append(&mut log, &WalEntry::CreateList { list: list.clone() })?;
+        let list = self.memory.create_list(list)?;
+        self.compact_if_due(&mut log);
+        Ok(list)
+    }
+
+    fn get_list(&self, owner: &str, id: &str) -> Result<Option<List>, RepositoryError> {
+        self.memory.get_list(owner, id)
+    }
+
+    fn list_lists(&self, owner: &str) -> Result<Vec<List>, RepositoryError> {
+        self.memory.list_lists(owner)
+    }
+
+    fn update_list(
+        &self,
+        owner: &str,
+        id: &str,
+        apply: &mut dyn FnMut(&mut List),
+    ) -> Result<Option<List>, RepositoryError> {
+        let mut log = self.lock()?;
+        let Some(mut list) = self.memory.get_list(owner, id)? else {
+            return Ok(None);
+        };
+        apply(&mut list);
+        self.append(&mut log, &WalEntry::UpdateList { list: list.clone() })?;
+        let updated = self
+            .memory
+            .update_list(owner, id, &mut |stored| *stored = list.clone())?;
+        self.compact_if_due(&mut log);
+        Ok(updated)
+    }
+
+    fn delete_list(&self, owner: &str, id: &str) -> Result<bool, RepositoryError> {
+        let mut log = self.lock()?;
+        if self.memory.get_list(owner, id)?.is_none() {
+            return Ok(false);
+        }
+        self.append(&mut log, &WalEntry::DeleteList { id: id.to_string() })?;
+        let deleted = self.memory.delete_list(owner, id)?;
+        self.compact_if_due(&mut log);
+        Ok(deleted)
+    }
+}
+
+impl UserRepository for WalStore {
+    fn create_user(&self, user: User) -> Result<User, RepositoryError> {
+        let mut log = self.lock()?;
+        if self.memory.find_user_by_username(&user.username)?.is_some() {
+            return Err(RepositoryError::username_taken(&user.username));
+        }
+        self.append(&mut log, &WalEntry::CreateUser { user: user.clone() })?;
+        let user = self.memory.create_user(user)?;
+        self.compact_if_due(&mut log);
+        Ok(user)
+    }
+
+    fn get_user(&self, id: &str) -> Result<Option<User>, RepositoryError> {
+        self.memory.get_user(id)
+    }
+
+    fn find_user_by_username(&self, username: &str) -> Result<Option<User>, RepositoryError> {
+        self.memory.find_user_by_username(username)
+    }
+
+    fn revoke_token(
+        &self,
+        token_id: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        let mut log = self.lock()?;
+        let entry = WalEntry::RevokeToken {
+            token_id: token_id.to_string(),
+            expires_at,
+        };
+        self.append(&mut log, &entry)?;
+        self.memory.revoke_token(token_id, expires_at)?;
+        self.compact_if_due(&mut log);
+        Ok(())
+    }
+
+    fn is_token_revoked(&self, token_id: &str) -> Result<bool, RepositoryError> {
+        self.memory.is_token_revoked(token_id)
+    }
+}
+
+impl ApiKeyRepository for WalStore {
+    fn create_api_key(&self, key: ApiKey) -> Result<ApiKey, RepositoryError> {
+        let mut log = self.lock()?;
+        let entry = WalEntry::CreateApiKey {
+            api_key: key.clone(),
+        };
+        self.append(&mut log, &entry)?;
+        let key = self.memory.create_api_key(key)?;
+        self.compact_if_due(&mut log);
+        Ok(key)
+    }
+
+    fn list_api_keys(&self, owner: &str) -> Result<Vec<ApiKey>, RepositoryError> {
+        self.memory.list_api_keys(owner)
+    }
+
+    fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, RepositoryError> {
+        self.memory.find_api_key(key_hash)
+    }
+
+    fn delete_api_key(&self, owner: &str, id: &str) -> Result<bool, RepositoryError> {
+        let mut log = self.lock()?;
+        let owned = self.memory.list_api_keys(owner)?;
+        if !owned.iter().any(|key| key.id == id) {
+            return Ok(false);
+        }
+        self.append(&mut log, &WalEntry::DeleteApiKey { id: id.to_string() })?;
+        let deleted = self.memory.delete_api_key(owner, id)?;
+        self.compact_if_due(&mut log);
+        Ok(deleted)
+    }
+}
+
+impl ReminderRepository for WalStore {
+    fn is_reminder_sent(&self, key: &str) -> Result<bool, RepositoryError> {
+        self.memory.is_reminder_sent(key)
+    }
+
+    fn record_reminder(&self, reminder: SentReminder) -> Result<(), RepositoryError> {
+        let mut log = self.lock()?;
+        let entry = WalEntry::RecordReminder {
+            reminder: reminder.clone(),
+        };
+        self.append(&mut log, &entry)?;
+        self.memory.record_reminder(reminder)?;
+        self.compact_if_due(&mut log);
+        Ok(())
+    }
+
+    // Only logged when there is something to forget, so idle scans do not
+    // grow the log
+    fn forget_reminders(&self, before: DateTime<Utc>) -> Result<(), RepositoryError> {
+        let mut log = self.lock()?;
+        if !self.memory.has_reminders_before(before)? {
+            return Ok(());
+        }
+        self.append(&mut log, &WalEntry::ForgetReminders { before })?;
+        self.memory.forget_reminders(before)?;
+        self.compact_if_due(&mut log);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn todo(id: &str) -> Todo {
+        let now = Utc::now();
+        Todo {
+            id: Some(id.to_string()),
+            title: id.to_string(),
+            content: "Test Todo Content".to_string(),
+            createdAt: Some(now),
+            updatedAt: Some(now),
+            ownerId: Some("alice".to_string()),
+            ..Todo::default()
+        }
+    }
+
+    fn titles(repository: &WalStore) -> Vec<String> {
+        let page = repository.list("alice", &TodoQuery::default()).unwrap();
+        page.items.into_iter().map(|todo| todo.title).collect()
+    }
+
+    #[test]
+    fn replay_reconstructs_state_test() {
+        let dir = std::env::temp_dir().join(format!("todos-wal-{}", uuid::Uuid::new_v4()));
+        // Compacting every 3 entries leaves both a snapshot and a log tail.
+        let repository = WalStore::open(&dir, 3).unwrap();
+        for id in ["first", "second", "third", "fourth"] {
+            repository.create(todo(id)).unwrap();
+        }
+        repository
+            .update("alice", "third", &mut |todo| {
+                todo.title = "renamed".to_string()
+            })
+            .unwrap();
+        repository.delete("alice", "second").unwrap();
+        let ids = ["first".to_string(), "fourth".to_string()];
+        repository
+            .update_many("alice", &ids, &mut |todo| todo.title.make_ascii_uppercase())
+            .unwrap();
+        let before = titles(&repository);
+        let latest = repository.max_updated_at("alice").unwrap();
+        drop(repository);
+
+        // Simulate a crash in the middle of an append.
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(dir.join(LOG_FILE))
+            .unwrap();
+        log.write_all(b"{\"op\":\"delete\",\"i").unwrap();
+        drop(log);
+
+        let reopened = WalStore::open(&dir, 3).unwrap();
+        let after = titles(&reopened);
+        let reopened_latest = reopened.max_updated_at("alice").unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(before, ["FIRST", "renamed", "FOURTH"]);
+        assert_eq!(after, before);
+        assert!(latest.is_some());
+        assert_eq!(reopened_latest, latest);
+    }
+
+    #[test]
+    fn unchanged_updates_are_not_logged_test() {
+        let dir = std::env::temp_dir().join(format!("todos-wal-{}", uuid::Uuid::new_v4()));
+        let repository = WalStore::open(&dir, 100).unwrap();
+        repository.create(todo("first")).unwrap();
+        let updated = repository.update("alice", "first", &mut |_| {}).unwrap();
+        let ids = ["first".to_string()];
+        repository
+            .update_many("alice", &ids, &mut |todo| todo.title = "first".to_string())
+            .unwrap();
+        let entries = repository.lock().unwrap().entries;
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(updated.map(|todo| todo.title).as_deref(), Some("first"));
+        assert_eq!(entries, 1);
+    }
+
+    #[test]
+    fn adopt_unowned_todos_test() {
+        let dir = std::env::temp_dir().join(format!("todos-wal-{}", uuid::Uuid::new_v4()));
+        let repository = WalStore::open(&dir, 100).unwrap();
+        // As saved before todos had owners
+        let legacy = Todo {
+            ownerId: None,
+            ..todo("legacy")
+        };
+        repository.create(legacy).unwrap();
+        repository.create(todo("owned")).unwrap();
+        assert!(repository.get("bob", "legacy").unwrap().is_none());
+
+        assert_eq!(repository.adopt_unowned_todos("bob").unwrap(), 1);
+        assert_eq!(repository.adopt_unowned_todos("carol").unwrap(), 0);
+        drop(repository);
+        let reopened = WalStore::open(&dir, 100).unwrap();
+        let legacy = reopened.get("bob", "legacy").unwrap();
+        let owned = reopened.get("alice", "owned").unwrap();
+        let latest = reopened.max_updated_at("bob").unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(legacy.unwrap().title, "legacy");
+        assert!(owned.is_some());
+        assert!(latest.is_some());
+    }
+
+    #[test]
+    fn failed_compaction_is_retried_test() {
+        let dir = std::env::temp_dir().join(format!("todos-wal-{}", uuid::Uuid::new_v4()));
+        let repository = WalStore::open(&dir, 1).unwrap();
+        // A directory in the way of the temporary snapshot makes compacting fail
+        let tmp = dir.join(format!("{}.tmp", SNAPSHOT_FILE));
+        fs::create_dir(&tmp).unwrap();
+        repository.create(todo("first")).unwrap();
+        let pending = repository.lock().unwrap().entries;
+        let compacted = dir.join(SNAPSHOT_FILE).exists();
+
+        fs::remove_dir(&tmp).unwrap();
+        repository.create(todo("second")).unwrap();
+        let entries = repository.lock().unwrap().entries;
+        drop(repository);
+        let reopened = WalStore::open(&dir, 1).unwrap();
+        let after = titles(&reopened);
+        let recompacted = dir.join(SNAPSHOT_FILE).exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(pending, 1);
+        assert!(!compacted);
+        assert_eq!(entries, 0);
+        assert!(recompacted);
+        assert_eq!(after, ["first", "second"]);
+    }
+}
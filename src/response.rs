@@ -1,16 +1,34 @@
+use actix_web::{http::StatusCode, HttpResponse, HttpResponseBuilder, ResponseError};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::model::Todo;
+use crate::model::{ApiKeyInfo, List, Subtasks, TagCount, Todo, UserProfile};
+use crate::repository::RepositoryError;
+use crate::search::SearchHit;
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct GenericResponse {
     pub status: String,
     pub message: String,
 }
 
+// The JSON error every handler answers with, under `response`'s status
+pub fn error_response(mut response: HttpResponseBuilder, message: &str) -> HttpResponse {
+    response.json(GenericResponse {
+        status: "error".to_string(),
+        message: message.to_string(),
+    })
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TodoData {
     pub todo: Todo,
+    // Created when completing a recurring todo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_occurrence: Option<Todo>,
+    // Completion of the direct subtasks, when there are any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtasks: Option<Subtasks>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -19,9 +37,134 @@ pub struct SingleTodoResponse {
     pub data: TodoData,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct TodoListResponse {
     pub status: String,
+    // Todos on this page; `total` counts every match
     pub results: usize,
+    pub total: usize,
+    pub has_more: bool,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
     pub todos: Vec<Todo>,
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TodoNode {
+    pub todo: Todo,
+    pub subtasks: Subtasks,
+    pub children: Vec<TodoNode>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TodoTreeResponse {
+    pub status: String,
+    // Top-level nodes
+    pub results: usize,
+    pub tree: Vec<TodoNode>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TagListResponse {
+    pub status: String,
+    pub results: usize,
+    pub tags: Vec<TagCount>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TagData {
+    pub tag: TagCount,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SingleTagResponse {
+    pub status: String,
+    pub data: TagData,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListListResponse {
+    pub status: String,
+    pub results: usize,
+    pub lists: Vec<List>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListData {
+    pub list: List,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SingleListResponse {
+    pub status: String,
+    pub data: ListData,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchResponse {
+    pub status: String,
+    pub results: usize,
+    // Best match first
+    pub hits: Vec<SearchHit>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OccurrenceListResponse {
+    pub status: String,
+    pub results: usize,
+    pub has_more: bool,
+    pub recurrence: Option<String>,
+    pub occurrences: Vec<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UserData {
+    pub user: UserProfile,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SingleUserResponse {
+    pub status: String,
+    pub data: UserData,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TokenResponse {
+    pub status: String,
+    pub token: String,
+    pub token_type: String,
+    pub expires_in: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ApiKeyData {
+    pub api_key: ApiKeyInfo,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreatedApiKeyResponse {
+    pub status: String,
+    // The plain key, shown only once
+    pub key: String,
+    pub data: ApiKeyData,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ApiKeyListResponse {
+    pub status: String,
+    pub results: usize,
+    pub api_keys: Vec<ApiKeyInfo>,
+}
+
+impl ResponseError for RepositoryError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            RepositoryError::Conflict(_) => StatusCode::CONFLICT,
+            RepositoryError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        error_response(HttpResponse::build(self.status_code()), &self.to_string())
+    }
+}
@@ -0,0 +1,594 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::model::Todo;
+use crate::repository::{Page, RepositoryError, TodoFilter, TodoQuery, TodoRepository};
+
+// Title matches count this much more than content matches
+const TITLE_WEIGHT: f64 = 2.0;
+// BM25 parameters
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+// Longest content snippet, in characters
+const SNIPPET_LENGTH: usize = 160;
+// Characters of context kept before the first match in a snippet
+const SNIPPET_LEAD: usize = 40;
+
+// One part of a search query. A todo has to match every clause.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Clause {
+    Term(String),
+    // Matches any word starting with this
+    Prefix(String),
+    // Consecutive words within one field
+    Phrase(Vec<String>),
+}
+
+// Parses queries such as `report "quarterly numbers" draf*`. Words in quotes
+// form a phrase, a trailing `*` makes a prefix.
+pub fn parse_query(query: &str) -> Result<Vec<Clause>, String> {
+    let mut clauses = Vec::new();
+    for (index, part) in query.split('"').enumerate() {
+        if index % 2 == 1 {
+            let words = words(part);
+            match words.len() {
+                0 => {}
+                1 => clauses.extend(words.into_iter().map(Clause::Term)),
+                _ => clauses.push(Clause::Phrase(words)),
+            }
+            continue;
+        }
+        for word in part.split_whitespace() {
+            let (word, prefix) = match word.strip_suffix('*') {
+                Some(word) => (word, true),
+                None => (word, false),
+            };
+            let mut words = words(word);
+            match words.pop() {
+                Some(last) if prefix && words.is_empty() => clauses.push(Clause::Prefix(last)),
+                Some(last) if words.is_empty() => clauses.push(Clause::Term(last)),
+                Some(last) => {
+                    // "e-mail" is searched as the phrase "e mail"
+                    words.push(last);
+                    clauses.push(Clause::Phrase(words));
+                }
+                None => {}
+            }
+        }
+    }
+    if clauses.is_empty() {
+        return Err("Search query has no words.".to_string());
+    }
+    Ok(clauses)
+}
+
+// Lowercased words of `text` with their byte ranges
+fn tokens(text: &str) -> Vec<(usize, usize, String)> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (index, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(index),
+            (false, Some(from)) => {
+                tokens.push((from, index, text[from..index].to_lowercase()));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+fn words(text: &str) -> Vec<String> {
+    tokens(text).into_iter().map(|(_, _, word)| word).collect()
+}
+
+// Positions in `words` where `clause` matches, with the number of words each
+// match spans
+fn matches(words: &[String], clause: &Clause) -> Vec<(usize, usize)> {
+    match clause {
+        Clause::Term(term) => (0..words.len())
+            .filter(|&at| &words[at] == term)
+            .map(|at| (at, 1))
+            .collect(),
+        Clause::Prefix(prefix) => (0..words.len())
+            .filter(|&at| words[at].starts_with(prefix.as_str()))
+            .map(|at| (at, 1))
+            .collect(),
+        Clause::Phrase(phrase) => words
+            .windows(phrase.len())
+            .enumerate()
+            .filter(|(_, window)| window == phrase)
+            .map(|(at, _)| (at, phrase.len()))
+            .collect(),
+    }
+}
+
+struct Document {
+    todo: Todo,
+    title: Vec<String>,
+    content: Vec<String>,
+}
+
+impl Document {
+    fn new(todo: Todo) -> Self {
+        Document {
+            title: words(&todo.title),
+            content: words(&todo.content),
+            todo,
+        }
+    }
+
+    fn weighted_length(&self) -> f64 {
+        TITLE_WEIGHT * self.title.len() as f64 + self.content.len() as f64
+    }
+}
+
+// The inverted index of one owner's todos
+#[derive(Default)]
+struct OwnerIndex {
+    documents: HashMap<String, Document>,
+    // word -> ids of the todos containing it. Ordered, so prefixes are a
+    // range scan.
+    postings: BTreeMap<String, HashSet<String>>,
+}
+
+impl OwnerIndex {
+    fn insert(&mut self, todo: Todo) {
+        let Some(id) = todo.id.clone() else {
+            return;
+        };
+        self.remove(&id);
+        let document = Document::new(todo);
+        for word in document.title.iter().chain(&document.content) {
+            self.postings
+                .entry(word.clone())
+                .or_default()
+                .insert(id.clone());
+        }
+        self.documents.insert(id, document);
+    }
+
+    fn remove(&mut self, id: &str) {
+        let Some(document) = self.documents.remove(id) else {
+            return;
+        };
+        for word in document.title.iter().chain(&document.content) {
+            if let Some(ids) = self.postings.get_mut(word) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    self.postings.remove(word);
+                }
+            }
+        }
+    }
+
+    // Ids of the todos that may match `clause`, from the postings alone
+    fn candidates(&self, clause: &Clause) -> HashSet<&str> {
+        let ids = |word: &str| -> HashSet<&str> {
+            self.postings
+                .get(word)
+                .map(|ids| ids.iter().map(String::as_str).collect())
+                .unwrap_or_default()
+        };
+        match clause {
+            Clause::Term(term) => ids(term),
+            Clause::Prefix(prefix) => self
+                .postings
+                .range(prefix.clone()..)
+                .take_while(|(word, _)| word.starts_with(prefix.as_str()))
+                .flat_map(|(_, ids)| ids.iter().map(String::as_str))
+                .collect(),
+            Clause::Phrase(phrase) => phrase
+                .iter()
+                .map(|word| ids(word))
+                .reduce(|all, next| &all & &next)
+                .unwrap_or_default(),
+        }
+    }
+
+    fn search(&self, clauses: &[Clause], limit: usize) -> Vec<SearchHit> {
+        let count = self.documents.len() as f64;
+        let average_length = self
+            .documents
+            .values()
+            .map(Document::weighted_length)
+            .sum::<f64>()
+            / count.max(1.0);
+
+        let mut scores: Option<HashMap<&str, f64>> = None;
+        for clause in clauses {
+            let candidates = self.candidates(clause);
+            let mut clause_scores = HashMap::new();
+            for id in candidates {
+                let document = &self.documents[id];
+                let frequency = TITLE_WEIGHT * matches(&document.title, clause).len() as f64
+                    + matches(&document.content, clause).len() as f64;
+                if frequency > 0.0 {
+                    clause_scores.insert(id, frequency);
+                }
+            }
+            // BM25 over the title-weighted term frequency
+            let found = clause_scores.len() as f64;
+            let idf = (1.0 + (count - found + 0.5) / (found + 0.5)).ln();
+            let clause_scores: HashMap<&str, f64> = clause_scores
+                .into_iter()
+                .map(|(id, frequency)| {
+                    let length = self.documents[id].weighted_length() / average_length.max(1.0);
+                    let score =
+                        idf * frequency * (K1 + 1.0) / (frequency + K1 * (1.0 - B + B * length));
+                    (id, score)
+                })
+                .collect();
+            scores = Some(match scores {
+                None => clause_scores,
+                Some(scores) => scores
+                    .into_iter()
+                    .filter_map(|(id, score)| clause_scores.get(id).map(|more| (id, score + more)))
+                    .collect(),
+            });
+        }
+
+        let mut ranked: Vec<(&str, f64)> = scores.unwrap_or_default().into_iter().collect();
+        ranked.sort_by(|(a_id, a), (b_id, b)| b.total_cmp(a).then_with(|| a_id.cmp(b_id)));
+        ranked
+            .into_iter()
+            .take(limit)
+            .map(|(id, score)| {
+                let document = &self.documents[id];
+                SearchHit {
+                    score,
+                    highlights: Highlights {
+                        title: highlight(&document.todo.title, clauses, None),
+                        content: highlight(&document.todo.content, clauses, Some(SNIPPET_LENGTH)),
+                    },
+                    todo: document.todo.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// `text` with the words matching `clauses` wrapped in <mark> tags, cut down
+// to about `length` characters around the first match when given. The text
+// itself is HTML escaped, so the result can be rendered as is.
+fn highlight(text: &str, clauses: &[Clause], length: Option<usize>) -> String {
+    let tokens = tokens(text);
+    let words: Vec<String> = tokens.iter().map(|(_, _, word)| word.clone()).collect();
+    let mut marked = vec![false; tokens.len()];
+    for clause in clauses {
+        for (at, span) in matches(&words, clause) {
+            marked[at..at + span].fill(true);
+        }
+    }
+
+    let (mut from, mut to) = (0, text.len());
+    if let Some(length) = length {
+        if text.chars().count() > length {
+            let first = marked.iter().position(|&marked| marked);
+            let start = first.map_or(0, |at| tokens[at].0);
+            from = text[..start]
+                .char_indices()
+                .rev()
+                .nth(SNIPPET_LEAD.saturating_sub(1))
+                .map_or(0, |(index, _)| index);
+            to = text[from..]
+                .char_indices()
+                .nth(length)
+                .map_or(text.len(), |(index, _)| from + index);
+        }
+    }
+
+    let mut snippet = String::new();
+    if from > 0 {
+        snippet.push('…');
+    }
+    // Runs of marked words share one <mark>, so a phrase reads as one match
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+    for (index, &(start, end, _)) in tokens.iter().enumerate() {
+        if !marked[index] || start < from || end > to {
+            continue;
+        }
+        match spans.last_mut() {
+            Some(span) if index > 0 && marked[index - 1] && span.1 == tokens[index - 1].1 => {
+                span.1 = end
+            }
+            _ => spans.push((start, end)),
+        }
+    }
+    let mut at = from;
+    for (start, end) in spans {
+        snippet.push_str(&escape(&text[at..start]));
+        snippet.push_str("<mark>");
+        snippet.push_str(&escape(&text[start..end]));
+        snippet.push_str("</mark>");
+        at = end;
+    }
+    snippet.push_str(&escape(&text[at..to]));
+    if to < text.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Highlights {
+    pub title: String,
+    pub content: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchHit {
+    pub score: f64,
+    pub todo: Todo,
+    pub highlights: Highlights,
+}
+
+// Wraps a todo store and keeps a full-text index of its todos in step with
+// every write. An owner's index is built from the store on their first search
+// and kept in memory from then on.
+pub struct SearchIndexedStore {
+    store: Arc<dyn TodoRepository>,
+    owners: Mutex<HashMap<String, OwnerIndex>>,
+}
+
+impl SearchIndexedStore {
+    pub fn new(store: Arc<dyn TodoRepository>) -> Self {
+        SearchIndexedStore {
+            store,
+            owners: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, HashMap<String, OwnerIndex>>, RepositoryError> {
+        self.owners
+            .lock()
+            .map_err(|_| RepositoryError::Storage("search index lock poisoned".to_string()))
+    }
+
+    // The owner's best matches for `clauses`, highest score first
+    pub fn search(
+        &self,
+        owner: &str,
+        clauses: &[Clause],
+        limit: usize,
+    ) -> Result<Vec<SearchHit>, RepositoryError> {
+        let mut owners = self.lock()?;
+        if !owners.contains_key(owner) {
+            let mut index = OwnerIndex::default();
+            for todo in self.store.list(owner, &TodoQuery::default())?.items {
+                index.insert(todo);
+            }
+            owners.insert(owner.to_string(), index);
+        }
+        Ok(owners[owner].search(clauses, limit))
+    }
+}
+
+// Writes hold the index lock across the store call, so a search never sees
+// the index and the store disagree.
+impl TodoRepository for SearchIndexedStore {
+    fn create(&self, todo: Todo) -> Result<Todo, RepositoryError> {
+        let mut owners = self.lock()?;
+        let todo = self.store.create(todo)?;
+        if let Some(index) = todo
+            .ownerId
+            .as_ref()
+            .and_then(|owner| owners.get_mut(owner))
+        {
+            index.insert(todo.clone());
+        }
+        Ok(todo)
+    }
+
+    fn get(&self, owner: &str, id: &str) -> Result<Option<Todo>, RepositoryError> {
+        self.store.get(owner, id)
+    }
+
+    fn list(&self, owner: &str, query: &TodoQuery) -> Result<Page, RepositoryError> {
+        self.store.list(owner, query)
+    }
+
+    fn update(
+        &self,
+        owner: &str,
+        id: &str,
+        apply: &mut dyn FnMut(&mut Todo),
+    ) -> Result<Option<Todo>, RepositoryError> {
+        let mut owners = self.lock()?;
+        let todo = self.store.update(owner, id, apply)?;
+        if let (Some(todo), Some(index)) = (&todo, owners.get_mut(owner)) {
+            // Trashed todos drop out of search until they are restored
+            match todo.deletedAt {
+                Some(_) => index.remove(id),
+                None => index.insert(todo.clone()),
+            }
+        }
+        Ok(todo)
+    }
+
+    fn update_many(
+        &self,
+        owner: &str,
+        ids: &[String],
+        apply: &mut dyn FnMut(&mut Todo),
+    ) -> Result<Vec<Todo>, RepositoryError> {
+        let mut owners = self.lock()?;
+        let todos = self.store.update_many(owner, ids, apply)?;
+        if let Some(index) = owners.get_mut(owner) {
+            for todo in &todos {
+                match (&todo.id, todo.deletedAt) {
+                    (Some(id), Some(_)) => index.remove(id),
+                    (Some(_), None) => index.insert(todo.clone()),
+                    (None, _) => {}
+                }
+            }
+        }
+        Ok(todos)
+    }
+
+    fn delete(&self, owner: &str, id: &str) -> Result<bool, RepositoryError> {
+        let mut owners = self.lock()?;
+        let deleted = self.store.delete(owner, id)?;
+        if let Some(index) = owners.get_mut(owner) {
+            index.remove(id);
+        }
+        Ok(deleted)
+    }
+
+    fn delete_if_deleted_before(
+        &self,
+        owner: &str,
+        id: &str,
+        cutoff: DateTime<Utc>,
+    ) -> Result<bool, RepositoryError> {
+        let mut owners = self.lock()?;
+        let deleted = self.store.delete_if_deleted_before(owner, id, cutoff)?;
+        if deleted {
+            if let Some(index) = owners.get_mut(owner) {
+                index.remove(id);
+            }
+        }
+        Ok(deleted)
+    }
+
+    fn list_all(&self, filter: &TodoFilter) -> Result<Vec<Todo>, RepositoryError> {
+        self.store.list_all(filter)
+    }
+
+    fn max_updated_at(&self, owner: &str) -> Result<Option<DateTime<Utc>>, RepositoryError> {
+        self.store.max_updated_at(owner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::MemoryStore;
+
+    fn todo(id: &str, title: &str, content: &str) -> Todo {
+        Todo {
+            id: Some(id.to_string()),
+            title: title.to_string(),
+            content: content.to_string(),
+            ownerId: Some("alice".to_string()),
+            ..Todo::default()
+        }
+    }
+
+    fn ids(hits: &[SearchHit]) -> Vec<&str> {
+        hits.iter()
+            .filter_map(|hit| hit.todo.id.as_deref())
+            .collect()
+    }
+
+    #[test]
+    fn parse_query_test() {
+        assert_eq!(
+            parse_query(r#"Report "Quarterly  numbers" draf* e-mail"#).unwrap(),
+            [
+                Clause::Term("report".to_string()),
+                Clause::Phrase(vec!["quarterly".to_string(), "numbers".to_string()]),
+                Clause::Prefix("draf".to_string()),
+                Clause::Phrase(vec!["e".to_string(), "mail".to_string()]),
+            ]
+        );
+        assert!(parse_query(" \"\" * ").is_err());
+    }
+
+    #[test]
+    fn search_ranks_phrases_and_prefixes_test() {
+        let store = SearchIndexedStore::new(Arc::new(MemoryStore::new()));
+        store
+            .create(todo("a", "Groceries", "buy milk and bread"))
+            .unwrap();
+        store
+            .create(todo("b", "Milk run", "milk for the office"))
+            .unwrap();
+        store.create(todo("c", "Bake", "bread needs milk")).unwrap();
+
+        // Title matches outweigh content matches
+        let hits = store
+            .search("alice", &parse_query("milk").unwrap(), 10)
+            .unwrap();
+        assert_eq!(ids(&hits)[0], "b");
+        assert_eq!(hits.len(), 3);
+
+        let hits = store
+            .search("alice", &parse_query("\"milk and bread\"").unwrap(), 10)
+            .unwrap();
+        assert_eq!(ids(&hits), ["a"]);
+        assert_eq!(
+            hits[0].highlights.content,
+            "buy <mark>milk and bread</mark>"
+        );
+
+        let hits = store
+            .search("alice", &parse_query("bak* milk").unwrap(), 10)
+            .unwrap();
+        assert_eq!(ids(&hits), ["c"]);
+        assert_eq!(hits[0].highlights.title, "<mark>Bake</mark>");
+
+        assert!(store
+            .search("bob", &parse_query("milk").unwrap(), 10)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            store
+                .search("alice", &parse_query("milk").unwrap(), 1)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn index_follows_writes_test() {
+        let store = SearchIndexedStore::new(Arc::new(MemoryStore::new()));
+        store
+            .create(todo("a", "Call Bob", "about the <invoice>"))
+            .unwrap();
+        let search = |query: &str| {
+            store
+                .search("alice", &parse_query(query).unwrap(), 10)
+                .unwrap()
+        };
+        assert_eq!(
+            search("invoice")[0].highlights.content,
+            "about the &lt;<mark>invoice</mark>&gt;"
+        );
+
+        // Indexed from here on
+        store.create(todo("b", "Call Carol", "")).unwrap();
+        assert_eq!(search("call").len(), 2);
+
+        store
+            .update("alice", "a", &mut |todo| {
+                todo.title = "Email Bob".to_string()
+            })
+            .unwrap();
+        assert_eq!(ids(&search("call")), ["b"]);
+        assert_eq!(ids(&search("email")), ["a"]);
+
+        store.delete("alice", "b").unwrap();
+        assert!(search("call").is_empty());
+    }
+
+    #[test]
+    fn long_content_is_cut_around_the_match_test() {
+        let content = format!("{} needle {}", "hay ".repeat(50), "hay ".repeat(50));
+        let snippet = highlight(&content, &[Clause::Term("needle".to_string())], Some(60));
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'));
+        assert!(snippet.contains("<mark>needle</mark>"));
+        assert!(snippet.chars().count() < 80);
+    }
+}
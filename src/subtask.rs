@@ -0,0 +1,359 @@
+use std::collections::{HashMap, HashSet};
+
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use crate::{
+    auth::Principal,
+    handler::{todo_list_response, NOT_FOUND},
+    model::{AppState, QueryOptions, Subtasks, Todo},
+    repository::{RepositoryError, TodoFilter, TodoQuery},
+    response::{error_response, TodoNode, TodoTreeResponse},
+    trash::live_todo,
+};
+
+const CYCLE: &str = "A todo cannot be moved under itself or its subtasks.";
+
+fn children_query(id: &str) -> TodoQuery {
+    TodoQuery {
+        filter: TodoFilter {
+            parent_id: Some(id.to_string()),
+            ..TodoFilter::default()
+        },
+        ..TodoQuery::default()
+    }
+}
+
+fn count(children: &[Todo]) -> Subtasks {
+    Subtasks {
+        total: children.len(),
+        completed: children
+            .iter()
+            .filter(|child| child.completed == Some(true))
+            .count(),
+    }
+}
+
+// The direct subtasks of a todo
+pub fn children(app_state: &AppState, owner: &str, id: &str) -> Result<Vec<Todo>, RepositoryError> {
+    Ok(app_state.todo_db.list(owner, &children_query(id))?.items)
+}
+
+// Completion of a todo's direct subtasks, or None when it has none
+pub fn rollup(
+    app_state: &AppState,
+    owner: &str,
+    id: &str,
+) -> Result<Option<Subtasks>, RepositoryError> {
+    let subtasks = count(&children(app_state, owner, id)?);
+    Ok(Some(subtasks).filter(|subtasks| subtasks.total > 0))
+}
+
+// Every todo below `id`, deepest first
+pub fn descendants(
+    app_state: &AppState,
+    owner: &str,
+    id: &str,
+) -> Result<Vec<Todo>, RepositoryError> {
+    let mut found = Vec::new();
+    let mut seen = HashSet::from([id.to_string()]);
+    let mut level = vec![id.to_string()];
+    while !level.is_empty() {
+        let mut next = Vec::new();
+        for parent in &level {
+            for child in children(app_state, owner, parent)? {
+                let Some(child_id) = child.id.clone() else {
+                    continue;
+                };
+                if seen.insert(child_id.clone()) {
+                    next.push(child_id);
+                    found.push(child);
+                }
+            }
+        }
+        level = next;
+    }
+    found.reverse();
+    Ok(found)
+}
+
+// Why `parent` cannot become the parent of `id` (None for a new todo), if it
+// cannot. The check reads the hierarchy before the write that changes it, so
+// two concurrent moves could still close a loop; tree building tolerates that.
+pub fn parent_error(
+    app_state: &AppState,
+    owner: &str,
+    id: Option<&str>,
+    parent: &str,
+) -> Result<Option<String>, RepositoryError> {
+    if id == Some(parent) {
+        return Ok(Some(CYCLE.to_string()));
+    }
+    let Some(mut ancestor) = live_todo(app_state, owner, parent)? else {
+        return Ok(Some("Parent todo not found.".to_string()));
+    };
+    // Walking up from the new parent must not reach the todo being moved
+    let mut seen = HashSet::new();
+    while let Some(next) = ancestor.parentId.take() {
+        if id == Some(next.as_str()) {
+            return Ok(Some(CYCLE.to_string()));
+        }
+        if !seen.insert(next.clone()) {
+            break;
+        }
+        match app_state.todo_db.get(owner, &next)? {
+            Some(todo) => ancestor = todo,
+            None => break,
+        }
+    }
+    Ok(None)
+}
+
+// Subtasks keyed by the id of their parent
+type Children = HashMap<String, Vec<Todo>>;
+
+// Takes each todo's subtasks out of `children`, so a loop in the stored
+// hierarchy ends the branch instead of recursing forever
+fn build(todo: Todo, children: &mut Children) -> TodoNode {
+    let own = todo
+        .id
+        .as_ref()
+        .and_then(|id| children.remove(id))
+        .unwrap_or_default();
+    let subtasks = count(&own);
+    TodoNode {
+        todo,
+        subtasks,
+        children: own
+            .into_iter()
+            .map(|child| build(child, children))
+            .collect(),
+    }
+}
+
+// All of the owner's todos grouped by parent, in creation order
+fn by_parent(app_state: &AppState, owner: &str) -> Result<(Vec<Todo>, Children), RepositoryError> {
+    let todos = app_state.todo_db.list(owner, &TodoQuery::default())?.items;
+    let ids: HashSet<String> = todos.iter().filter_map(|todo| todo.id.clone()).collect();
+    let mut roots = Vec::new();
+    let mut children = Children::new();
+    for todo in todos {
+        match todo.parentId.clone().filter(|parent| ids.contains(parent)) {
+            Some(parent) => children.entry(parent).or_default().push(todo),
+            // Subtasks of deleted todos show up at the top
+            None => roots.push(todo),
+        }
+    }
+    Ok((roots, children))
+}
+
+fn tree_response(tree: Vec<TodoNode>) -> HttpResponse {
+    HttpResponse::Ok().json(TodoTreeResponse {
+        status: "success".to_string(),
+        results: tree.len(),
+        tree,
+    })
+}
+
+// List a todo's direct subtasks, filtered, sorted and paged like /todos
+pub async fn get_children(
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    path: web::Path<String>,
+    query: web::Query<QueryOptions>,
+) -> Result<HttpResponse, RepositoryError> {
+    let id = path.into_inner();
+    if live_todo(&app_state, &principal.subject, &id)?.is_none() {
+        return Ok(error_response(HttpResponse::NotFound(), NOT_FOUND));
+    }
+    todo_list_response(&req, &app_state, &principal.subject, &query, |filter| {
+        filter.parent_id = Some(id)
+    })
+}
+
+// Every todo of the caller, nested under its parent
+pub async fn get_tree(
+    app_state: web::Data<AppState>,
+    principal: Principal,
+) -> Result<HttpResponse, RepositoryError> {
+    let (roots, mut children) = by_parent(&app_state, &principal.subject)?;
+    let tree = roots
+        .into_iter()
+        .map(|root| build(root, &mut children))
+        .collect();
+    Ok(tree_response(tree))
+}
+
+// A todo with everything below it
+pub async fn get_subtree(
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    path: web::Path<String>,
+) -> Result<HttpResponse, RepositoryError> {
+    let id = path.into_inner();
+    let (roots, mut children) = by_parent(&app_state, &principal.subject)?;
+    let todo = roots
+        .into_iter()
+        .chain(children.values().flatten().cloned())
+        .find(|todo| todo.id.as_deref() == Some(id.as_str()));
+    match todo {
+        Some(todo) => Ok(tree_response(vec![build(todo, &mut children)])),
+        None => Ok(error_response(HttpResponse::NotFound(), NOT_FOUND)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::{SingleTodoResponse, TodoListResponse};
+    use crate::test_support::{api, app_states, request};
+    use actix_web::{http, test};
+    use serde_json::json;
+
+    fn titles(nodes: &[TodoNode]) -> Vec<String> {
+        nodes
+            .iter()
+            .map(|node| match node.children.is_empty() {
+                true => node.todo.title.clone(),
+                false => format!("{} {:?}", node.todo.title, titles(&node.children)),
+            })
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn subtasks_test() {
+        for app_data in app_states() {
+            let app = api(&app_data).await;
+            let mut ids = HashMap::new();
+            for (title, parent) in [
+                ("Move", None),
+                ("Pack", Some("Move")),
+                ("Books", Some("Pack")),
+                ("Kitchen", Some("Pack")),
+                ("Book van", Some("Move")),
+                ("Taxes", None),
+            ] {
+                let body = json!({
+                    "title": title,
+                    "content": "",
+                    "parentId": parent.map(|parent| &ids[parent]),
+                });
+                let created: SingleTodoResponse = test::call_and_read_body_json(
+                    &app,
+                    request(http::Method::POST, "/api/v1/todos", body),
+                )
+                .await;
+                ids.insert(title, created.data.todo.id.unwrap());
+            }
+            let resp = test::call_service(
+                &app,
+                request(
+                    http::Method::POST,
+                    "/api/v1/todos",
+                    json!({ "title": "Lost", "content": "", "parentId": "missing" }),
+                ),
+            )
+            .await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+
+            let todo_uri = |title: &str| format!("/api/v1/todos/{}", ids[title]);
+            test::call_service(
+                &app,
+                request(
+                    http::Method::PATCH,
+                    &todo_uri("Books"),
+                    json!({ "completed": true }),
+                ),
+            )
+            .await;
+
+            let get = |uri: &str| request(http::Method::GET, uri, json!({}));
+            let pack: SingleTodoResponse =
+                test::call_and_read_body_json(&app, get(&todo_uri("Pack"))).await;
+            assert_eq!(
+                pack.data.subtasks,
+                Some(Subtasks {
+                    total: 2,
+                    completed: 1
+                })
+            );
+            let taxes: SingleTodoResponse =
+                test::call_and_read_body_json(&app, get(&todo_uri("Taxes"))).await;
+            assert_eq!(taxes.data.subtasks, None);
+
+            let children: TodoListResponse = test::call_and_read_body_json(
+                &app,
+                get(&format!("{}/children?completed=false", todo_uri("Pack"))),
+            )
+            .await;
+            assert_eq!(children.todos.len(), 1);
+            assert_eq!(children.todos[0].title, "Kitchen");
+            let resp = test::call_service(&app, get("/api/v1/todos/missing/children")).await;
+            assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+
+            let tree: TodoTreeResponse =
+                test::call_and_read_body_json(&app, get("/api/v1/todos/tree")).await;
+            assert_eq!(
+                titles(&tree.tree),
+                [
+                    r#"Move ["Pack [\"Books\", \"Kitchen\"]", "Book van"]"#,
+                    "Taxes"
+                ]
+            );
+            assert_eq!(tree.tree[0].subtasks.total, 2);
+            let subtree: TodoTreeResponse =
+                test::call_and_read_body_json(&app, get(&format!("{}/tree", todo_uri("Pack"))))
+                    .await;
+            assert_eq!(titles(&subtree.tree), [r#"Pack ["Books", "Kitchen"]"#]);
+
+            // Moving a todo under itself or below itself is refused
+            for parent in ["Move", "Pack", "Kitchen"] {
+                let resp = test::call_service(
+                    &app,
+                    request(
+                        http::Method::PATCH,
+                        &todo_uri("Move"),
+                        json!({ "parentId": ids[parent] }),
+                    ),
+                )
+                .await;
+                assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST, "{}", parent);
+            }
+            let moved: SingleTodoResponse = test::call_and_read_body_json(
+                &app,
+                request(
+                    http::Method::PATCH,
+                    &todo_uri("Kitchen"),
+                    json!({ "parentId": ids["Book van"] }),
+                ),
+            )
+            .await;
+            assert_eq!(moved.data.todo.parentId.as_ref(), Some(&ids["Book van"]));
+
+            // Move > Pack > Books, Move > Book van > Kitchen
+            let delete = |title: &str, mode: &str| {
+                request(
+                    http::Method::DELETE,
+                    &format!("{}?children={}", todo_uri(title), mode),
+                    json!({}),
+                )
+            };
+            let resp = test::call_service(
+                &app,
+                request(http::Method::DELETE, &todo_uri("Move"), json!({})),
+            )
+            .await;
+            assert_eq!(resp.status(), http::StatusCode::CONFLICT);
+            let resp = test::call_service(&app, delete("Pack", "orphan")).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            let resp = test::call_service(&app, delete("Move", "cascade")).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            let resp = test::call_service(&app, delete("Taxes", "reject")).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+
+            let tree: TodoTreeResponse =
+                test::call_and_read_body_json(&app, get("/api/v1/todos/tree")).await;
+            assert_eq!(titles(&tree.tree), ["Books"]);
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+
+use actix_web::{web, HttpResponse};
+use chrono::prelude::*;
+
+use crate::{
+    auth::Principal,
+    model::{AppState, MergeTagSchema, RenameTagSchema, TagCount},
+    repository::{RepositoryError, TodoFilter, TodoQuery},
+    response::{error_response, SingleTagResponse, TagData, TagListResponse},
+};
+
+const MAX_TAGS: usize = 20;
+const MAX_TAG_LENGTH: usize = 50;
+
+// Trims and lowercases tags and drops repeats, keeping the first occurrence.
+// Commas are rejected since `tag=` filters are comma separated.
+pub fn normalize_tags(tags: Vec<String>) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = normalize_tag(&tag)?;
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    if normalized.len() > MAX_TAGS {
+        return Err(format!("A todo can have at most {} tags.", MAX_TAGS));
+    }
+    Ok(normalized)
+}
+
+fn normalize_tag(tag: &str) -> Result<String, String> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() || tag.chars().count() > MAX_TAG_LENGTH {
+        return Err(format!("Tags must be 1-{} characters.", MAX_TAG_LENGTH));
+    }
+    if tag.contains(',') || tag.chars().any(char::is_control) {
+        return Err(format!(
+            "Tag {:?} contains a comma or control character.",
+            tag
+        ));
+    }
+    Ok(tag)
+}
+
+fn tagged(tag: &str) -> TodoQuery {
+    TodoQuery {
+        filter: TodoFilter {
+            tags: vec![tag.to_string()],
+            ..TodoFilter::default()
+        },
+        ..TodoQuery::default()
+    }
+}
+
+// Replaces `from` with `to` on each of the owner's todos tagged `from` and
+// returns how many there were. Todos in the trash are retagged along with
+// them, so restoring one does not bring the old tag back, but only live todos
+// count.
+fn retag(
+    app_state: &AppState,
+    owner: &str,
+    from: &str,
+    to: &str,
+) -> Result<usize, RepositoryError> {
+    let live = app_state.todo_db.list(owner, &tagged(from))?.items;
+    if live.is_empty() {
+        return Ok(0);
+    }
+    let mut in_trash = tagged(from);
+    in_trash.filter.deleted = true;
+    let trashed = app_state.todo_db.list(owner, &in_trash)?.items;
+    let count = live.len();
+    let ids: Vec<String> = live
+        .into_iter()
+        .chain(trashed)
+        .filter_map(|todo| todo.id)
+        .collect();
+    let now = Utc::now();
+    app_state.todo_db.update_many(owner, &ids, &mut |todo| {
+        if !todo.tags.iter().any(|tag| tag == from) {
+            return;
+        }
+        let mut tags = Vec::with_capacity(todo.tags.len());
+        for tag in &todo.tags {
+            let tag = if tag == from { to } else { tag };
+            if !tags.iter().any(|kept| kept == tag) {
+                tags.push(tag.to_string());
+            }
+        }
+        todo.tags = tags;
+        todo.touch(now);
+    })?;
+    Ok(count)
+}
+
+// Moves every use of the tag in the path to `to` and responds with `to`
+fn move_tag(
+    app_state: &AppState,
+    owner: &str,
+    from: &str,
+    to: &str,
+) -> Result<HttpResponse, RepositoryError> {
+    let (from, to) = match (normalize_tag(from), normalize_tag(to)) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(message), _) | (_, Err(message)) => {
+            return Ok(error_response(HttpResponse::BadRequest(), &message))
+        }
+    };
+    if from != to && retag(app_state, owner, &from, &to)? == 0 {
+        return Ok(error_response(HttpResponse::NotFound(), "Tag not found."));
+    }
+    let count = app_state.todo_db.list(owner, &tagged(&to))?.total;
+    if count == 0 {
+        return Ok(error_response(HttpResponse::NotFound(), "Tag not found."));
+    }
+
+    let response_json = &SingleTagResponse {
+        status: "success".to_string(),
+        data: TagData {
+            tag: TagCount { name: to, count },
+        },
+    };
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+// List the caller's tags with how many todos use each, most used first
+pub async fn get_tags(
+    app_state: web::Data<AppState>,
+    principal: Principal,
+) -> Result<HttpResponse, RepositoryError> {
+    let todos = app_state
+        .todo_db
+        .list(&principal.subject, &TodoQuery::default())?
+        .items;
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for tag in todos.into_iter().flat_map(|todo| todo.tags) {
+        *counts.entry(tag).or_default() += 1;
+    }
+    let mut tags: Vec<TagCount> = counts
+        .into_iter()
+        .map(|(name, count)| TagCount { name, count })
+        .collect();
+    tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+
+    let response_json = &TagListResponse {
+        status: "success".to_string(),
+        results: tags.len(),
+        tags,
+    };
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+// Rename a tag. Renaming to a tag already in use merges the two.
+pub async fn rename_tag(
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    path: web::Path<String>,
+    payload: web::Json<RenameTagSchema>,
+) -> Result<HttpResponse, RepositoryError> {
+    move_tag(&app_state, &principal.subject, &path, &payload.name)
+}
+
+// Merge a tag into another one
+pub async fn merge_tag(
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    path: web::Path<String>,
+    payload: web::Json<MergeTagSchema>,
+) -> Result<HttpResponse, RepositoryError> {
+    move_tag(&app_state, &principal.subject, &path, &payload.into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::test_support::bearer;
+    use crate::response::{SingleTodoResponse, TodoListResponse};
+    use crate::test_support::{api, app_states};
+    use actix_web::{http, test};
+    use serde_json::json;
+
+    // Plain `#[test]` would name actix's attribute imported above
+    #[core::prelude::v1::test]
+    fn normalize_tags_test() {
+        let tags = ["Work", " work ", "Home"].map(String::from).to_vec();
+        assert_eq!(normalize_tags(tags).unwrap(), ["work", "home"]);
+        assert!(normalize_tags(vec![" ".to_string()]).is_err());
+        assert!(normalize_tags(vec!["a,b".to_string()]).is_err());
+        assert!(normalize_tags((0..=MAX_TAGS).map(|n| n.to_string()).collect()).is_err());
+    }
+
+    #[actix_web::test]
+    async fn tags_test() {
+        for app_data in app_states() {
+            let app = api(&app_data).await;
+            for (title, tags) in [
+                ("Report", json!(["Work", "urgent"])),
+                ("Groceries", json!(["home"])),
+                ("Taxes", json!(["home", "urgent"])),
+            ] {
+                let req = test::TestRequest::post()
+                    .uri("/api/v1/todos")
+                    .insert_header(bearer("alice"))
+                    .set_json(json!({ "title": title, "content": "", "tags": tags }))
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                assert_eq!(resp.status(), http::StatusCode::OK);
+            }
+            let req = test::TestRequest::post()
+                .uri("/api/v1/todos")
+                .insert_header(bearer("alice"))
+                .set_json(json!({ "title": "Bad", "content": "", "tags": [""] }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+
+            let get = |uri: &str| {
+                test::TestRequest::get()
+                    .uri(uri)
+                    .insert_header(bearer("alice"))
+                    .to_request()
+            };
+            let list: TagListResponse =
+                test::call_and_read_body_json(&app, get("/api/v1/tags")).await;
+            let counts: Vec<(&str, usize)> = list
+                .tags
+                .iter()
+                .map(|tag| (tag.name.as_str(), tag.count))
+                .collect();
+            assert_eq!(counts, [("home", 2), ("urgent", 2), ("work", 1)]);
+
+            let titles = |list: TodoListResponse| -> Vec<String> {
+                list.todos.into_iter().map(|todo| todo.title).collect()
+            };
+            let any: TodoListResponse =
+                test::call_and_read_body_json(&app, get("/api/v1/todos?tag=work,HOME")).await;
+            assert_eq!(titles(any), ["Report", "Groceries", "Taxes"]);
+            let all: TodoListResponse = test::call_and_read_body_json(
+                &app,
+                get("/api/v1/todos?tag=home,urgent&tag_mode=all"),
+            )
+            .await;
+            assert_eq!(titles(all), ["Taxes"]);
+
+            // Renaming onto a tag in use merges them
+            let req = test::TestRequest::patch()
+                .uri("/api/v1/tags/work")
+                .insert_header(bearer("alice"))
+                .set_json(json!({ "name": "Urgent" }))
+                .to_request();
+            let renamed: SingleTagResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(
+                renamed.data.tag,
+                TagCount {
+                    name: "urgent".to_string(),
+                    count: 2
+                }
+            );
+
+            let req = test::TestRequest::post()
+                .uri("/api/v1/tags/urgent/merge")
+                .insert_header(bearer("alice"))
+                .set_json(json!({ "into": "home" }))
+                .to_request();
+            let merged: SingleTagResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(merged.data.tag.count, 3);
+            let all: TodoListResponse =
+                test::call_and_read_body_json(&app, get("/api/v1/todos?tag=home")).await;
+            assert!(all.todos.iter().all(|todo| todo.tags == ["home"]));
+
+            let req = test::TestRequest::patch()
+                .uri("/api/v1/tags/urgent")
+                .insert_header(bearer("alice"))
+                .set_json(json!({ "name": "later" }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+
+            // Other users see none of it
+            let req = test::TestRequest::get()
+                .uri("/api/v1/tags")
+                .insert_header(bearer("bob"))
+                .to_request();
+            let list: TagListResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(list.results, 0);
+        }
+    }
+
+    #[actix_web::test]
+    async fn retag_reaches_the_trash_test() {
+        for app_data in app_states() {
+            let app = api(&app_data).await;
+            let send = |method: http::Method, uri: &str, body: serde_json::Value| {
+                test::TestRequest::default()
+                    .method(method)
+                    .uri(uri)
+                    .insert_header(bearer("alice"))
+                    .set_json(body)
+                    .to_request()
+            };
+            let mut ids = Vec::new();
+            for title in ["Report", "Slides"] {
+                let body = json!({ "title": title, "content": "", "tags": ["work", "draft"] });
+                let created: SingleTodoResponse = test::call_and_read_body_json(
+                    &app,
+                    send(http::Method::POST, "/api/v1/todos", body),
+                )
+                .await;
+                ids.push(created.data.todo.id.unwrap());
+            }
+            let slides = format!("/api/v1/todos/{}", ids[1]);
+            let resp =
+                test::call_service(&app, send(http::Method::DELETE, &slides, json!({}))).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+
+            // Only live todos count towards the tag
+            let body = json!({ "name": "job" });
+            let renamed: SingleTagResponse = test::call_and_read_body_json(
+                &app,
+                send(http::Method::PATCH, "/api/v1/tags/work", body),
+            )
+            .await;
+            assert_eq!(renamed.data.tag.count, 1);
+
+            let restore = format!("{}/restore", slides);
+            let restored: SingleTodoResponse =
+                test::call_and_read_body_json(&app, send(http::Method::POST, &restore, json!({})))
+                    .await;
+            assert_eq!(restored.data.todo.tags, ["job", "draft"]);
+
+            // A tag left only in the trash is not found, and stays as it is
+            let resp =
+                test::call_service(&app, send(http::Method::DELETE, &slides, json!({}))).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            let report = format!("/api/v1/todos/{}", ids[0]);
+            let body = json!({ "tags": ["job"] });
+            let resp = test::call_service(&app, send(http::Method::PATCH, &report, body)).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            let body = json!({ "name": "notes" });
+            let resp =
+                test::call_service(&app, send(http::Method::PATCH, "/api/v1/tags/draft", body))
+                    .await;
+            assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+            let trashed = app_data.todo_db.get("alice", &ids[1]).unwrap().unwrap();
+            assert_eq!(trashed.tags, ["job", "draft"]);
+        }
+    }
+}
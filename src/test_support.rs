@@ -0,0 +1,138 @@
+// Fixtures shared by the endpoint tests
+use std::sync::{Arc, Mutex};
+
+use actix_http::Request;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::{http, test, web, App, Error};
+use chrono::{DateTime, Utc};
+
+use crate::auth::test_support::{authenticator, bearer};
+use crate::handler::config;
+use crate::model::{AppState, Todo};
+use crate::repository::{
+    Page, RepositoryError, SqliteStore, TodoFilter, TodoQuery, TodoRepository,
+};
+
+// Every store the suites run against
+pub(crate) fn app_states() -> Vec<web::Data<AppState>> {
+    let sqlite = SqliteStore::open(":memory:").unwrap();
+    vec![
+        web::Data::new(AppState::init()),
+        web::Data::new(AppState::new(Arc::new(sqlite))),
+    ]
+}
+
+// The whole API over `app_data`, taking the tokens `bearer` makes
+pub(crate) async fn api(
+    app_data: &web::Data<AppState>,
+) -> impl Service<Request, Response = ServiceResponse, Error = Error> {
+    test::init_service(
+        App::new()
+            .app_data(app_data.clone())
+            .app_data(authenticator())
+            .configure(config),
+    )
+    .await
+}
+
+// A request from alice with a JSON body
+pub(crate) fn request(method: http::Method, uri: &str, body: serde_json::Value) -> Request {
+    test::TestRequest::default()
+        .method(method)
+        .uri(uri)
+        .insert_header(bearer("alice"))
+        .set_json(body)
+        .to_request()
+}
+
+// `app_data` with `interfere` run on its todos just before the next update
+// or update_many lands, standing in for a request that races the one under
+// test
+pub(crate) fn racing(
+    app_data: &web::Data<AppState>,
+    interfere: impl FnOnce(&dyn TodoRepository) + Send + 'static,
+) -> web::Data<AppState> {
+    let todo_db = Arc::new(Racing {
+        inner: app_data.todo_db.clone(),
+        interfere: Mutex::new(Some(Box::new(interfere))),
+    });
+    web::Data::new(AppState {
+        todo_db,
+        search: app_data.search.clone(),
+        user_db: app_data.user_db.clone(),
+        api_key_db: app_data.api_key_db.clone(),
+        reminder_db: app_data.reminder_db.clone(),
+        list_db: app_data.list_db.clone(),
+    })
+}
+
+type Interference = Box<dyn FnOnce(&dyn TodoRepository) + Send>;
+
+struct Racing {
+    inner: Arc<dyn TodoRepository>,
+    interfere: Mutex<Option<Interference>>,
+}
+
+impl Racing {
+    fn interfere(&self) {
+        let interfere = self.interfere.lock().unwrap().take();
+        if let Some(interfere) = interfere {
+            interfere(self.inner.as_ref());
+        }
+    }
+}
+
+impl TodoRepository for Racing {
+    fn create(&self, todo: Todo) -> Result<Todo, RepositoryError> {
+        self.inner.create(todo)
+    }
+
+    fn get(&self, owner: &str, id: &str) -> Result<Option<Todo>, RepositoryError> {
+        self.inner.get(owner, id)
+    }
+
+    fn list(&self, owner: &str, query: &TodoQuery) -> Result<Page, RepositoryError> {
+        self.inner.list(owner, query)
+    }
+
+    fn update(
+        &self,
+        owner: &str,
+        id: &str,
+        apply: &mut dyn FnMut(&mut Todo),
+    ) -> Result<Option<Todo>, RepositoryError> {
+        self.interfere();
+        self.inner.update(owner, id, apply)
+    }
+
+    fn update_many(
+        &self,
+        owner: &str,
+        ids: &[String],
+        apply: &mut dyn FnMut(&mut Todo),
+    ) -> Result<Vec<Todo>, RepositoryError> {
+        self.interfere();
+        self.inner.update_many(owner, ids, apply)
+    }
+
+    fn delete(&self, owner: &str, id: &str) -> Result<bool, RepositoryError> {
+        self.inner.delete(owner, id)
+    }
+
+    fn delete_if_deleted_before(
+        &self,
+        owner: &str,
+        id: &str,
+        cutoff: DateTime<Utc>,
+    ) -> Result<bool, RepositoryError> {
+        self.inner.delete_if_deleted_before(owner, id, cutoff)
+    }
+
+    fn list_all(&self, filter: &TodoFilter) -> Result<Vec<Todo>, RepositoryError> {
+        self.inner.list_all(filter)
+    }
+
+    fn max_updated_at(&self, owner: &str) -> Result<Option<DateTime<Utc>>, RepositoryError> {
+        self.inner.max_updated_at(owner)
+    }
+}
@@ -0,0 +1,432 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::prelude::*;
+use chrono::TimeDelta;
+
+use crate::{
+    auth::Principal,
+    config::TrashConfig,
+    handler::{single_todo, todo_list_response, NOT_FOUND},
+    model::{AppState, QueryOptions, Todo},
+    repository::{RepositoryError, TodoFilter, TodoQuery, TodoRepository},
+    response::{error_response, TodoData},
+    subtask,
+};
+
+// The owner's todo with this id, unless it is in the trash
+pub fn live_todo(
+    app_state: &AppState,
+    owner: &str,
+    id: &str,
+) -> Result<Option<Todo>, RepositoryError> {
+    Ok(app_state
+        .todo_db
+        .get(owner, id)?
+        .filter(|todo| todo.deletedAt.is_none()))
+}
+
+// What `trash` did with a todo
+pub enum Trashed {
+    Moved,
+    // The todo is no longer at the version the caller expected, so it stays
+    Changed(Box<Todo>),
+    NotFound,
+}
+
+// Moves a todo to the trash, provided it is still at `version` when one is
+// given, and in the same write applies `along` to the todos in `others`, as
+// deleting a todo does to its subtasks. They are left alone when the todo
+// stays. Todos already in the trash count as not found.
+pub fn trash(
+    app_state: &AppState,
+    owner: &str,
+    id: &str,
+    version: Option<u64>,
+    others: &[String],
+    along: &mut dyn FnMut(&mut Todo),
+    now: DateTime<Utc>,
+) -> Result<Trashed, RepositoryError> {
+    let mut ids = vec![id.to_string()];
+    ids.extend(others.iter().filter(|other| *other != id).cloned());
+    // `update_many` goes through the ids in order, so the todo comes first
+    let mut trashed = None;
+    let todos = app_state.todo_db.update_many(owner, &ids, &mut |todo| {
+        if todo.id.as_deref() == Some(id) {
+            let current = version.is_none_or(|version| version == todo.version);
+            let moved = todo.deletedAt.is_none() && current;
+            if moved {
+                todo.deletedAt = Some(now);
+                todo.touch(now);
+            }
+            trashed = Some(moved);
+        } else if trashed == Some(true) {
+            along(todo);
+        }
+    })?;
+    let todo = todos
+        .into_iter()
+        .find(|todo| todo.id.as_deref() == Some(id));
+    Ok(match todo {
+        Some(_) if trashed == Some(true) => Trashed::Moved,
+        Some(todo) if todo.deletedAt.is_none() => Trashed::Changed(Box::new(todo)),
+        _ => Trashed::NotFound,
+    })
+}
+
+// Moves the todos to the trash in one write, as deleting a list does with the
+// todos it takes along. Those already in the trash are left as they
+// are.
+pub fn trash_all(
+    app_state: &AppState,
+    owner: &str,
+    ids: &[String],
+    now: DateTime<Utc>,
+) -> Result<(), RepositoryError> {
+    app_state.todo_db.update_many(owner, ids, &mut |todo| {
+        if todo.deletedAt.is_none() {
+            todo.deletedAt = Some(now);
+            todo.touch(now);
+        }
+    })?;
+    Ok(())
+}
+
+// List the caller's deleted todos, filtered, sorted and paged like /todos
+pub async fn get_trash(
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    query: web::Query<QueryOptions>,
+) -> Result<HttpResponse, RepositoryError> {
+    todo_list_response(&req, &app_state, &principal.subject, &query, |filter| {
+        filter.deleted = true
+    })
+}
+
+// Takes a todo out of the trash along with the subtasks deleted with it. A
+// parent or list that is gone by now is cleared rather than left dangling.
+pub async fn restore_todo(
+    app_state: web::Data<AppState>,
+    principal: Principal,
+    path: web::Path<String>,
+) -> Result<HttpResponse, RepositoryError> {
+    let id = path.into_inner();
+    let owner = &principal.subject;
+    let Some(todo) = app_state.todo_db.get(owner, &id)? else {
+        return Ok(error_response(HttpResponse::NotFound(), NOT_FOUND));
+    };
+    let Some(deleted_at) = todo.deletedAt else {
+        return Ok(error_response(
+            HttpResponse::Conflict(),
+            "Todo is not in the trash.",
+        ));
+    };
+
+    // Deleting a todo with children=cascade trashes its subtasks at the same
+    // moment, which is how they are told apart from ones deleted on their own
+    let trash_query = TodoQuery {
+        filter: TodoFilter {
+            deleted: true,
+            ..TodoFilter::default()
+        },
+        ..TodoQuery::default()
+    };
+    let mut trashed: HashMap<String, Todo> = HashMap::new();
+    for todo in app_state.todo_db.list(owner, &trash_query)?.items {
+        if todo.deletedAt == Some(deleted_at) {
+            trashed.insert(todo.id.clone().unwrap_or_default(), todo);
+        }
+    }
+    let mut restoring = vec![id.clone()];
+    let mut seen = HashSet::from([id.clone()]);
+    let mut next = 0;
+    while next < restoring.len() {
+        let parent = restoring[next].clone();
+        for (child_id, child) in &trashed {
+            if child.parentId.as_ref() == Some(&parent) && seen.insert(child_id.clone()) {
+                restoring.push(child_id.clone());
+            }
+        }
+        next += 1;
+    }
+
+    // Whether each todo's parent and list are still there to go back to
+    let mut keeps = HashMap::new();
+    for todo_id in &restoring {
+        let Some(todo) = trashed.get(todo_id) else {
+            continue;
+        };
+        let keep_parent = match &todo.parentId {
+            Some(parent) => {
+                seen.contains(parent) || live_todo(&app_state, owner, parent)?.is_some()
+            }
+            None => true,
+        };
+        let keep_list = match &todo.listId {
+            Some(list_id) => app_state.list_db.get_list(owner, list_id)?.is_some(),
+            None => true,
+        };
+        keeps.insert(todo_id.clone(), (keep_parent, keep_list));
+    }
+
+    let now = Utc::now();
+    let todos = app_state
+        .todo_db
+        .update_many(owner, &restoring, &mut |todo| {
+            let keep = todo.id.as_ref().and_then(|todo_id| keeps.get(todo_id));
+            let Some(&(keep_parent, keep_list)) = keep else {
+                return;
+            };
+            if todo.deletedAt != Some(deleted_at) {
+                return;
+            }
+            todo.deletedAt = None;
+            if !keep_parent {
+                todo.parentId = None;
+            }
+            if !keep_list {
+                todo.listId = None;
+            }
+            todo.touch(now);
+        })?;
+    let restored = todos.into_iter().find(|todo| todo.id.as_ref() == Some(&id));
+
+    match restored {
+        Some(todo) if todo.deletedAt.is_none() => Ok(single_todo(
+            HttpResponse::Ok(),
+            TodoData {
+                todo,
+                next_occurrence: None,
+                subtasks: subtask::rollup(&app_state, owner, &id)?,
+            },
+        )),
+        Some(_) => Ok(error_response(
+            HttpResponse::Conflict(),
+            "Todo is not in the trash.",
+        )),
+        None => Ok(error_response(HttpResponse::NotFound(), NOT_FOUND)),
+    }
+}
+
+// Removes todos for good once they have been in the trash for the retention
+// period
+pub struct Purger {
+    todo_db: Arc<dyn TodoRepository>,
+    retention: TimeDelta,
+}
+
+impl Purger {
+    pub fn new(app_state: &AppState, config: &TrashConfig) -> Purger {
+        let retention = i64::try_from(config.retention_days)
+            .ok()
+            .and_then(TimeDelta::try_days)
+            .unwrap_or(TimeDelta::MAX);
+        Purger {
+            todo_db: app_state.todo_db.clone(),
+            retention,
+        }
+    }
+
+    // Purges the todos deleted a retention period or more before `now` and
+    // returns how many went
+    pub fn run_once(&self, now: DateTime<Utc>) -> Result<usize, RepositoryError> {
+        let Some(cutoff) = now.checked_sub_signed(self.retention) else {
+            return Ok(0);
+        };
+        let filter = TodoFilter {
+            deleted: true,
+            ..TodoFilter::default()
+        };
+
+        let mut purged = 0;
+        for todo in self.todo_db.list_all(&filter)? {
+            let (Some(id), Some(owner), Some(deleted_at)) =
+                (&todo.id, &todo.ownerId, todo.deletedAt)
+            else {
+                continue;
+            };
+            // The store checks again as it deletes, in case the todo was
+            // restored since the scan
+            if deleted_at <= cutoff && self.todo_db.delete_if_deleted_before(owner, id, cutoff)? {
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    // Scans every `interval` on the current actix runtime, on the blocking
+    // thread pool like reminder scans
+    pub fn start(self, interval: Duration) {
+        let purger = Arc::new(self);
+        actix_web::rt::spawn(async move {
+            let mut ticks = actix_web::rt::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                let purger = purger.clone();
+                match web::block(move || purger.run_once(Utc::now())).await {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(purged)) => log::debug!("Purged {} todos from the trash", purged),
+                    Ok(Err(err)) => log::error!("Trash purge failed: {}", err),
+                    Err(err) => log::error!("Trash purge panicked: {}", err),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::test_support::bearer;
+    use crate::response::{
+        GenericResponse, SingleListResponse, SingleTodoResponse, TodoListResponse,
+    };
+    use crate::test_support::{api, app_states, request};
+    use actix_web::{http, test};
+    use serde_json::json;
+
+    #[actix_web::test]
+    async fn trash_test() {
+        for app_data in app_states() {
+            let app = api(&app_data).await;
+            let titles = |uri: &str| {
+                let req = test::TestRequest::get()
+                    .uri(uri)
+                    .insert_header(bearer("alice"))
+                    .to_request();
+                let app = &app;
+                async move {
+                    let list: TodoListResponse = test::call_and_read_body_json(app, req).await;
+                    list.todos
+                        .into_iter()
+                        .map(|todo| todo.title)
+                        .collect::<Vec<_>>()
+                }
+            };
+
+            let req = request(
+                http::Method::POST,
+                "/api/v1/lists",
+                json!({ "name": "Work" }),
+            );
+            let list: SingleListResponse = test::call_and_read_body_json(&app, req).await;
+            let list_id = list.data.list.id;
+            let mut ids: Vec<String> = Vec::new();
+            for title in ["Report", "Outline", "Errand"] {
+                let mut body = json!({ "title": title, "content": "" });
+                match title {
+                    "Report" => body["listId"] = json!(list_id),
+                    // The outline is a subtask of the report
+                    "Outline" => body["parentId"] = json!(ids[0]),
+                    _ => {}
+                }
+                let req = request(http::Method::POST, "/api/v1/todos", body);
+                let created: SingleTodoResponse = test::call_and_read_body_json(&app, req).await;
+                ids.push(created.data.todo.id.unwrap());
+            }
+            let uri = |id: &str, rest: &str| format!("/api/v1/todos/{}{}", id, rest);
+
+            let req = request(
+                http::Method::DELETE,
+                &uri(&ids[0], "?children=cascade"),
+                json!({}),
+            );
+            let resp: GenericResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(resp.message, "Todo moved to the trash.");
+            let req = request(http::Method::DELETE, &uri(&ids[2], ""), json!({}));
+            assert_eq!(
+                test::call_service(&app, req).await.status(),
+                http::StatusCode::OK
+            );
+
+            assert!(titles("/api/v1/todos").await.is_empty());
+            let mut trashed = titles("/api/v1/trash").await;
+            trashed.sort();
+            assert_eq!(trashed, ["Errand", "Outline", "Report"]);
+            for method in [http::Method::GET, http::Method::PATCH, http::Method::DELETE] {
+                let resp =
+                    test::call_service(&app, request(method, &uri(&ids[0], ""), json!({}))).await;
+                assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+            }
+
+            // The list is gone by the time the todo comes back
+            let req = request(
+                http::Method::DELETE,
+                &format!("/api/v1/lists/{}", list_id),
+                json!({}),
+            );
+            assert_eq!(
+                test::call_service(&app, req).await.status(),
+                http::StatusCode::OK
+            );
+            let req = request(http::Method::POST, &uri(&ids[0], "/restore"), json!({}));
+            let restored: SingleTodoResponse = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(restored.data.todo.deletedAt, None);
+            assert_eq!(restored.data.todo.listId, None);
+            assert_eq!(
+                restored.data.subtasks.map(|subtasks| subtasks.total),
+                Some(1)
+            );
+            assert_eq!(titles("/api/v1/todos").await, ["Report", "Outline"]);
+            assert_eq!(titles("/api/v1/trash").await, ["Errand"]);
+
+            let req = request(http::Method::POST, &uri(&ids[0], "/restore"), json!({}));
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::CONFLICT);
+            let req = request(http::Method::POST, &uri("missing", "/restore"), json!({}));
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+
+            let purger = Purger::new(&app_data, &TrashConfig::default());
+            let now = Utc::now();
+            assert_eq!(purger.run_once(now).unwrap(), 0);
+            assert_eq!(purger.run_once(now + TimeDelta::days(31)).unwrap(), 1);
+            assert!(titles("/api/v1/trash").await.is_empty());
+            assert!(app_data.todo_db.get("alice", &ids[2]).unwrap().is_none());
+        }
+    }
+
+    #[actix_web::test]
+    async fn purge_spares_restored_todos_test() {
+        for app_data in app_states() {
+            let now = Utc::now();
+            // Trashed when the purger scanned, but restored before it got
+            // to the todo
+            let todo = Todo {
+                id: Some("report".to_string()),
+                title: "Report".to_string(),
+                createdAt: Some(now),
+                updatedAt: Some(now),
+                ownerId: Some("alice".to_string()),
+                deletedAt: None,
+                ..Todo::default()
+            };
+            app_data.todo_db.create(todo).unwrap();
+            let later = now + TimeDelta::days(31);
+            let deleted = app_data
+                .todo_db
+                .delete_if_deleted_before("alice", "report", later)
+                .unwrap();
+            assert!(!deleted);
+
+            assert!(matches!(
+                trash(&app_data, "alice", "report", None, &[], &mut |_| {}, now).unwrap(),
+                Trashed::Moved
+            ));
+            let deleted = app_data
+                .todo_db
+                .delete_if_deleted_before("alice", "report", now - TimeDelta::days(1))
+                .unwrap();
+            assert!(!deleted);
+            let deleted = app_data
+                .todo_db
+                .delete_if_deleted_before("alice", "report", later)
+                .unwrap();
+            assert!(deleted);
+            assert!(app_data.todo_db.get("alice", "report").unwrap().is_none());
+        }
+    }
+}